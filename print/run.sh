@@ -1 +1 @@
-cargo build --release && sudo target/release/print $@
+cargo build --release && sudo target/release/print "$@"
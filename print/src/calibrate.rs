@@ -0,0 +1,307 @@
+use anyhow::{ensure, Result};
+
+use crate::{dither::ALL_DITHERS, font, heat::Heat, profile::Profile};
+
+/// Scale of titles and dither names, in dots per font pixel
+const TEXT_SCALE: usize = 2;
+/// Width of the ruler down the left edge and the ticks down the right edge, in mm
+const RULER_MM: f32 = 4.;
+/// Height of each gray ramp, in mm
+const RAMP_MM: f32 = 10.;
+/// Height of each resolution wedge, in mm
+const WEDGE_MM: f32 = 16.;
+/// Blank paper between sections, in mm
+const SPACE_MM: f32 = 3.;
+/// Widest and narrowest line period in the resolution wedges, in dots
+const WEDGE_PERIODS: (usize, usize) = (8, 2);
+/// Narrowest ramp, so each 10% step below it falls on a dot of its own
+const MIN_RAMP_DOTS: usize = 11;
+
+/// Test page for tuning dithering, heat and alignment, as a bitmap `profile.dots_per_row` wide
+/// (true = black):
+/// - a ruler down the left edge in mm, and matching ticks down the right edge, on the first and
+///   last dots of the printable area, so clipping or a skewed head shows
+/// - a ruler across the top, to check the printable width
+/// - a white to black ramp dithered with each algorithm
+/// - wedges of vertical and horizontal lines getting finer, to see where the head stops resolving
+///
+/// Fails if the print head is too narrow for the rulers and ramps side by side.
+pub fn test_page(profile: &Profile, heat: &Heat) -> Result<Vec<bool>> {
+    let width = profile.dots_per_row;
+    let mm = |mm: f32| profile.mm_to_dots(mm);
+    // Rulers on both edges and a dot of space inside them
+    let margin = mm(RULER_MM) + mm(1.);
+    ensure!(
+        width >= 2 * margin + MIN_RAMP_DOTS,
+        "Printer profile \"{}\" is {} dots wide, too narrow for a test page of at least {} dots",
+        profile.name,
+        width,
+        2 * margin + MIN_RAMP_DOTS
+    );
+    let mut page = Page::new(width);
+
+    // Ruler across the top
+    page.space(mm(3.));
+    for x_mm in 0..=profile.print_width as usize {
+        let x = mm(x_mm as f32).min(width - 1);
+        let len = match x_mm {
+            _ if x_mm.is_multiple_of(10) => mm(3.),
+            _ if x_mm.is_multiple_of(5) => mm(2.),
+            _ => mm(1.),
+        };
+        page.fill(x, 0, 1, len);
+    }
+    page.fill(0, 0, width, 1);
+    page.space(mm(2.));
+
+    page.text(&format!("{} {:.0} dpi", profile.name, profile.dpi));
+    page.text(&format!(
+        "{} dots {:.1} mm",
+        profile.dots_per_row, profile.print_width
+    ));
+    for line in heat_lines(heat) {
+        page.text(&line);
+    }
+    page.space(mm(SPACE_MM));
+
+    // Gray ramps, inside the rulers
+    let left = margin;
+    let right = width - margin;
+    for dither in ALL_DITHERS {
+        page.text(dither.name());
+        page.space(mm(1.));
+
+        let ramp_width = right - left;
+        let rows = mm(RAMP_MM);
+        let luma: Vec<f32> = (0..rows)
+            .flat_map(|_| (0..ramp_width).map(|x| 1. - x as f32 / (ramp_width - 1) as f32))
+            .collect();
+        let dots = dither.apply(&luma, ramp_width);
+        let top = page.height();
+        page.space(rows);
+        for (y, row) in dots.chunks(ramp_width).enumerate() {
+            for (x, &black) in row.iter().enumerate() {
+                if black {
+                    page.fill(left + x, top + y, 1, 1);
+                }
+            }
+        }
+
+        // Steps of 10% below the ramp
+        let top = page.height();
+        page.space(mm(1.));
+        for step in 0..=10 {
+            page.fill(left + step * (ramp_width - 1) / 10, top, 1, mm(1.));
+        }
+        page.space(mm(SPACE_MM));
+    }
+
+    // Resolution wedges: vertical lines getting finer downwards, then horizontal lines getting
+    // finer to the right
+    page.text("lines");
+    page.space(mm(1.));
+    let (coarse, fine) = WEDGE_PERIODS;
+    let rows = mm(WEDGE_MM);
+    let top = page.height();
+    page.space(rows);
+    // Whole dots per period, so lines don't alias into moire
+    let period = |pos: usize, len: usize| coarse - (coarse - fine + 1) * pos / len;
+    for y in 0..rows {
+        let period = period(y, rows);
+        for x in left..right {
+            if (x - left) % period < period / 2 {
+                page.fill(x, top + y, 1, 1);
+            }
+        }
+    }
+    page.space(mm(SPACE_MM));
+
+    let top = page.height();
+    page.space(rows);
+    for x in left..right {
+        let period = period(x - left, right - left);
+        for y in 0..rows {
+            if y % period < period / 2 {
+                page.fill(x, top + y, 1, 1);
+            }
+        }
+    }
+    page.space(mm(SPACE_MM));
+
+    // Rulers down both edges, now the length is known
+    let height = page.height();
+    page.fill(0, height - 1, width, 1);
+    let length_mm = (height as f32 / profile.dots_per_mm()) as usize;
+    for y_mm in 0..=length_mm {
+        let y = mm(y_mm as f32).min(height - 1);
+        let len = match y_mm {
+            _ if y_mm.is_multiple_of(10) => mm(3.),
+            _ if y_mm.is_multiple_of(5) => mm(2.),
+            _ => mm(1.),
+        };
+        page.fill(0, y, len, 1);
+        page.fill(width - len, y, len, 1);
+
+        if y_mm > 0 && y_mm.is_multiple_of(10) {
+            page.draw_text(mm(2.) + 2, y + 2, &(y_mm / 10).to_string(), 1);
+        }
+    }
+
+    Ok(page.dots)
+}
+
+/// The heat settings in use, for the page
+fn heat_lines(heat: &Heat) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(dots) = heat.heating_dots {
+        lines.push(format!("heating dots {}", dots));
+    }
+    if let Some(time) = heat.heating_time {
+        lines.push(format!("heating time {} us", time));
+    }
+    if let Some(interval) = heat.heating_interval {
+        lines.push(format!("interval {} us", interval));
+    }
+    if let Some(density) = heat.density {
+        lines.push(format!("density {}%", density));
+    }
+    if let Some(time) = heat.break_time {
+        lines.push(format!("break time {} us", time));
+    }
+    if let Some(speed) = heat.speed {
+        lines.push(format!("speed {}", speed));
+    }
+    lines
+}
+
+/// Bitmap that grows downwards as it is drawn on
+struct Page {
+    width: usize,
+    dots: Vec<bool>,
+}
+
+impl Page {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            dots: vec![],
+        }
+    }
+
+    fn height(&self) -> usize {
+        self.dots.len() / self.width
+    }
+
+    /// Add blank rows
+    fn space(&mut self, rows: usize) {
+        self.dots.resize(self.dots.len() + rows * self.width, false);
+    }
+
+    /// Add a line of centered text
+    fn text(&mut self, text: &str) {
+        self.dots.extend(font::render(text, TEXT_SCALE, self.width));
+    }
+
+    /// Blacken a `w` by `h` block of dots, clipped to the page
+    fn fill(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let height = self.height();
+        for row in y..(y + h).min(height) {
+            for col in x..(x + w).min(self.width) {
+                self.dots[row * self.width + col] = true;
+            }
+        }
+    }
+
+    /// Draw text with its top left corner at (`x`, `y`)
+    fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize) {
+        for (i, c) in text.chars().enumerate() {
+            for fy in 0..font::CELL_HEIGHT {
+                for fx in 0..font::CELL_WIDTH {
+                    if font::pixel(c, fx, fy) {
+                        let left = x + (i * font::CELL_WIDTH + fx) * scale;
+                        self.fill(left, y + fy * scale, scale, scale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emulator::Emulator, profile::builtin_profiles, raster::RasterCommand};
+
+    /// The test page for the POS58 profile, as printed
+    fn printed() -> (Profile, Emulator) {
+        let profile = builtin_profiles().remove(0);
+        let page = test_page(&profile, &Heat::default()).unwrap();
+        let mut bytes = vec![];
+        RasterCommand::Raster
+            .encode(&mut bytes, &page, profile.dots_per_row)
+            .unwrap();
+        let emu = Emulator::decode(profile.dots_per_row, &bytes).unwrap();
+        assert!(emu.paper() == page);
+        (profile, emu)
+    }
+
+    #[test]
+    fn page_fills_the_head() {
+        let (profile, emu) = printed();
+        assert_eq!(emu.width(), profile.dots_per_row);
+        // Title, four dither ramps and two wedges
+        let mm = emu.height() as f32 / profile.dots_per_mm();
+        assert!((100. ..200.).contains(&mm), "{} mm", mm);
+
+        // The top and bottom rows are solid, to show the printable width
+        let paper = emu.paper();
+        let width = emu.width();
+        assert!(paper[..width].iter().all(|&dot| dot));
+        assert!(paper[paper.len() - width..].iter().all(|&dot| dot));
+    }
+
+    #[test]
+    fn ticks_every_mm_on_both_edges() {
+        let (profile, emu) = printed();
+        let (width, height) = (emu.width(), emu.height());
+        let paper = emu.paper();
+        let dot = |x: usize, y: usize| paper[y * width + x];
+
+        let ticks: Vec<usize> = (0..)
+            .map(|y_mm| profile.mm_to_dots(y_mm as f32))
+            .take_while(|&y| y < height)
+            .collect();
+        // Below the ruler across the top, the edges are black on the ticks only
+        for y in profile.mm_to_dots(3.)..height - 1 {
+            let tick = ticks.contains(&y);
+            assert_eq!(dot(0, y), tick, "left edge, row {}", y);
+            assert_eq!(dot(width - 1, y), tick, "right edge, row {}", y);
+        }
+
+        // Every 10 mm the ticks are longest
+        let long = profile.mm_to_dots(3.);
+        for &y in ticks.iter().step_by(10).skip(1) {
+            assert!(dot(long - 1, y) && !dot(long, y), "row {}", y);
+            assert!(
+                dot(width - long, y) && !dot(width - long - 1, y),
+                "row {}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn narrow_heads_are_an_error() {
+        let mut profile = builtin_profiles().remove(0);
+        for width in [0, 10, 80] {
+            profile.dots_per_row = width;
+            assert!(
+                test_page(&profile, &Heat::default()).is_err(),
+                "{} dots",
+                width
+            );
+        }
+        profile.dots_per_row = 100;
+        assert!(test_page(&profile, &Heat::default()).is_ok());
+    }
+}
@@ -0,0 +1,303 @@
+use std::{io::Write, ops::RangeInclusive, str::FromStr};
+
+use anyhow::bail;
+use qrcode::EcLevel;
+pub use strip_common::code::SCENE_KEYWORD;
+use strip_common::code::{
+    barcode_module_dots, code128, qr_module_dots, scale_modules, BARCODE_HEIGHT_DOTS, CODE_GAP,
+};
+
+use crate::{
+    error::{Error, Result},
+    label::SELECT_JUSTIFICATION,
+    raster::{feed, pack_row, Encoder, RasterCommand},
+};
+
+/// `GS ( k`: QR code functions
+pub const QR_FUNCTION: &[u8] = b"\x1d(k";
+/// `GS k`: print barcode
+pub const BARCODE: &[u8] = b"\x1dk";
+/// `GS w`: barcode module width
+pub const BARCODE_MODULE: &[u8] = b"\x1dw";
+/// `GS h`: barcode height
+pub const BARCODE_HEIGHT: &[u8] = b"\x1dh";
+/// `GS H`: position of the barcode's human readable text
+pub const BARCODE_TEXT: &[u8] = b"\x1dH";
+
+/// `GS k` system for Code 128, with the data length before the data
+const CODE128_SYSTEM: u8 = 73;
+/// Prefix of `GS k` Code 128 data selecting code set B
+const CODE128_SET_B: &[u8] = b"{B";
+/// `GS w` module widths most printers accept, in dots. Barcodes needing narrower modules are
+/// sent as images; wider ones are printed at the widest.
+pub const BARCODE_MODULE_RANGE: RangeInclusive<usize> = 2..=6;
+
+/// Which codes to print
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codes {
+    Qr,
+    Barcode,
+    Both,
+}
+
+impl Codes {
+    pub fn qr(&self) -> bool {
+        matches!(self, Self::Qr | Self::Both)
+    }
+
+    pub fn barcode(&self) -> bool {
+        matches!(self, Self::Barcode | Self::Both)
+    }
+}
+
+impl FromStr for Codes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "qr" => Self::Qr,
+            "barcode" => Self::Barcode,
+            "both" => Self::Both,
+            _ => bail!("Unknown code \"{}\"; expected qr, barcode or both", s),
+        })
+    }
+}
+
+/// What a strip's codes say: its index, and the scene it belongs to if known
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StripId {
+    pub index: usize,
+    /// Hash of the strip_gui scene, from the strip's `SCENE_KEYWORD` text chunk
+    pub scene: Option<u32>,
+}
+
+impl StripId {
+    /// Text encoded in the codes: `SCENE/INDEX` with the scene hash in hex, or just the index
+    pub fn payload(&self) -> String {
+        strip_common::code::payload(self.scene, self.index)
+    }
+}
+
+/// Parse a scene hash as written by strip_gui
+pub fn parse_scene(text: &str) -> anyhow::Result<u32> {
+    match u32::from_str_radix(text.trim(), 16) {
+        Ok(scene) => Ok(scene),
+        Err(_) => bail!("Scene hash \"{}\" should be 8 hex digits", text),
+    }
+}
+
+/// Machine-readable codes printed above or below a strip, so it can be identified with a phone
+/// or webcam after assembly. Printers draw them from `GS ( k` and `GS k`; those that can't are
+/// sent the same codes as images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StripCodes {
+    pub codes: Codes,
+    /// Send QR codes with `GS ( k`
+    pub native_qr: bool,
+    /// Send barcodes with `GS k`
+    pub native_barcode: bool,
+}
+
+impl StripCodes {
+    /// Print the codes above a strip
+    pub fn header<W: Write>(
+        &self,
+        mut printer: W,
+        id: &StripId,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        self.print(&mut printer, id, command, width)?;
+        feed(&mut printer, CODE_GAP)
+    }
+
+    /// Print the codes below a strip
+    pub fn footer<W: Write>(
+        &self,
+        mut printer: W,
+        id: &StripId,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        feed(&mut printer, CODE_GAP)?;
+        self.print(&mut printer, id, command, width)
+    }
+
+    fn print<W: Write>(
+        &self,
+        mut printer: W,
+        id: &StripId,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        let payload = id.payload();
+
+        if self.codes.qr() {
+            let (side, modules) = qr_modules(&payload, EcLevel::M)?;
+            let dots = qr_module_dots(side, width);
+            if self.native_qr {
+                print_qr(&mut printer, &payload, dots)?;
+            } else {
+                let (w, bitmap) = scale_modules(&modules, side, dots, dots);
+                print_centered(&mut printer, &bitmap, w, command, width)?;
+            }
+        }
+
+        if self.codes == Codes::Both {
+            feed(&mut printer, CODE_GAP)?;
+        }
+
+        if self.codes.barcode() {
+            let Some(modules) = code128(&payload) else {
+                return Err(Error::UnsupportedFormat(format!(
+                    "\"{}\" can't be encoded in Code 128",
+                    payload
+                )));
+            };
+            let dots = barcode_module_dots(modules.len(), width).ok_or_else(|| {
+                Error::UnsupportedFormat(format!(
+                    "Barcode for \"{}\" is wider than {} dots",
+                    payload, width
+                ))
+            })?;
+            if self.native_barcode && dots >= *BARCODE_MODULE_RANGE.start() {
+                print_barcode(&mut printer, &payload, dots)?;
+            } else {
+                let (w, bitmap) = scale_modules(&modules, modules.len(), dots, BARCODE_HEIGHT_DOTS);
+                print_centered(&mut printer, &bitmap, w, command, width)?;
+            }
+        }
+
+        printer.flush()?;
+        Ok(())
+    }
+}
+
+/// Send a QR code with `GS ( k`: model 2, error correction M, `dots` per module, centered
+fn print_qr<W: Write>(mut printer: W, payload: &str, dots: usize) -> Result<()> {
+    let store_len = (payload.len() + 3) as u16;
+
+    printer.write_all(SELECT_JUSTIFICATION)?;
+    printer.write_all(&[1])?;
+    // Model 2
+    printer.write_all(QR_FUNCTION)?;
+    printer.write_all(&[4, 0, 49, 65, 50, 0])?;
+    // Module size
+    printer.write_all(QR_FUNCTION)?;
+    printer.write_all(&[3, 0, 49, 67, dots as u8])?;
+    // Error correction M
+    printer.write_all(QR_FUNCTION)?;
+    printer.write_all(&[3, 0, 49, 69, 49])?;
+    // Store the data, then print it
+    printer.write_all(QR_FUNCTION)?;
+    printer.write_all(&store_len.to_le_bytes())?;
+    printer.write_all(&[49, 80, 48])?;
+    printer.write_all(payload.as_bytes())?;
+    printer.write_all(QR_FUNCTION)?;
+    printer.write_all(&[3, 0, 49, 81, 48])?;
+    printer.write_all(SELECT_JUSTIFICATION)?;
+    printer.write_all(&[0])?;
+    Ok(())
+}
+
+/// Send a Code 128 barcode with `GS k`, `dots` per module clamped to `BARCODE_MODULE_RANGE`,
+/// centered and without text
+fn print_barcode<W: Write>(mut printer: W, payload: &str, dots: usize) -> Result<()> {
+    let dots = dots.clamp(*BARCODE_MODULE_RANGE.start(), *BARCODE_MODULE_RANGE.end());
+    printer.write_all(SELECT_JUSTIFICATION)?;
+    printer.write_all(&[1])?;
+    printer.write_all(BARCODE_MODULE)?;
+    printer.write_all(&[dots as u8])?;
+    printer.write_all(BARCODE_HEIGHT)?;
+    printer.write_all(&[BARCODE_HEIGHT_DOTS as u8])?;
+    printer.write_all(BARCODE_TEXT)?;
+    printer.write_all(&[0])?;
+    printer.write_all(BARCODE)?;
+    printer.write_all(&[CODE128_SYSTEM, (CODE128_SET_B.len() + payload.len()) as u8])?;
+    printer.write_all(CODE128_SET_B)?;
+    printer.write_all(payload.as_bytes())?;
+    printer.write_all(SELECT_JUSTIFICATION)?;
+    printer.write_all(&[0])?;
+    Ok(())
+}
+
+/// Send a bitmap `w` dots wide centered on a row `width` dots wide
+fn print_centered<W: Write>(
+    printer: W,
+    bitmap: &[bool],
+    w: usize,
+    command: RasterCommand,
+    width: usize,
+) -> Result<()> {
+    let left = width.saturating_sub(w) / 2;
+    let mut encoder = Encoder::new(printer, command, width)?;
+    for row in bitmap.chunks(w) {
+        let mut line = vec![false; width];
+        for (x, &black) in row.iter().enumerate().take(width - left) {
+            line[left + x] = black;
+        }
+        encoder.push_row(&pack_row(&line))?;
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Modules of the smallest QR code holding `data`: its side, and its modules row-major (true =
+/// dark) without the quiet zone
+pub fn qr_modules(data: &str, ec: EcLevel) -> Result<(usize, Vec<bool>)> {
+    strip_common::code::qr_modules(data, ec)
+        .map_err(|e| Error::UnsupportedFormat(format!("QR code for \"{}\": {}", data, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::RASTER_IMAGE;
+
+    /// Bytes sent for the barcode of strip `index` on paper `width` dots wide
+    fn barcode(index: usize, width: usize) -> Vec<u8> {
+        let codes = StripCodes {
+            codes: Codes::Barcode,
+            native_qr: true,
+            native_barcode: true,
+        };
+        let id = StripId { index, scene: None };
+        let mut out = vec![];
+        codes
+            .print(&mut out, &id, RasterCommand::Raster, width)
+            .unwrap();
+        out
+    }
+
+    /// The `GS w` module width in `bytes`, if any
+    fn module_width(bytes: &[u8]) -> Option<u8> {
+        let at = bytes.windows(2).position(|w| w == BARCODE_MODULE)?;
+        Some(bytes[at + 2])
+    }
+
+    #[test]
+    fn barcode_modules_are_clamped() {
+        // 46 modules and 20 of quiet zone: 5 dots each fit in 384, 8 in 576
+        assert_eq!(module_width(&barcode(5, 384)), Some(5));
+        assert_eq!(module_width(&barcode(5, 576)), Some(6));
+    }
+
+    #[test]
+    fn narrow_barcodes_are_images() {
+        let bytes = barcode(5, 100);
+        assert_eq!(module_width(&bytes), None);
+        assert!(!bytes.windows(2).any(|w| w == BARCODE));
+        assert!(bytes.windows(RASTER_IMAGE.len()).any(|w| w == RASTER_IMAGE));
+    }
+
+    #[test]
+    fn payloads() {
+        let id = StripId {
+            index: 3,
+            scene: Some(0x1234abcd),
+        };
+        assert_eq!(id.payload(), "1234abcd/3");
+        assert_eq!(parse_scene(" 1234abcd\n").unwrap(), 0x1234abcd);
+        assert!(parse_scene("strip").is_err());
+    }
+}
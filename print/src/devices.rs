@@ -0,0 +1,115 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::usb::{UsbDevice, UsbSelector};
+
+/// USB printers chosen with `--device` are remembered in this file in the working directory
+pub const DEFAULT_DEVICES_PATH: &str = "print-devices.ron";
+
+/// The USB printer chosen for each printer profile, so `--device` only has to be given once
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Devices(BTreeMap<String, UsbSelector>);
+
+impl Devices {
+    /// Read remembered devices. A missing file remembers nothing.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match File::open(path) {
+            Ok(f) => ron::de::from_reader(f).with_context(|| format!("Parsing {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Opening {}", path.display())),
+        }
+    }
+
+    /// Write the remembered devices, replacing the file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(path, text + "\n").with_context(|| format!("Writing {}", path.display()))
+    }
+
+    /// The printer chosen for `profile`
+    pub fn get(&self, profile: &str) -> Option<&UsbSelector> {
+        self.0.get(profile)
+    }
+
+    /// Choose `device` for `profile`, or forget the choice with `None`. Returns whether anything
+    /// changed.
+    pub fn set(&mut self, profile: &str, device: Option<UsbSelector>) -> bool {
+        let old = match device.clone() {
+            Some(device) => self.0.insert(profile.into(), device),
+            None => self.0.remove(profile),
+        };
+        old != device
+    }
+
+    /// Profiles `printer` is chosen for
+    pub fn chosen(&self, printer: &UsbDevice) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(_, device)| printer.is(device))
+            .map(|(profile, _)| profile.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(serial: Option<&str>) -> UsbDevice {
+        UsbDevice {
+            bus: 1,
+            address: 4,
+            vendor_id: 0x0416,
+            product_id: 0x5011,
+            serial: serial.map(Into::into),
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    #[test]
+    fn remembers_a_printer_per_profile() {
+        let mut devices = Devices::default();
+        assert!(devices.set("pos58", Some("A1B2".parse().unwrap())));
+        assert!(!devices.set("pos58", Some("A1B2".parse().unwrap())));
+        assert!(devices.set("pos80", Some("1:4".parse().unwrap())));
+        assert!(devices.set("label", Some("0416:5011".parse().unwrap())));
+
+        assert_eq!(
+            devices.chosen(&printer(Some("A1B2"))),
+            ["label", "pos58", "pos80"]
+        );
+        assert_eq!(devices.chosen(&printer(None)), ["label", "pos80"]);
+
+        assert!(devices.set("pos80", None));
+        assert!(!devices.set("pos80", None));
+        assert_eq!(devices.get("pos80"), None);
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let path = std::env::temp_dir().join(format!("print-devices-{}.ron", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(Devices::load(&path).unwrap(), Devices::default());
+
+        let mut devices = Devices::default();
+        devices.set("pos58", Some("A1B2".parse().unwrap()));
+        devices.set("pos80", Some("1:4".parse().unwrap()));
+        devices.set("label", Some("0416:5011".parse().unwrap()));
+        devices.save(&path).unwrap();
+        let loaded = Devices::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), devices);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+/// Shares a job's strips out between several printers, each taking the next as soon as it is
+/// free. A printer that gives up hands its strip back for the others, so nothing is lost and
+/// nothing is printed twice.
+pub struct Dispatcher<T> {
+    state: Mutex<State<T>>,
+    /// Signalled whenever work is handed back or finished, or a printer drops out
+    changed: Condvar,
+}
+
+struct State<T> {
+    /// Work no printer has taken, in order
+    waiting: VecDeque<T>,
+    /// Work taken and not yet finished or handed back
+    taken: usize,
+    /// Printers still taking work
+    printers: usize,
+    /// Whether the job was stopped
+    aborted: bool,
+}
+
+impl<T> Dispatcher<T> {
+    /// Share `work` between `printers` printers
+    pub fn new(work: impl IntoIterator<Item = T>, printers: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                waiting: work.into_iter().collect(),
+                taken: 0,
+                printers,
+                aborted: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// The next piece of work, or `None` once there is none left. While other printers are busy
+    /// this waits, since they may yet hand theirs back.
+    pub fn next(&self) -> Option<T> {
+        let mut state = self.lock();
+        loop {
+            if state.aborted {
+                return None;
+            }
+            if let Some(work) = state.waiting.pop_front() {
+                state.taken += 1;
+                return Some(work);
+            }
+            if state.taken == 0 {
+                return None;
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Finish work taken with `next`, whether it printed or was skipped
+    pub fn done(&self) {
+        self.lock().taken -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Drop out, giving `work` back to the printers that are left. Returns how many are left.
+    pub fn give_up(&self, work: T) -> usize {
+        let mut state = self.lock();
+        state.taken -= 1;
+        state.waiting.push_front(work);
+        self.drop_out(state)
+    }
+
+    /// Drop out without having taken anything, e.g. when the printer can't be opened. Returns
+    /// how many printers are left.
+    pub fn leave(&self) -> usize {
+        self.drop_out(self.lock())
+    }
+
+    /// Stop handing out work; printers finish what they have
+    pub fn abort(&self) {
+        self.lock().aborted = true;
+        self.changed.notify_all();
+    }
+
+    /// Work nobody printed, because every printer dropped out or the job was stopped
+    pub fn into_remaining(self) -> Vec<T> {
+        let state = self
+            .state
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.waiting.into()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn drop_out(&self, mut state: MutexGuard<'_, State<T>>) -> usize {
+        state.printers -= 1;
+        self.changed.notify_all();
+        state.printers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn hands_out_work_in_order_until_it_is_done() {
+        let dispatcher = Dispatcher::new(0..3, 2);
+        assert_eq!(dispatcher.next(), Some(0));
+        assert_eq!(dispatcher.next(), Some(1));
+        dispatcher.done();
+        assert_eq!(dispatcher.next(), Some(2));
+        dispatcher.done();
+        dispatcher.done();
+        assert_eq!(dispatcher.next(), None);
+        assert!(dispatcher.into_remaining().is_empty());
+    }
+
+    #[test]
+    fn spreads_work_across_printers() {
+        let dispatcher = Dispatcher::new(0..9, 3);
+        let taken = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for printer in 0..3 {
+                let (dispatcher, taken) = (&dispatcher, &taken);
+                scope.spawn(move || {
+                    while let Some(work) = dispatcher.next() {
+                        taken.lock().unwrap().push((printer, work));
+                        thread::sleep(Duration::from_millis(20));
+                        dispatcher.done();
+                    }
+                });
+            }
+        });
+
+        let mut taken = taken.into_inner().unwrap();
+        for printer in 0..3 {
+            assert!(
+                taken.iter().any(|&(p, _)| p == printer),
+                "printer {}",
+                printer
+            );
+        }
+        taken.sort_by_key(|&(_, work)| work);
+        let work: Vec<_> = taken.iter().map(|&(_, work)| work).collect();
+        assert_eq!(work, (0..9).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn work_given_up_goes_to_the_others() {
+        let dispatcher = Dispatcher::new(0..3, 2);
+        let first = dispatcher.next().unwrap();
+        assert_eq!(dispatcher.next(), Some(1));
+
+        // Taken back before anything after it
+        assert_eq!(dispatcher.give_up(first), 1);
+        assert_eq!(dispatcher.next(), Some(0));
+        dispatcher.done();
+        dispatcher.done();
+        assert_eq!(dispatcher.next(), Some(2));
+        dispatcher.done();
+        assert_eq!(dispatcher.next(), None);
+    }
+
+    #[test]
+    fn waits_for_work_a_busy_printer_may_give_up() {
+        let dispatcher = Dispatcher::new([0], 2);
+        let work = dispatcher.next().unwrap();
+        thread::scope(|scope| {
+            let other = scope.spawn(|| dispatcher.next());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!other.is_finished());
+            dispatcher.give_up(work);
+            assert_eq!(other.join().unwrap(), Some(0));
+        });
+    }
+
+    #[test]
+    fn work_is_left_when_every_printer_gives_up() {
+        let dispatcher = Dispatcher::new(0..3, 1);
+        let work = dispatcher.next().unwrap();
+        assert_eq!(dispatcher.give_up(work), 0);
+        assert_eq!(dispatcher.into_remaining(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn abort_stops_handing_out_work() {
+        let dispatcher = Dispatcher::new(0..3, 2);
+        assert_eq!(dispatcher.next(), Some(0));
+        dispatcher.abort();
+        assert_eq!(dispatcher.next(), None);
+        dispatcher.done();
+        assert_eq!(dispatcher.into_remaining(), [1, 2]);
+    }
+}
@@ -0,0 +1,397 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use anyhow::bail;
+
+/// Algorithm used to reduce a grayscale image to black and white dots
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Plain 50% threshold
+    Threshold,
+    /// Error diffusion with the Floyd-Steinberg kernel, scanning every row left to right
+    #[default]
+    FloydSteinberg,
+    /// Error diffusion which drops 1/4 of the error; higher contrast, less bleeding
+    Atkinson,
+    /// Jarvis, Judice & Ninke 12-neighbour error diffusion
+    JarvisJudiceNinke,
+    /// Stucki 12-neighbour error diffusion
+    Stucki,
+    /// Ordered dither with an 8x8 Bayer matrix
+    Bayer,
+    /// Ordered dither with a 64x64 void-and-cluster blue noise mask
+    BlueNoise,
+}
+
+/// Every algorithm, in the order they are listed in help text
+pub const ALL_DITHERS: [Dither; 7] = [
+    Dither::Threshold,
+    Dither::FloydSteinberg,
+    Dither::Atkinson,
+    Dither::JarvisJudiceNinke,
+    Dither::Stucki,
+    Dither::Bayer,
+    Dither::BlueNoise,
+];
+
+impl Dither {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Threshold => "threshold",
+            Self::FloydSteinberg => "floyd-steinberg",
+            Self::Atkinson => "atkinson",
+            Self::JarvisJudiceNinke => "jjn",
+            Self::Stucki => "stucki",
+            Self::Bayer => "bayer",
+            Self::BlueNoise => "blue-noise",
+        }
+    }
+
+    /// Convert luminance (0 = black, 1 = white), `width` pixels per row, into dots (true = black)
+    pub fn apply(&self, luma: &[f32], width: usize) -> Vec<bool> {
+        let mut ditherer = Ditherer::new(*self, width);
+        luma.chunks(width)
+            .flat_map(|row| ditherer.row(row))
+            .collect()
+    }
+
+    /// Error diffusion kernel and its divisor, if this is an error diffusion method
+    fn kernel(&self) -> Option<(&'static Kernel, f32)> {
+        match self {
+            Self::FloydSteinberg => Some((&FLOYD_STEINBERG, 16.)),
+            Self::Atkinson => Some((&ATKINSON, 8.)),
+            Self::JarvisJudiceNinke => Some((&JARVIS_JUDICE_NINKE, 48.)),
+            Self::Stucki => Some((&STUCKI, 42.)),
+            _ => None,
+        }
+    }
+}
+
+/// Dithers an image one row at a time, carrying diffused error down to the following rows
+pub struct Ditherer {
+    dither: Dither,
+    width: usize,
+    /// Index of the next row
+    y: usize,
+    /// Error diffused into the next rows, nearest first
+    carry: Vec<Vec<f32>>,
+}
+
+impl Ditherer {
+    pub fn new(dither: Dither, width: usize) -> Self {
+        let depth = dither
+            .kernel()
+            .and_then(|(kernel, _)| kernel.iter().map(|&(_, dy, _)| dy).max())
+            .unwrap_or(0);
+
+        Self {
+            dither,
+            width,
+            y: 0,
+            carry: vec![vec![0.; width]; depth + 1],
+        }
+    }
+
+    /// Dither the next row of luminance (0 = black, 1 = white) into dots (true = black)
+    pub fn row(&mut self, luma: &[f32]) -> Vec<bool> {
+        let y = self.y;
+        self.y += 1;
+
+        match self.dither {
+            Dither::Threshold => luma.iter().map(|&l| l < 0.5).collect(),
+            Dither::Bayer => ordered(luma, y, &bayer_matrix(), BAYER_SIZE),
+            Dither::BlueNoise => ordered(luma, y, blue_noise_matrix(), BLUE_NOISE_SIZE),
+            _ => {
+                let (kernel, divisor) = self.dither.kernel().expect("Error diffusion kernel");
+                self.diffuse(luma, kernel, divisor)
+            }
+        }
+    }
+
+    fn diffuse(&mut self, luma: &[f32], kernel: &Kernel, divisor: f32) -> Vec<bool> {
+        let width = self.width;
+        let mut buf = self.carry.remove(0);
+        self.carry.push(vec![0.; width]);
+        for (b, &l) in buf.iter_mut().zip(luma) {
+            *b += l;
+        }
+
+        let mut out = vec![false; width];
+        for x in 0..width {
+            let old = buf[x];
+            let black = old < 0.5;
+            out[x] = black;
+
+            let err = old - if black { 0. } else { 1. };
+            for &(dx, dy, weight) in kernel {
+                let nx = x as isize + dx;
+                if nx < 0 || nx as usize >= width {
+                    continue;
+                }
+                let nx = nx as usize;
+                let e = err * weight / divisor;
+                if dy == 0 {
+                    buf[nx] += e;
+                } else {
+                    self.carry[dy - 1][nx] += e;
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "threshold" | "none" => Self::Threshold,
+            "floyd-steinberg" | "fs" => Self::FloydSteinberg,
+            "atkinson" => Self::Atkinson,
+            "jjn" | "jarvis-judice-ninke" => Self::JarvisJudiceNinke,
+            "stucki" => Self::Stucki,
+            "bayer" => Self::Bayer,
+            "blue-noise" => Self::BlueNoise,
+            _ => {
+                let names: Vec<&str> = ALL_DITHERS.iter().map(Dither::name).collect();
+                bail!(
+                    "Unknown dither \"{}\"; expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            }
+        })
+    }
+}
+
+/// Error diffusion kernel entries: (dx, dy, weight)
+type Kernel = [(isize, usize, f32)];
+
+const FLOYD_STEINBERG: [(isize, usize, f32); 4] = [(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)];
+
+const ATKINSON: [(isize, usize, f32); 6] = [
+    (1, 0, 1.),
+    (2, 0, 1.),
+    (-1, 1, 1.),
+    (0, 1, 1.),
+    (1, 1, 1.),
+    (0, 2, 1.),
+];
+
+const JARVIS_JUDICE_NINKE: [(isize, usize, f32); 12] = [
+    (1, 0, 7.),
+    (2, 0, 5.),
+    (-2, 1, 3.),
+    (-1, 1, 5.),
+    (0, 1, 7.),
+    (1, 1, 5.),
+    (2, 1, 3.),
+    (-2, 2, 1.),
+    (-1, 2, 3.),
+    (0, 2, 5.),
+    (1, 2, 3.),
+    (2, 2, 1.),
+];
+
+const STUCKI: [(isize, usize, f32); 12] = [
+    (1, 0, 8.),
+    (2, 0, 4.),
+    (-2, 1, 2.),
+    (-1, 1, 4.),
+    (0, 1, 8.),
+    (1, 1, 4.),
+    (2, 1, 2.),
+    (-2, 2, 1.),
+    (-1, 2, 2.),
+    (0, 2, 4.),
+    (1, 2, 2.),
+    (2, 2, 1.),
+];
+
+/// Threshold row `y` against a tiled `size` x `size` matrix of values in (0, 1)
+fn ordered(luma: &[f32], y: usize, matrix: &[f32], size: usize) -> Vec<bool> {
+    let matrix_row = &matrix[(y % size) * size..][..size];
+    luma.iter()
+        .enumerate()
+        .map(|(x, &l)| l < matrix_row[x % size])
+        .collect()
+}
+
+const BAYER_SIZE: usize = 8;
+
+fn bayer_matrix() -> [f32; BAYER_SIZE * BAYER_SIZE] {
+    let mut matrix = [0.; BAYER_SIZE * BAYER_SIZE];
+    for y in 0..BAYER_SIZE {
+        for x in 0..BAYER_SIZE {
+            // Bit-reversed interleave of x ^ y and y
+            let (a, b) = (x ^ y, y);
+            let mut rank = 0;
+            for bit in 0..3 {
+                rank = (rank << 2) | ((a >> bit) & 1) << 1 | ((b >> bit) & 1);
+            }
+            matrix[y * BAYER_SIZE + x] = (rank as f32 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f32;
+        }
+    }
+    matrix
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Blue noise threshold mask, generated once with Ulichney's void-and-cluster method
+fn blue_noise_matrix() -> &'static [f32] {
+    static MATRIX: OnceLock<Vec<f32>> = OnceLock::new();
+    MATRIX.get_or_init(|| {
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE, 1.5);
+        let n = ranks.len() as f32;
+        ranks.iter().map(|&r| (r as f32 + 0.5) / n).collect()
+    })
+}
+
+/// Rank every cell of a toroidal `size` x `size` grid so that thresholding the ranks at any
+/// level gives an evenly spread (blue noise) pattern
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<usize> {
+    let n = size * size;
+
+    // Gaussian energy contributed by a point at each toroidal offset
+    let kernel: Vec<f32> = (0..n)
+        .map(|idx| {
+            let d = |v: usize| v.min(size - v) as f32;
+            let (dx, dy) = (d(idx % size), d(idx / size));
+            (-(dx * dx + dy * dy) / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.; n];
+    let toggle = |pattern: &mut [bool], energy: &mut [f32], idx: usize| {
+        pattern[idx] = !pattern[idx];
+        let sign = if pattern[idx] { 1. } else { -1. };
+        let (px, py) = (idx % size, idx / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+
+    // Tightest cluster among set cells, or largest void among unset cells
+    let tightest = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // Deterministic sparse random seed pattern
+    let mut state: u32 = 0x1234_5678;
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        let idx = (state >> 8) as usize % n;
+        if !pattern[idx] {
+            toggle(&mut pattern, &mut energy, idx);
+            placed += 1;
+        }
+    }
+
+    // Spread the seed points out by moving clusters into voids until stable
+    loop {
+        let cluster = tightest(&pattern, &energy).unwrap();
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy).unwrap();
+        if void == cluster {
+            toggle(&mut pattern, &mut energy, cluster);
+            break;
+        }
+        toggle(&mut pattern, &mut energy, void);
+    }
+
+    let mut ranks = vec![0; n];
+
+    // Phase 1: rank the seed points by removing the tightest clusters
+    let (mut seed, mut seed_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest(&seed, &seed_energy).unwrap();
+        toggle(&mut seed, &mut seed_energy, cluster);
+        ranks[cluster] = rank;
+    }
+
+    // Phase 2: rank the remaining cells by filling the largest voids
+    for rank in initial..n {
+        let void = largest_void(&pattern, &energy).unwrap();
+        toggle(&mut pattern, &mut energy, void);
+        ranks[void] = rank;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 128;
+
+    fn black_fraction(dots: &[bool]) -> f32 {
+        dots.iter().filter(|&&black| black).count() as f32 / dots.len() as f32
+    }
+
+    #[test]
+    fn mid_gray_is_half_black() {
+        let luma = vec![0.5; SIZE * SIZE];
+        for dither in ALL_DITHERS {
+            // A plain threshold has no way to show 50% gray
+            if dither == Dither::Threshold {
+                continue;
+            }
+            let fraction = black_fraction(&dither.apply(&luma, SIZE));
+            assert!(
+                (0.45..=0.55).contains(&fraction),
+                "{}: {} black",
+                dither.name(),
+                fraction
+            );
+        }
+    }
+
+    #[test]
+    fn black_and_white_are_unchanged() {
+        for dither in ALL_DITHERS {
+            let black = dither.apply(&vec![0.; SIZE * SIZE], SIZE);
+            assert!(black.iter().all(|&b| b), "{}", dither.name());
+            let white = dither.apply(&vec![1.; SIZE * SIZE], SIZE);
+            assert!(white.iter().all(|&b| !b), "{}", dither.name());
+        }
+    }
+
+    #[test]
+    fn streaming_matches_whole_image() {
+        // Diagonal gradient, so error is carried across rows
+        let luma: Vec<f32> = (0..SIZE * SIZE)
+            .map(|i| (i % SIZE + i / SIZE) as f32 / (2 * SIZE) as f32)
+            .collect();
+        for dither in ALL_DITHERS {
+            let mut ditherer = Ditherer::new(dither, SIZE);
+            let streamed: Vec<bool> = luma
+                .chunks(SIZE)
+                .flat_map(|row| ditherer.row(row))
+                .collect();
+            assert_eq!(streamed, dither.apply(&luma, SIZE), "{}", dither.name());
+        }
+    }
+
+    #[test]
+    fn names_parse() {
+        for dither in ALL_DITHERS {
+            assert_eq!(dither.name().parse::<Dither>().unwrap(), dither);
+        }
+        assert!("dots".parse::<Dither>().is_err());
+    }
+}
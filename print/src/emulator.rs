@@ -0,0 +1,734 @@
+use std::{io, path::Path};
+
+use anyhow::{bail, ensure, Result};
+
+use qrcode::EcLevel;
+use strip_common::code::{code128, qr_modules, scale_modules};
+
+use crate::{font, raster::bit_image_mode, save_bitmap_png, PRINTER_HORIZ_RES};
+
+const DLE: u8 = 0x10;
+const DC2: u8 = 0x12;
+const ESC: u8 = 0x1b;
+const GS: u8 = 0x1d;
+const LF: u8 = b'\n';
+const CR: u8 = b'\r';
+
+/// Line spacing after `ESC @` or `ESC 2`, in dots
+pub const DEFAULT_LINE_SPACING: usize = 30;
+
+/// Font A character cell, in dots
+const FONT_A_CELL: (usize, usize) = (12, 24);
+/// Dots per pixel of our font when standing in for Font A
+const FONT_A_SCALE: usize = 2;
+
+/// `GS ( k` QR module size after `ESC @`, in dots
+const DEFAULT_QR_MODULE: usize = 3;
+/// `GS w` barcode module width after `ESC @`, in dots
+const DEFAULT_BARCODE_MODULE: usize = 3;
+/// `GS h` barcode height after `ESC @`, in dots
+const DEFAULT_BARCODE_HEIGHT: usize = 162;
+
+/// Virtual ESC/POS printer. Bytes written to it are decoded and rendered onto an endless roll of
+/// paper, which can then be inspected or saved as a PNG.
+pub struct Emulator {
+    /// Paper width in dots
+    width: usize,
+    /// Printed dots (true = black), row-major
+    paper: Vec<bool>,
+    /// Top of the current print line, in dots
+    line_y: usize,
+    /// Horizontal print position, in dots
+    x: usize,
+    /// Height of the tallest image on the current line, in dots
+    line_height: usize,
+    /// Line feed amount, in dots
+    line_spacing: usize,
+    /// Graphics stored by `GS ( L` function 112: (width, rows, packed data)
+    graphics: Option<(usize, usize, Vec<u8>)>,
+    /// Positions of `GS V` cuts, in dots from the top
+    cuts: Vec<usize>,
+    /// Text on the current line with the `ESC !` mode of each character
+    text: Vec<(char, u8)>,
+    /// `ESC !` print mode
+    print_mode: u8,
+    /// `ESC a` justification: 0 left, 1 center, 2 right
+    justification: u8,
+    /// `GS ( k` QR module size, in dots
+    qr_module: usize,
+    /// `GS ( k` QR error correction level
+    qr_ec: EcLevel,
+    /// QR code data stored with `GS ( k`
+    qr_data: Vec<u8>,
+    /// `GS w` barcode module width, in dots
+    barcode_module: usize,
+    /// `GS h` barcode height, in dots
+    barcode_height: usize,
+    /// Bytes received but not yet decoded (an incomplete command)
+    pending: Vec<u8>,
+    /// Stream offset of `pending`, for error messages
+    offset: usize,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(PRINTER_HORIZ_RES)
+    }
+}
+
+impl Emulator {
+    /// Printer with a print head `width` dots wide
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            paper: vec![],
+            line_y: 0,
+            x: 0,
+            line_height: 0,
+            line_spacing: DEFAULT_LINE_SPACING,
+            graphics: None,
+            cuts: vec![],
+            text: vec![],
+            print_mode: 0,
+            justification: 0,
+            qr_module: DEFAULT_QR_MODULE,
+            qr_ec: EcLevel::L,
+            qr_data: vec![],
+            barcode_module: DEFAULT_BARCODE_MODULE,
+            barcode_height: DEFAULT_BARCODE_HEIGHT,
+            pending: vec![],
+            offset: 0,
+        }
+    }
+
+    /// Decode a complete byte stream
+    pub fn decode(width: usize, bytes: &[u8]) -> Result<Self> {
+        let mut emu = Self::new(width);
+        emu.feed(bytes)?;
+        emu.finish()?;
+        Ok(emu)
+    }
+
+    /// Decode as many complete commands as possible; the remainder is kept for the next call
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(bytes);
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut pos = 0;
+        let result = loop {
+            match self.command(&pending[pos..]) {
+                Ok(Some(n)) => pos += n,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e.context(format!("At byte {}", self.offset + pos))),
+            }
+        };
+
+        self.offset += pos;
+        self.pending = pending;
+        self.pending.drain(..pos);
+
+        result
+    }
+
+    /// Check that the stream did not end in the middle of a command
+    pub fn finish(&self) -> Result<()> {
+        ensure!(
+            self.pending.is_empty(),
+            "Stream ends with an incomplete command at byte {}: {:02x?}",
+            self.offset,
+            self.pending
+        );
+        Ok(())
+    }
+
+    /// Paper width in dots
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Length of paper fed so far, including the current line, in dots
+    pub fn height(&self) -> usize {
+        let printed = self.paper.len() / self.width;
+        printed.max(self.line_y + self.line_height)
+    }
+
+    /// Printed paper (true = black), `width() * height()` dots row-major
+    pub fn paper(&self) -> Vec<bool> {
+        let mut paper = self.paper.clone();
+        paper.resize(self.width * self.height(), false);
+        paper
+    }
+
+    /// Positions of the cuts made so far, in dots from the top. The emulated cutter sits at the
+    /// print head.
+    pub fn cuts(&self) -> &[usize] {
+        &self.cuts
+    }
+
+    /// Save the paper as a 1-bit PNG
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_bitmap_png(path, self.width, &self.paper())
+    }
+
+    /// Execute the command at the start of `buf`, returning the number of bytes consumed, or
+    /// `None` if the command is incomplete. Text is drawn in our bitmap font, standing in for the
+    /// printer's Font A.
+    fn command(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        let Some(&first) = buf.first() else {
+            return Ok(None);
+        };
+
+        match first {
+            LF => {
+                self.line_feed();
+                Ok(Some(1))
+            }
+            CR => Ok(Some(1)),
+            // DLE EOT n: real-time status request; there's nobody to answer
+            DLE => match buf.get(1..3) {
+                Some(&[4, _]) => Ok(Some(3)),
+                Some(&[cmd, _]) => bail!("Unsupported command DLE {:#04x}", cmd),
+                _ => Ok(None),
+            },
+            // DC2 # n: print density
+            DC2 => match buf.get(1..3) {
+                Some(&[b'#', _]) => Ok(Some(3)),
+                Some(&[cmd, _]) => bail!("Unsupported command DC2 {:#04x}", cmd),
+                _ => Ok(None),
+            },
+            ESC => self.esc_command(buf),
+            GS => self.gs_command(buf),
+            b' '..=b'~' => {
+                self.text.push((first as char, self.print_mode));
+                Ok(Some(1))
+            }
+            other => bail!("Unsupported byte {:#04x}", other),
+        }
+    }
+
+    fn esc_command(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        let Some(&cmd) = buf.get(1) else {
+            return Ok(None);
+        };
+
+        match cmd {
+            // ESC @: initialize
+            b'@' => {
+                self.text.clear();
+                self.x = 0;
+                self.line_spacing = DEFAULT_LINE_SPACING;
+                self.print_mode = 0;
+                self.justification = 0;
+                self.qr_module = DEFAULT_QR_MODULE;
+                self.qr_ec = EcLevel::L;
+                self.qr_data.clear();
+                self.barcode_module = DEFAULT_BARCODE_MODULE;
+                self.barcode_height = DEFAULT_BARCODE_HEIGHT;
+                Ok(Some(2))
+            }
+            // ESC ! n: print mode
+            b'!' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                self.print_mode = n;
+                Ok(Some(3))
+            }
+            // ESC a n: justification
+            b'a' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                self.justification = match n {
+                    0..=2 => n,
+                    48..=50 => n - 48,
+                    _ => bail!("Unsupported justification {}", n),
+                };
+                Ok(Some(3))
+            }
+            // ESC 7 n1 n2 n3: heating settings
+            b'7' => Ok(buf.get(2..5).map(|_| 5)),
+            // ESC 2: default line spacing
+            b'2' => {
+                self.line_spacing = DEFAULT_LINE_SPACING;
+                Ok(Some(2))
+            }
+            // ESC 3 n: line spacing
+            b'3' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                self.line_spacing = n.into();
+                Ok(Some(3))
+            }
+            // ESC J n: print and feed n dots
+            b'J' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                self.print_text();
+                self.line_y += n as usize;
+                self.line_height = 0;
+                self.x = 0;
+                Ok(Some(3))
+            }
+            // ESC $ nL nH: absolute print position
+            b'$' => {
+                let Some(&[nl, nh]) = buf.get(2..4) else {
+                    return Ok(None);
+                };
+                self.x = u16::from_le_bytes([nl, nh]).into();
+                Ok(Some(4))
+            }
+            // ESC * m nL nH d1...dk: bit image
+            b'*' => {
+                let [m, nl, nh] = match buf.get(2..5) {
+                    Some(&[m, nl, nh]) => [m, nl, nh],
+                    _ => return Ok(None),
+                };
+                let columns = u16::from_le_bytes([nl, nh]) as usize;
+
+                let Some((dots, v_scale, h_scale)) = bit_image_mode(m) else {
+                    bail!("Unsupported bit image mode {}", m);
+                };
+
+                let bytes_per_column = dots / 8;
+                let len = 5 + columns * bytes_per_column;
+                let Some(data) = buf.get(5..len) else {
+                    return Ok(None);
+                };
+
+                for (col, column) in data.chunks_exact(bytes_per_column).enumerate() {
+                    for (row, black) in bits_msb_first(column).enumerate() {
+                        if black {
+                            let x = self.x + col * h_scale;
+                            let y = self.line_y + row * v_scale;
+                            self.fill(x, y, h_scale, v_scale);
+                        }
+                    }
+                }
+
+                self.x += columns * h_scale;
+                self.line_height = self.line_height.max(dots * v_scale);
+
+                Ok(Some(len))
+            }
+            other => bail!("Unsupported command ESC {:#04x}", other),
+        }
+    }
+
+    fn gs_command(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        let Some(&cmd) = buf.get(1) else {
+            return Ok(None);
+        };
+
+        match cmd {
+            // GS v 0 m xL xH yL yH d1...dk: raster bit image
+            b'v' => {
+                let Some(&[zero, m, xl, xh, yl, yh]) = buf.get(2..8) else {
+                    return Ok(None);
+                };
+                ensure!(zero == b'0', "Unsupported command GS v {:#04x}", zero);
+
+                let (h_scale, v_scale) = match m {
+                    0 | 48 => (1, 1),
+                    1 | 49 => (2, 1),
+                    2 | 50 => (1, 2),
+                    3 | 51 => (2, 2),
+                    _ => bail!("Unsupported raster mode {}", m),
+                };
+                let bytes_per_row = u16::from_le_bytes([xl, xh]) as usize;
+                let rows = u16::from_le_bytes([yl, yh]) as usize;
+
+                let len = 8 + bytes_per_row * rows;
+                let Some(data) = buf.get(8..len) else {
+                    return Ok(None);
+                };
+
+                self.raster(data, bytes_per_row * 8, rows, h_scale, v_scale);
+                Ok(Some(len))
+            }
+            // GS ( L pL pH m fn [params]: graphics
+            // GS ( k pL pH cn fn [params]: 2D codes
+            // GS ( K pL pH fn [params]: print control, such as speed
+            b'(' => {
+                let Some(&[l, pl, ph]) = buf.get(2..5) else {
+                    return Ok(None);
+                };
+                ensure!(
+                    matches!(l, b'L' | b'k' | b'K'),
+                    "Unsupported command GS ( {:#04x}",
+                    l
+                );
+                let len = 5 + u16::from_le_bytes([pl, ph]) as usize;
+                let Some(params) = buf.get(5..len) else {
+                    return Ok(None);
+                };
+                match l {
+                    b'L' => self.graphics(params)?,
+                    b'k' => self.qr(params)?,
+                    _ => {}
+                }
+                Ok(Some(len))
+            }
+            // GS 8 L p1 p2 p3 p4 m fn [params]: graphics with a 32 bit length
+            b'8' => {
+                let Some(&[l, p1, p2, p3, p4]) = buf.get(2..7) else {
+                    return Ok(None);
+                };
+                ensure!(l == b'L', "Unsupported command GS 8 {:#04x}", l);
+                let len = 7 + u32::from_le_bytes([p1, p2, p3, p4]) as usize;
+                let Some(params) = buf.get(7..len) else {
+                    return Ok(None);
+                };
+                self.graphics(params)?;
+                Ok(Some(len))
+            }
+            // GS r n: status request
+            b'r' => Ok(buf.get(2).map(|_| 3)),
+            // GS w n: barcode module width
+            b'w' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                ensure!(n > 0, "Barcode module width must be at least 1 dot");
+                self.barcode_module = n.into();
+                Ok(Some(3))
+            }
+            // GS h n: barcode height
+            b'h' => {
+                let Some(&n) = buf.get(2) else {
+                    return Ok(None);
+                };
+                self.barcode_height = n.into();
+                Ok(Some(3))
+            }
+            // GS H n: barcode text position; the text is never drawn
+            b'H' => Ok(buf.get(2).map(|_| 3)),
+            // GS k m n d1...dn: barcode
+            b'k' => {
+                let Some(&[m, n]) = buf.get(2..4) else {
+                    return Ok(None);
+                };
+                ensure!(m == 73, "Unsupported barcode system {}", m);
+                let len = 4 + n as usize;
+                let Some(data) = buf.get(4..len) else {
+                    return Ok(None);
+                };
+                self.barcode(data)?;
+                Ok(Some(len))
+            }
+            // GS V m [n]: cut, after feeding n dots for m = 65 or 66
+            b'V' => {
+                let Some(&m) = buf.get(2) else {
+                    return Ok(None);
+                };
+                let (len, feed) = match m {
+                    0 | 1 | 48 | 49 => (3, 0),
+                    65 | 66 => {
+                        let Some(&n) = buf.get(3) else {
+                            return Ok(None);
+                        };
+                        (4, n as usize)
+                    }
+                    _ => bail!("Unsupported cut mode {}", m),
+                };
+
+                // Anything on the current line is printed before feeding and cutting
+                self.line_y += self.line_height + feed;
+                self.line_height = 0;
+                self.x = 0;
+                self.cuts.push(self.line_y);
+                Ok(Some(len))
+            }
+            other => bail!("Unsupported command GS {:#04x}", other),
+        }
+    }
+
+    /// Graphics functions; `params` starts at `m`
+    fn graphics(&mut self, params: &[u8]) -> Result<()> {
+        match params {
+            // Print the stored graphics
+            [48, 2 | 50] => {
+                if let Some((width, rows, data)) = self.graphics.take() {
+                    self.raster(&data, width, rows, 1, 1);
+                }
+                Ok(())
+            }
+            // Store raster graphics: a bx by c xL xH yL yH d1...dk
+            [48, 112, a, bx, by, c, xl, xh, yl, yh, data @ ..] => {
+                ensure!(
+                    *a == 48,
+                    "Only monochrome graphics are supported, got tone {}",
+                    a
+                );
+                ensure!(
+                    (*bx, *by) == (1, 1),
+                    "Unsupported graphics scale {}x{}",
+                    bx,
+                    by
+                );
+                ensure!(*c == 49, "Unsupported graphics color {}", c);
+
+                let width = u16::from_le_bytes([*xl, *xh]) as usize;
+                let rows = u16::from_le_bytes([*yl, *yh]) as usize;
+                ensure!(
+                    data.len() == width.div_ceil(8) * rows,
+                    "Graphics data is {} bytes, expected {}",
+                    data.len(),
+                    width.div_ceil(8) * rows
+                );
+
+                self.graphics = Some((width, rows, data.to_vec()));
+                Ok(())
+            }
+            [m, f, ..] => bail!("Unsupported graphics function m={} fn={}", m, f),
+            _ => bail!("Graphics command too short"),
+        }
+    }
+
+    /// QR code functions; `params` starts at `cn`
+    fn qr(&mut self, params: &[u8]) -> Result<()> {
+        match params {
+            // Select the model
+            [49, 65, ..] => {}
+            // Module size
+            [49, 67, n] => {
+                ensure!(*n > 0, "QR module size must be at least 1 dot");
+                self.qr_module = *n as usize
+            }
+            // Error correction level
+            [49, 69, n] => {
+                self.qr_ec = match n {
+                    48 => EcLevel::L,
+                    49 => EcLevel::M,
+                    50 => EcLevel::Q,
+                    51 => EcLevel::H,
+                    _ => bail!("Unsupported QR error correction level {}", n),
+                }
+            }
+            // Store the data
+            [49, 80, 48, data @ ..] => self.qr_data = data.to_vec(),
+            // Print the stored data
+            [49, 81, 48] => {
+                let data = String::from_utf8_lossy(&self.qr_data).into_owned();
+                let (side, modules) = qr_modules(&data, self.qr_ec)?;
+                let (w, bitmap) = scale_modules(&modules, side, self.qr_module, self.qr_module);
+                self.code(&bitmap, w);
+            }
+            [cn, f, ..] => bail!("Unsupported 2D code function cn={} fn={}", cn, f),
+            _ => bail!("2D code command too short"),
+        }
+        Ok(())
+    }
+
+    /// Print a Code 128 barcode; `data` starts with the code set
+    fn barcode(&mut self, data: &[u8]) -> Result<()> {
+        let Some(text) = data.strip_prefix(b"{B") else {
+            bail!("Only Code 128 code set B is supported, got {:02x?}", data);
+        };
+        let text = String::from_utf8_lossy(text);
+        let Some(modules) = code128(&text) else {
+            bail!("Can't encode \"{}\" in Code 128 code set B", text);
+        };
+        let (w, bitmap) = scale_modules(
+            &modules,
+            modules.len(),
+            self.barcode_module,
+            self.barcode_height,
+        );
+        self.code(&bitmap, w);
+        Ok(())
+    }
+
+    /// Print a code `w` dots wide (true = black), justified, and feed past it
+    fn code(&mut self, bitmap: &[bool], w: usize) {
+        self.print_text();
+        let left = match self.justification {
+            1 => self.width.saturating_sub(w) / 2,
+            2 => self.width.saturating_sub(w),
+            _ => 0,
+        };
+        for (y, row) in bitmap.chunks(w).enumerate() {
+            for (x, &black) in row.iter().enumerate() {
+                if black {
+                    self.fill(left + x, self.line_y + y, 1, 1);
+                }
+            }
+        }
+
+        self.line_y += self.line_height.max(bitmap.len() / w.max(1));
+        self.line_height = 0;
+        self.x = 0;
+    }
+
+    /// Print a raster image (set bits black) at the current line and feed past it
+    fn raster(&mut self, data: &[u8], width: usize, rows: usize, h_scale: usize, v_scale: usize) {
+        let bytes_per_row = width.div_ceil(8);
+        for row in 0..rows {
+            let bits = bits_msb_first(&data[row * bytes_per_row..(row + 1) * bytes_per_row]);
+            for (col, black) in bits.take(width).enumerate() {
+                if black {
+                    let y = self.line_y + row * v_scale;
+                    self.fill(col * h_scale, y, h_scale, v_scale);
+                }
+            }
+        }
+
+        self.line_y += rows * v_scale;
+        self.line_height = 0;
+        self.x = 0;
+    }
+
+    /// Print the current line and advance the paper. Lines holding images are fed at least by
+    /// the image height, as real printers do when the line spacing is too small.
+    fn line_feed(&mut self) {
+        self.print_text();
+        self.line_y += self.line_spacing.max(self.line_height);
+        self.line_height = 0;
+        self.x = 0;
+    }
+
+    /// Draw the text on the current line, justified, and make the line tall enough for it
+    fn print_text(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        // Double width and double height bits of `ESC !`
+        let scale = |mode: u8| (1 + (mode >> 5 & 1) as usize, 1 + (mode >> 4 & 1) as usize);
+
+        let (cell_w, cell_h) = FONT_A_CELL;
+        let line_width: usize = text.iter().map(|&(_, mode)| cell_w * scale(mode).0).sum();
+        let mut x = match self.justification {
+            1 => self.width.saturating_sub(line_width) / 2,
+            2 => self.width.saturating_sub(line_width),
+            _ => self.x,
+        };
+
+        for (c, mode) in text {
+            let (sx, sy) = scale(mode);
+            let (w, h) = (FONT_A_SCALE * sx, FONT_A_SCALE * sy);
+            // Center the glyph in its cell
+            let left = x + (cell_w * sx - font::CELL_WIDTH * w) / 2;
+            let top = self.line_y + (cell_h * sy - font::CELL_HEIGHT * h) / 2;
+            for fy in 0..font::CELL_HEIGHT {
+                for fx in 0..font::CELL_WIDTH {
+                    if font::pixel(c, fx, fy) {
+                        self.fill(left + fx * w, top + fy * h, w, h);
+                    }
+                }
+            }
+
+            x += cell_w * sx;
+            self.line_height = self.line_height.max(cell_h * sy);
+        }
+    }
+
+    /// Blacken a `w` by `h` block of dots; anything past the print head is clipped
+    fn fill(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let rows = self.paper.len() / self.width;
+        if y + h > rows {
+            self.paper.resize((y + h) * self.width, false);
+        }
+
+        for row in y..y + h {
+            for col in x..(x + w).min(self.width) {
+                self.paper[row * self.width + col] = true;
+            }
+        }
+    }
+}
+
+impl io::Write for Emulator {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.feed(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Iterate the bits of `bytes`, most significant first
+fn bits_msb_first(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|b| (0..8).map(move |i| (b << i) & 0x80 != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::{RasterCommand, ALL_RASTER_COMMANDS};
+
+    const WIDTH: usize = PRINTER_HORIZ_RES;
+
+    /// Test image with blank margins, a blank gap between two patterned areas, and a last band
+    /// cut short. Dots come in `sx` by `sy` blocks, so coarse commands reproduce it exactly.
+    fn pattern(sx: usize, sy: usize) -> (usize, Vec<bool>) {
+        let height = 300 * sy / 3;
+        let bitmap = (0..height)
+            .flat_map(|y| {
+                (0..WIDTH).map(move |x| {
+                    let (bx, by) = (x / sx, y / sy);
+                    let inside = (8..WIDTH - 40).contains(&x) && !(50..70).contains(&(y / sy));
+                    inside && (bx * 7 + by * 13 + bx * by) % 5 < 2
+                })
+            })
+            .collect();
+        (height, bitmap)
+    }
+
+    fn round_trip(command: RasterCommand, bitmap: &[bool]) -> Emulator {
+        let mut bytes = vec![];
+        command.encode(&mut bytes, bitmap, WIDTH).unwrap();
+        Emulator::decode(WIDTH, &bytes).unwrap()
+    }
+
+    #[test]
+    fn raster_commands_round_trip() {
+        for command in ALL_RASTER_COMMANDS {
+            let (height, bitmap) = match command {
+                RasterCommand::BitImage8 => pattern(2, 3),
+                _ => pattern(1, 1),
+            };
+            let emu = round_trip(command, &bitmap);
+            let paper = emu.paper();
+
+            assert!(emu.height() >= height, "{}", command.name());
+            assert!(
+                paper[..bitmap.len()] == bitmap[..],
+                "{} doesn't print what was sent",
+                command.name()
+            );
+            // Bit images fill out their last band with white
+            assert!(
+                paper[bitmap.len()..].iter().all(|&black| !black),
+                "{} prints past the image",
+                command.name()
+            );
+        }
+    }
+
+    #[test]
+    fn raster_images_feed_exactly_their_height() {
+        let (height, bitmap) = pattern(1, 1);
+        for command in [RasterCommand::Raster, RasterCommand::Graphics] {
+            assert_eq!(round_trip(command, &bitmap).height(), height);
+        }
+    }
+
+    #[test]
+    fn zero_module_sizes_are_errors() {
+        let qr = [
+            &b"\x1d(k\x03\x001C\x00"[..],
+            b"\x1d(k\x05\x001P0ab",
+            b"\x1d(k\x03\x001Q0",
+        ]
+        .concat();
+        assert!(Emulator::decode(WIDTH, &qr).is_err());
+
+        let barcode = b"\x1dw\x00\x1dkI\x04{Bab";
+        assert!(Emulator::decode(WIDTH, barcode).is_err());
+    }
+}
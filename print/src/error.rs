@@ -0,0 +1,108 @@
+use std::{fmt, io, str::FromStr};
+
+use anyhow::bail;
+
+/// Errors from loading strips and sending them to a printer
+#[derive(Debug)]
+pub enum Error {
+    /// Image is not as wide as the printer
+    WrongWidth { expected: usize, actual: usize },
+    /// Image is corrupt or in a format we can't print
+    UnsupportedFormat(String),
+    /// Reading an input file failed
+    Input(io::Error),
+    /// Writing to the printer failed. `io::Error`s convert to this, since writes are the only
+    /// I/O the encoders do.
+    Transport(io::Error),
+    /// No printer was found, or it can't be reached
+    PrinterOffline(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Whether trying again might succeed, i.e. the problem is the printer and not the file
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Transport(_) | Self::PrinterOffline(_))
+    }
+
+    /// Whether the printer went away, e.g. was unplugged or switched off, so reconnecting might
+    /// let the job carry on
+    pub fn is_disconnect(&self) -> bool {
+        use io::ErrorKind::*;
+
+        match self {
+            Self::Transport(e) => matches!(
+                e.kind(),
+                NotConnected | BrokenPipe | ConnectionReset | ConnectionAborted | UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongWidth { expected, actual } => {
+                write!(f, "Image must be {} pixels wide, got {}", expected, actual)
+            }
+            Self::UnsupportedFormat(msg) => write!(f, "Unsupported image: {}", msg),
+            Self::Input(e) => write!(f, "Reading image failed: {}", e),
+            Self::Transport(e) => write!(f, "Writing to printer failed: {}", e),
+            Self::PrinterOffline(msg) => write!(f, "Printer offline: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Input(e) | Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        match e {
+            png::DecodingError::IoError(e) => Self::Input(e),
+            other => Self::UnsupportedFormat(other.to_string()),
+        }
+    }
+}
+
+/// What to do when a strip fails to print
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Report it and continue with the next strip
+    #[default]
+    Skip,
+    /// Stop the whole job
+    Abort,
+    /// Try printer problems again, then skip. Bad files are skipped straight away.
+    Retry,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "skip" => Self::Skip,
+            "abort" => Self::Abort,
+            "retry" => Self::Retry,
+            _ => bail!(
+                "Unknown error policy \"{}\"; expected skip, abort or retry",
+                s
+            ),
+        })
+    }
+}
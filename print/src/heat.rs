@@ -0,0 +1,261 @@
+use std::io::Write;
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// `ESC 7 n1 n2 n3`: heating dots, heating time and heating interval
+pub const HEAT_CONTROL: &[u8] = b"\x1b7";
+/// `DC2 # n`: print density and break time
+pub const DENSITY: &[u8] = b"\x12#";
+/// `GS ( K pL pH fn m`: print control
+pub const PRINT_CONTROL: &[u8] = b"\x1d(K";
+
+/// `GS ( K` function selecting the print speed
+const PRINT_SPEED_FUNCTION: u8 = 50;
+
+/// `ESC 7` settings after power on, for the ones not given
+const DEFAULT_HEATING_DOTS: usize = 64;
+const DEFAULT_HEATING_TIME: u32 = 800;
+const DEFAULT_HEATING_INTERVAL: u32 = 20;
+/// `DC2 #` settings after power on, for the one not given
+const DEFAULT_DENSITY: u32 = 100;
+const DEFAULT_BREAK_TIME: u32 = 500;
+
+/// Print head settings. Darker prints need more heat (a longer heating time or a higher
+/// density), and images that bleed need less; fewer heating dots at once or a lower speed help
+/// weak power supplies. Anything left unset stays as the printer has it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Heat {
+    /// Most dots heated at once, a multiple of 8 from 8 to 2048
+    pub heating_dots: Option<usize>,
+    /// Time each dot is heated, in microseconds, from 30 to 2550 in steps of 10
+    pub heating_time: Option<u32>,
+    /// Pause between heating groups of dots, in microseconds, up to 2550 in steps of 10
+    pub heating_interval: Option<u32>,
+    /// Print density in percent, from 50 to 205 in steps of 5
+    pub density: Option<u32>,
+    /// Print break time in microseconds, up to 1750 in steps of 250
+    pub break_time: Option<u32>,
+    /// Print speed, from 1 (slowest) to 9
+    pub speed: Option<u8>,
+}
+
+impl Heat {
+    /// These settings, with any unset ones taken from `base`
+    pub fn or(self, base: Heat) -> Heat {
+        Heat {
+            heating_dots: self.heating_dots.or(base.heating_dots),
+            heating_time: self.heating_time.or(base.heating_time),
+            heating_interval: self.heating_interval.or(base.heating_interval),
+            density: self.density.or(base.density),
+            break_time: self.break_time.or(base.break_time),
+            speed: self.speed.or(base.speed),
+        }
+    }
+
+    /// Check that every setting is in range
+    pub fn check(&self) -> anyhow::Result<()> {
+        if let Some(dots) = self.heating_dots {
+            ensure!(
+                (8..=2048).contains(&dots) && dots.is_multiple_of(8),
+                "Heating dots must be a multiple of 8 from 8 to 2048, got {}",
+                dots
+            );
+        }
+        if let Some(time) = self.heating_time {
+            ensure!(
+                (30..=2550).contains(&time),
+                "Heating time must be from 30 to 2550 us, got {}",
+                time
+            );
+        }
+        if let Some(interval) = self.heating_interval {
+            ensure!(
+                interval <= 2550,
+                "Heating interval must be at most 2550 us, got {}",
+                interval
+            );
+        }
+        if let Some(density) = self.density {
+            ensure!(
+                (50..=205).contains(&density),
+                "Density must be from 50 to 205%, got {}",
+                density
+            );
+        }
+        if let Some(time) = self.break_time {
+            ensure!(
+                time <= 1750,
+                "Break time must be at most 1750 us, got {}",
+                time
+            );
+        }
+        if let Some(speed) = self.speed {
+            ensure!(
+                (1..=9).contains(&speed),
+                "Speed must be from 1 to 9, got {}",
+                speed
+            );
+        }
+        Ok(())
+    }
+
+    /// Send the settings that are set. `ESC 7` and `DC2 #` set several things at once, so the
+    /// others in the same command go back to the printer's defaults.
+    pub fn send<W: Write>(&self, mut printer: W) -> Result<()> {
+        if self.heating_dots.is_some()
+            || self.heating_time.is_some()
+            || self.heating_interval.is_some()
+        {
+            let dots = self.heating_dots.unwrap_or(DEFAULT_HEATING_DOTS);
+            let time = self.heating_time.unwrap_or(DEFAULT_HEATING_TIME);
+            let interval = self.heating_interval.unwrap_or(DEFAULT_HEATING_INTERVAL);
+            printer.write_all(HEAT_CONTROL)?;
+            printer.write_all(&[(dots / 8 - 1) as u8, tens_of_us(time), tens_of_us(interval)])?;
+        }
+
+        if self.density.is_some() || self.break_time.is_some() {
+            let density = (self.density.unwrap_or(DEFAULT_DENSITY) - 50) / 5;
+            let break_time = self.break_time.unwrap_or(DEFAULT_BREAK_TIME) / 250;
+            printer.write_all(DENSITY)?;
+            printer.write_all(&[(break_time << 5 | density) as u8])?;
+        }
+
+        if let Some(speed) = self.speed {
+            printer.write_all(PRINT_CONTROL)?;
+            printer.write_all(&[2, 0, PRINT_SPEED_FUNCTION, speed])?;
+        }
+
+        printer.flush()?;
+        Ok(())
+    }
+}
+
+/// Microseconds in the units of `ESC 7`, rounded
+fn tens_of_us(us: u32) -> u8 {
+    ((us + 5) / 10).min(u8::MAX as u32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(heat: Heat) -> Vec<u8> {
+        let mut out = vec![];
+        heat.send(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn sends_only_what_is_set() {
+        assert!(sent(Heat::default()).is_empty());
+    }
+
+    #[test]
+    fn heat_control() {
+        let heat = Heat {
+            heating_dots: Some(160),
+            heating_time: Some(1204),
+            heating_interval: Some(0),
+            ..Heat::default()
+        };
+        assert_eq!(sent(heat), b"\x1b7\x13\x78\x00");
+
+        // The others in `ESC 7` go back to the printer's defaults
+        let heat = Heat {
+            heating_time: Some(30),
+            ..Heat::default()
+        };
+        assert_eq!(sent(heat), b"\x1b7\x07\x03\x02");
+    }
+
+    #[test]
+    fn density_and_break_time() {
+        let heat = Heat {
+            density: Some(205),
+            break_time: Some(1750),
+            ..Heat::default()
+        };
+        assert_eq!(sent(heat), [0x12, b'#', 7 << 5 | 31]);
+
+        let heat = Heat {
+            density: Some(50),
+            ..Heat::default()
+        };
+        assert_eq!(sent(heat), [0x12, b'#', 2 << 5]);
+    }
+
+    #[test]
+    fn speed() {
+        let heat = Heat {
+            speed: Some(3),
+            ..Heat::default()
+        };
+        assert_eq!(sent(heat), b"\x1d(K\x02\x00\x32\x03");
+    }
+
+    #[test]
+    fn check_rejects_out_of_range_settings() {
+        let checked = |heat: Heat| heat.check().is_ok();
+        assert!(checked(Heat {
+            heating_dots: Some(2048),
+            heating_time: Some(2550),
+            heating_interval: Some(2550),
+            density: Some(50),
+            break_time: Some(1750),
+            speed: Some(9),
+        }));
+
+        for bad in [
+            Heat {
+                heating_dots: Some(0),
+                ..Heat::default()
+            },
+            Heat {
+                heating_dots: Some(12),
+                ..Heat::default()
+            },
+            Heat {
+                heating_dots: Some(2056),
+                ..Heat::default()
+            },
+            Heat {
+                heating_time: Some(20),
+                ..Heat::default()
+            },
+            Heat {
+                heating_time: Some(2560),
+                ..Heat::default()
+            },
+            Heat {
+                heating_interval: Some(2560),
+                ..Heat::default()
+            },
+            Heat {
+                density: Some(45),
+                ..Heat::default()
+            },
+            Heat {
+                density: Some(210),
+                ..Heat::default()
+            },
+            Heat {
+                break_time: Some(2000),
+                ..Heat::default()
+            },
+            Heat {
+                speed: Some(0),
+                ..Heat::default()
+            },
+            Heat {
+                speed: Some(10),
+                ..Heat::default()
+            },
+        ] {
+            assert!(!checked(bad), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Journal file in the working directory when no path is given
+pub const DEFAULT_JOURNAL_PATH: &str = "print-journal.ron";
+
+/// Progress of one copy of one strip. The journal is a log of these, one RON record per line;
+/// the last record for a strip wins, and a line cut short by a crash is ignored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Strip index, as shown when printing
+    pub index: usize,
+    /// Copy number, from 0
+    pub copy: usize,
+    pub path: PathBuf,
+    /// `hash_file` of the strip, to notice when it was changed since
+    pub hash: u64,
+    /// Rows sent to the printer so far
+    pub rows: usize,
+    /// When the strip finished printing, in seconds since the Unix epoch
+    pub finished: Option<u64>,
+    /// Output the strip went to, when a job is shared between several
+    #[serde(default)]
+    pub printer: Option<String>,
+}
+
+/// Where a strip stands according to a journal being resumed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// Never started
+    NotStarted,
+    /// The file changed since it was printed, so it has to be printed again
+    Changed,
+    /// Cut short after this many rows
+    Partial(usize),
+    Finished,
+}
+
+/// Latest record for each (index, copy)
+type Records = HashMap<(usize, usize), Record>;
+
+/// Append-only record of what a job has printed, so it can be resumed after a crash or jam.
+/// Printers sharing a job share its journal behind a lock.
+pub struct Journal {
+    file: File,
+    records: Records,
+}
+
+impl Journal {
+    /// Start a new journal, replacing any previous one
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+        Ok(Self {
+            file,
+            records: HashMap::new(),
+        })
+    }
+
+    /// Continue an existing journal. A missing journal is an empty one.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (text, records) = read(path)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Opening {}", path.display()))?;
+        // End a line cut short, so the next record starts on a line of its own
+        if !text.is_empty() && !text.ends_with('\n') {
+            file.write_all(b"\n")
+                .with_context(|| format!("Writing {}", path.display()))?;
+        }
+
+        Ok(Self { file, records })
+    }
+
+    /// Latest records of the strips in the journal at `path` that never finished, in order, so
+    /// a new job doesn't replace it unawares. None if there is no journal.
+    pub fn unfinished(path: impl AsRef<Path>) -> Result<Vec<Record>> {
+        let (_, records) = read(path.as_ref())?;
+        let mut unfinished: Vec<Record> = records
+            .into_values()
+            .filter(|record| record.finished.is_none())
+            .collect();
+        unfinished.sort_by_key(|record| (record.index, record.copy));
+        Ok(unfinished)
+    }
+
+    /// Latest record for copy `copy` of strip `index`
+    pub fn get(&self, index: usize, copy: usize) -> Option<&Record> {
+        self.records.get(&(index, copy))
+    }
+
+    /// How far copy `copy` of strip `index` got, if it is still the strip at `path` with `hash`
+    pub fn progress(&self, index: usize, copy: usize, path: &Path, hash: u64) -> Progress {
+        match self.get(index, copy) {
+            None => Progress::NotStarted,
+            Some(record) if record.path != path || record.hash != hash => Progress::Changed,
+            Some(record) if record.finished.is_some() => Progress::Finished,
+            Some(record) => Progress::Partial(record.rows),
+        }
+    }
+
+    /// Append a record and make sure it is on disk
+    pub fn record(&mut self, record: Record) -> Result<()> {
+        let mut line = ron::to_string(&record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+
+        self.records.insert((record.index, record.copy), record);
+        Ok(())
+    }
+}
+
+/// Text of the journal at `path` and the latest record for each (index, copy) in it
+fn read(path: &Path) -> Result<(String, Records)> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+    };
+    let mut records = HashMap::new();
+    for line in text.lines() {
+        if let Ok(record) = ron::from_str::<Record>(line) {
+            records.insert((record.index, record.copy), record);
+        }
+    }
+    Ok((text, records))
+}
+
+/// Starting value of a 64 bit FNV-1a hash
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// 64 bit FNV-1a hash of a file's contents
+pub fn hash_file(path: impl AsRef<Path>) -> io::Result<u64> {
+    let mut f = BufReader::new(File::open(path)?);
+    let mut hash = FNV_OFFSET;
+    let mut buf = [0; 8192];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+}
+
+/// 64 bit FNV-1a hash of a bitmap, for strips that aren't files of their own
+pub fn hash_bitmap(bitmap: &[bool]) -> u64 {
+    fnv1a(FNV_OFFSET, &crate::bools_to_bits(bitmap))
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_journal(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("print-journal-{}-{}.ron", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn record(index: usize, rows: usize, finished: Option<u64>) -> Record {
+        Record {
+            index,
+            copy: 0,
+            path: format!("{}.png", index).into(),
+            hash: 0x1234,
+            rows,
+            finished,
+            printer: None,
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_last_record() {
+        let path = temp_journal("partial");
+        let mut journal = Journal::create(&path).unwrap();
+        journal.record(record(0, 0, None)).unwrap();
+        journal.record(record(0, 384, Some(1))).unwrap();
+        journal.record(record(1, 0, None)).unwrap();
+        journal.record(record(1, 128, None)).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        let progress = |index| journal.progress(index, 0, &record(index, 0, None).path, 0x1234);
+        assert_eq!(progress(0), Progress::Finished);
+        assert_eq!(progress(1), Progress::Partial(128));
+        assert_eq!(progress(2), Progress::NotStarted);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn changed_files_are_printed_again() {
+        let path = temp_journal("changed");
+        let mut journal = Journal::create(&path).unwrap();
+        journal.record(record(0, 384, Some(1))).unwrap();
+        journal.record(record(1, 100, None)).unwrap();
+
+        let old = record(0, 0, None).path;
+        assert_eq!(journal.progress(0, 0, &old, 0x5678), Progress::Changed);
+        assert_eq!(journal.progress(1, 0, &old, 0x1234), Progress::Changed);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_line_is_ignored() {
+        let path = temp_journal("truncated");
+        let mut journal = Journal::create(&path).unwrap();
+        journal.record(record(0, 64, None)).unwrap();
+        drop(journal);
+
+        // Crash halfway through the next record
+        let line = ron::to_string(&record(0, 256, None)).unwrap();
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&line.as_bytes()[..line.len() / 2]).unwrap();
+        drop(f);
+
+        let mut journal = Journal::open(&path).unwrap();
+        let strip = record(0, 0, None).path;
+        assert_eq!(
+            journal.progress(0, 0, &strip, 0x1234),
+            Progress::Partial(64)
+        );
+
+        // Records after the broken line are still read back
+        journal.record(record(0, 128, None)).unwrap();
+        drop(journal);
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(
+            journal.progress(0, 0, &strip, 0x1234),
+            Progress::Partial(128)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lists_unfinished_strips() {
+        let path = temp_journal("unfinished");
+        let mut journal = Journal::create(&path).unwrap();
+        journal.record(record(2, 0, None)).unwrap();
+        journal.record(record(0, 0, None)).unwrap();
+        journal.record(record(0, 384, Some(1))).unwrap();
+        journal.record(record(1, 128, None)).unwrap();
+        drop(journal);
+
+        let unfinished = Journal::unfinished(&path).unwrap();
+        assert_eq!(unfinished, [record(1, 128, None), record(2, 0, None)]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Journal::unfinished(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_journal_is_empty() {
+        let path = temp_journal("missing");
+        let journal = Journal::open(&path).unwrap();
+        assert!(journal.get(0, 0).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
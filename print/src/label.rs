@@ -0,0 +1,237 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::bail;
+
+use crate::{
+    error::Result,
+    font,
+    raster::{feed, pack_row, Encoder, RasterCommand},
+};
+
+pub const SELECT_PRINT_MODE: &[u8] = b"\x1b!";
+pub const SELECT_JUSTIFICATION: &[u8] = b"\x1ba";
+pub const DEFAULT_LINE_SPACING: &[u8] = b"\x1b2";
+
+/// `ESC !` mode for labels: emphasized Font A
+const PRINT_MODE_EMPHASIZED: u8 = 0x08;
+/// `ESC a` center justification
+const JUSTIFY_CENTER: u8 = 1;
+/// Width of a Font A character, in dots
+pub const FONT_A_WIDTH: usize = 12;
+/// Bitmap font scale, in dots per font pixel
+const BITMAP_SCALE: usize = 2;
+/// Blank paper between a label and the strip, in dots, to cut along
+const LABEL_GAP: usize = 16;
+
+/// How labels are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LabelFont {
+    /// The printer's built-in Font A, centered with `ESC a`
+    #[default]
+    Printer,
+    /// Our own 5x7 font, sent as an image; looks the same on every printer
+    Bitmap,
+}
+
+impl FromStr for LabelFont {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "printer" => Self::Printer,
+            "bitmap" => Self::Bitmap,
+            _ => bail!("Unknown label font \"{}\"; expected printer or bitmap", s),
+        })
+    }
+}
+
+/// Where labels go on a strip
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelPlacement {
+    Header,
+    Footer,
+    Both,
+}
+
+impl LabelPlacement {
+    pub fn header(&self) -> bool {
+        matches!(self, Self::Header | Self::Both)
+    }
+
+    pub fn footer(&self) -> bool {
+        matches!(self, Self::Footer | Self::Both)
+    }
+}
+
+impl FromStr for LabelPlacement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "header" => Self::Header,
+            "footer" => Self::Footer,
+            "both" => Self::Both,
+            _ => bail!(
+                "Unknown label placement \"{}\"; expected header, footer or both",
+                s
+            ),
+        })
+    }
+}
+
+/// Line printed above or below a strip saying which strip it is and which way is up. It is
+/// separated from the image by a gap, so it can be cut off after assembly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub index: usize,
+    /// Name of the job, e.g. the collage
+    pub job: String,
+    /// Length of the strip's image, in millimeters
+    pub length_mm: f32,
+}
+
+impl Label {
+    /// Label text with `arrow` pointing towards the top of the image at both ends
+    fn text(&self, arrow: &str) -> String {
+        format!(
+            "{} #{} {} {:.0}mm {}",
+            arrow, self.index, self.job, self.length_mm, arrow
+        )
+    }
+
+    /// Print the label above a strip
+    pub fn header<W: Write>(
+        &self,
+        mut printer: W,
+        font: LabelFont,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        self.print(&mut printer, font, command, width)?;
+        feed(&mut printer, LABEL_GAP)
+    }
+
+    /// Print the label below a strip
+    pub fn footer<W: Write>(
+        &self,
+        mut printer: W,
+        font: LabelFont,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        feed(&mut printer, LABEL_GAP)?;
+        self.print(&mut printer, font, command, width)
+    }
+
+    fn print<W: Write>(
+        &self,
+        mut printer: W,
+        font: LabelFont,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        match font {
+            LabelFont::Printer => {
+                // The code page is unknown beyond ASCII, and '^' is the closest thing to an arrow
+                let text: String = self
+                    .text("^")
+                    .chars()
+                    .map(|c| if c.is_ascii_graphic() { c } else { ' ' })
+                    .take(width / FONT_A_WIDTH)
+                    .collect();
+
+                // Bit images leave the line spacing at 0, which would overprint the text
+                printer.write_all(DEFAULT_LINE_SPACING)?;
+                printer.write_all(SELECT_JUSTIFICATION)?;
+                printer.write_all(&[JUSTIFY_CENTER])?;
+                printer.write_all(SELECT_PRINT_MODE)?;
+                printer.write_all(&[PRINT_MODE_EMPHASIZED])?;
+                printer.write_all(text.as_bytes())?;
+                printer.write_all(b"\n")?;
+                printer.write_all(SELECT_PRINT_MODE)?;
+                printer.write_all(&[0])?;
+                printer.write_all(SELECT_JUSTIFICATION)?;
+                printer.write_all(&[0])?;
+            }
+            LabelFont::Bitmap => {
+                let bitmap = font::render(&self.text("↑"), BITMAP_SCALE, width);
+                let mut encoder = Encoder::new(&mut printer, command, width)?;
+                for row in bitmap.chunks(width) {
+                    encoder.push_row(&pack_row(row))?;
+                }
+                encoder.finish()?;
+            }
+        }
+
+        printer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    const WIDTH: usize = 384;
+
+    fn label() -> Label {
+        Label {
+            index: 3,
+            job: "collage".into(),
+            length_mm: 120.4,
+        }
+    }
+
+    #[test]
+    fn text_points_up_at_both_ends() {
+        assert_eq!(label().text("^"), "^ #3 collage 120mm ^");
+    }
+
+    #[test]
+    fn printer_font_text_is_cut_to_the_width() {
+        let mut out = vec![];
+        label()
+            .print(
+                &mut out,
+                LabelFont::Printer,
+                RasterCommand::Raster,
+                9 * FONT_A_WIDTH,
+            )
+            .unwrap();
+        assert!(out.windows(10).any(|w| w == b"^ #3 coll\n"));
+        assert!(!out.windows(4).any(|w| w == b"lage"));
+    }
+
+    #[test]
+    fn gap_is_between_label_and_strip() {
+        let paper = |header: bool| {
+            let mut out = vec![];
+            let (font, command) = (LabelFont::Bitmap, RasterCommand::Raster);
+            if header {
+                label().header(&mut out, font, command, WIDTH).unwrap();
+            } else {
+                label().footer(&mut out, font, command, WIDTH).unwrap();
+            }
+            Emulator::decode(WIDTH, &out).unwrap().paper()
+        };
+        let text = font::render(&label().text("↑"), BITMAP_SCALE, WIDTH);
+        let gap = vec![false; LABEL_GAP * WIDTH];
+
+        assert_eq!(paper(true), [text.clone(), gap.clone()].concat());
+        assert_eq!(paper(false), [gap, text].concat());
+    }
+
+    #[test]
+    fn names_parse() {
+        assert_eq!("bitmap".parse::<LabelFont>().unwrap(), LabelFont::Bitmap);
+        assert_eq!("printer".parse::<LabelFont>().unwrap(), LabelFont::Printer);
+        assert!("serif".parse::<LabelFont>().is_err());
+
+        let both: LabelPlacement = "both".parse().unwrap();
+        assert!(both.header() && both.footer());
+        let footer: LabelPlacement = "footer".parse().unwrap();
+        assert!(!footer.header() && footer.footer());
+        assert!("side".parse::<LabelPlacement>().is_err());
+    }
+}
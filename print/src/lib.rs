@@ -0,0 +1,537 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::ensure;
+use png::{BitDepth, ColorType, Transformations};
+
+pub mod calibrate;
+pub mod code;
+pub mod devices;
+pub mod dispatch;
+pub mod dither;
+pub mod emulator;
+pub mod error;
+pub mod font;
+pub mod heat;
+pub mod journal;
+pub mod label;
+pub mod manifest;
+pub mod mock;
+pub mod pacing;
+pub mod poster;
+pub mod preview;
+pub mod profile;
+pub mod queue;
+pub mod raster;
+pub mod reconnect;
+pub mod separator;
+pub mod serve;
+pub mod sink;
+pub mod status;
+pub mod transform;
+pub mod usb;
+
+use dither::{Dither, Ditherer};
+use error::{Error, Result};
+use raster::{pack_row, Encoder, RasterCommand};
+
+pub const PIXELS_PER_BYTE: usize = 8;
+
+/// Number of bytes per printer row
+pub const PRINTER_BYTES_PER_ROW: usize = 48;
+
+/// Horizontal pixels per row for the printer
+pub const PRINTER_HORIZ_RES: usize = PRINTER_BYTES_PER_ROW * PIXELS_PER_BYTE;
+
+pub const LS_SET: &[u8] = b"\x1b\x33";
+
+/// Load a PNG `width` pixels wide, of any bit depth and color type, as a bitmap (true = black).
+/// Anything that isn't already black and white is reduced with `dither`; transparency is
+/// composited onto white paper.
+pub fn load_png(path: impl AsRef<Path>, width: usize, dither: Dither) -> Result<Vec<bool>> {
+    let (actual, luma) = load_luma(path)?;
+    check_width(width, actual as u32)?;
+    Ok(dither.apply(&luma, width))
+}
+
+/// Load a PNG of any size, bit depth and color type as luminance (0 = black, 1 = white),
+/// returning its width and the pixels. Transparency is composited onto white paper.
+pub fn load_luma(path: impl AsRef<Path>) -> Result<(usize, Vec<f32>)> {
+    read_luma(File::open(path).map_err(Error::Input)?)
+}
+
+/// `load_luma` for a PNG read from `reader`, e.g. an upload
+pub fn read_luma(reader: impl Read) -> Result<(usize, Vec<f32>)> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    buf.truncate(info.buffer_size());
+
+    Ok((info.width as usize, to_luma(&buf, info.color_type)))
+}
+
+/// Height of a PNG in rows, from its header
+pub fn png_height(path: impl AsRef<Path>) -> Result<usize> {
+    Ok(png_size(path)?.1)
+}
+
+/// Width and height of a PNG, from its header
+pub fn png_size(path: impl AsRef<Path>) -> Result<(usize, usize)> {
+    let decoder = png::Decoder::new(File::open(path).map_err(Error::Input)?);
+    let reader = decoder.read_info()?;
+    let info = reader.info();
+    Ok((info.width as usize, info.height as usize))
+}
+
+/// Text of a PNG's `keyword` text chunk, if it has one before the image data
+pub fn png_text(path: impl AsRef<Path>, keyword: &str) -> Result<Option<String>> {
+    let decoder = png::Decoder::new(File::open(path).map_err(Error::Input)?);
+    let reader = decoder.read_info()?;
+    let text = reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == keyword)
+        .map(|chunk| chunk.text.clone());
+    Ok(text)
+}
+
+/// Reads a PNG of any bit depth and color type one row at a time, dithering each row into packed
+/// dots (see `raster::pack_row`). Interlaced images can't be streamed and are decoded whole.
+pub struct PngRows {
+    reader: png::Reader<File>,
+    color_type: ColorType,
+    ditherer: Ditherer,
+    /// Decoded frame of an interlaced image, with its line size and next row
+    frame: Option<(Vec<u8>, usize, usize)>,
+}
+
+impl PngRows {
+    /// Open a PNG, which must be `width` pixels wide
+    pub fn open(path: impl AsRef<Path>, width: usize, dither: Dither) -> Result<Self> {
+        let mut decoder = png::Decoder::new(File::open(path).map_err(Error::Input)?);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+
+        let info = reader.info();
+        check_width(width, info.width)?;
+
+        let frame = if info.interlaced {
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let out = reader.next_frame(&mut buf)?;
+            Some((buf, out.line_size, 0))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            color_type: reader.output_color_type().0,
+            reader,
+            ditherer: Ditherer::new(dither, width),
+            frame,
+        })
+    }
+
+    /// Image height in rows
+    pub fn height(&self) -> usize {
+        self.reader.info().height as usize
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<u8>>> {
+        let luma = match &mut self.frame {
+            Some((buf, line_size, next)) => {
+                let Some(row) = buf.chunks_exact(*line_size).nth(*next) else {
+                    return Ok(None);
+                };
+                *next += 1;
+                to_luma(row, self.color_type)
+            }
+            None => match self.reader.next_row()? {
+                Some(row) => to_luma(row.data(), self.color_type),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(pack_row(&self.ditherer.row(&luma))))
+    }
+}
+
+impl Iterator for PngRows {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+/// Stream a PNG `width` pixels wide to the printer, dithering it with `dither`. Printing starts
+/// as soon as the first band is decoded, and memory use doesn't depend on the image length.
+///
+/// Printing starts at row `start`, to finish a strip that was cut short. Earlier rows are still
+/// dithered, so the result matches the strip printed whole. Raster images are sent
+/// `band_rows` rows at a time if given (see `Encoder::with_band_rows`). `progress` is called
+/// with the number of rows printed (counting from the top of the image) after every band.
+#[allow(clippy::too_many_arguments)]
+pub fn print_png_from<W: Write>(
+    printer: W,
+    path: impl AsRef<Path>,
+    width: usize,
+    dither: Dither,
+    command: RasterCommand,
+    band_rows: Option<usize>,
+    start: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<()> {
+    let rows = PngRows::open(path, width, dither)?;
+    let height = rows.height();
+    let mut encoder = Encoder::with_band_rows(printer, command, width, band_rows)?;
+    let mut sent = 0;
+    for (y, row) in rows.enumerate() {
+        let row = row?;
+        if y < start {
+            continue;
+        }
+
+        encoder.push_row(&row)?;
+        if encoder.rows_sent() != sent {
+            sent = encoder.rows_sent();
+            progress(start + sent);
+        }
+    }
+    encoder.finish()?;
+    progress(height);
+
+    Ok(())
+}
+
+/// `print_bitmap` for a bitmap (true = black) `width` dots per row, starting at row `start`, and
+/// taking `band_rows` and calling `progress` as `print_png_from` does
+pub fn print_bitmap_from<W: Write>(
+    printer: W,
+    bitmap: &[bool],
+    width: usize,
+    command: RasterCommand,
+    band_rows: Option<usize>,
+    start: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<()> {
+    let height = bitmap.len() / width;
+    let mut encoder = Encoder::with_band_rows(printer, command, width, band_rows)?;
+    let mut sent = 0;
+    for row in bitmap.chunks(width).skip(start) {
+        encoder.push_row(&pack_row(row))?;
+        if encoder.rows_sent() != sent {
+            sent = encoder.rows_sent();
+            progress(start + sent);
+        }
+    }
+    encoder.finish()?;
+    progress(height);
+
+    Ok(())
+}
+
+fn check_width(expected: usize, actual: u32) -> Result<()> {
+    if actual as usize != expected {
+        return Err(Error::WrongWidth {
+            expected,
+            actual: actual as usize,
+        });
+    }
+    Ok(())
+}
+
+/// Convert 8-bit pixels to luminance (0 = black, 1 = white)
+fn to_luma(buf: &[u8], color_type: ColorType) -> Vec<f32> {
+    let norm = |v: u8| v as f32 / 255.;
+    let rgb = |p: &[u8]| 0.2126 * norm(p[0]) + 0.7152 * norm(p[1]) + 0.0722 * norm(p[2]);
+    let over_white = |l: f32, a: u8| l * norm(a) + 1. - norm(a);
+
+    match color_type {
+        ColorType::Grayscale => buf.iter().copied().map(norm).collect(),
+        ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .map(|p| over_white(norm(p[0]), p[1]))
+            .collect(),
+        ColorType::Rgb => buf.chunks_exact(3).map(rgb).collect(),
+        ColorType::Rgba => buf
+            .chunks_exact(4)
+            .map(|p| over_white(rgb(p), p[3]))
+            .collect(),
+        // EXPAND turns palettes into RGB(A)
+        ColorType::Indexed => unreachable!("Indexed color after expansion"),
+    }
+}
+
+/// Pack a bitmap (true = black) into bytes as a 1-bit grayscale PNG stores it, 0 for black
+pub fn bools_to_bits(bitmap: &[bool]) -> Vec<u8> {
+    bitmap
+        .chunks(PIXELS_PER_BYTE)
+        .map(|px| {
+            px.iter()
+                .enumerate()
+                .fold(0, |b, (i, &black)| if black { b } else { b | 0x80 >> i })
+        })
+        .collect()
+}
+
+/// Save a bitmap (true = black) as a 1-bit grayscale PNG
+pub fn save_bitmap_png(
+    path: impl AsRef<Path>,
+    width: usize,
+    bitmap: &[bool],
+) -> anyhow::Result<()> {
+    ensure!(
+        width.is_multiple_of(PIXELS_PER_BYTE),
+        "Width must be a multiple of {} dots, got {}",
+        PIXELS_PER_BYTE,
+        width
+    );
+    let height = bitmap.len() / width;
+
+    let w = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(w, width as _, height as _);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::One);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&bools_to_bits(bitmap))?;
+
+    Ok(())
+}
+
+/// Send `bitmap` (true = black), `width` dots per row, using the given raster command
+pub fn print_bitmap<W: Write>(
+    mut printer: W,
+    bitmap: &[bool],
+    width: usize,
+    command: RasterCommand,
+) -> Result<()> {
+    if bitmap.is_empty() || width == 0 || !bitmap.len().is_multiple_of(width) {
+        return Err(Error::UnsupportedFormat(format!(
+            "bitmap of {} dots is not a whole number of {} dot rows",
+            bitmap.len(),
+            width
+        )));
+    }
+
+    command.encode(&mut printer, bitmap, width)?;
+
+    printer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::raster::ALL_RASTER_COMMANDS;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 90;
+
+    /// Grayscale test image: a gradient with a pattern over it and blank rows in the middle
+    fn gray() -> Vec<u8> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                if (30..40).contains(&y) {
+                    255
+                } else {
+                    ((x * 4 + y) % 256) as u8 ^ ((x * y) % 7 * 9) as u8
+                }
+            })
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("print-lib-{}-{}.png", std::process::id(), name))
+    }
+
+    fn write_png(name: &str, color: ColorType, data: &[u8]) -> PathBuf {
+        let path = temp_path(name);
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), WIDTH as _, HEIGHT as _);
+        encoder.set_color(color);
+        encoder.set_depth(BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        path
+    }
+
+    /// The png crate can't write interlaced images, so this one is put together by hand: 8-bit
+    /// grayscale, Adam7, unfiltered and stored without compression
+    fn write_interlaced_png(name: &str, data: &[u8]) -> PathBuf {
+        fn crc32(bytes: &[u8]) -> u32 {
+            !bytes.iter().fold(!0, |crc, &b| {
+                (0..8).fold(crc ^ b as u32, |c, _| {
+                    (c >> 1) ^ (0xedb8_8320 & (c & 1).wrapping_neg())
+                })
+            })
+        }
+        fn chunk(png: &mut Vec<u8>, kind: &[u8], body: &[u8]) {
+            png.extend((body.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend(kind);
+            png.extend(body);
+            let crc = crc32(&png[start..]);
+            png.extend(crc.to_be_bytes());
+        }
+
+        const PASSES: [(usize, usize, usize, usize); 7] = [
+            (0, 0, 8, 8),
+            (4, 0, 8, 8),
+            (0, 4, 4, 8),
+            (2, 0, 4, 4),
+            (0, 2, 2, 4),
+            (1, 0, 2, 2),
+            (0, 1, 1, 2),
+        ];
+        let mut raw = vec![];
+        for (x0, y0, dx, dy) in PASSES {
+            for y in (y0..HEIGHT).step_by(dy) {
+                if x0 >= WIDTH {
+                    continue;
+                }
+                raw.push(0);
+                raw.extend((x0..WIDTH).step_by(dx).map(|x| data[y * WIDTH + x]));
+            }
+        }
+
+        // zlib stream of stored deflate blocks
+        let mut zlib = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = raw.chunks(u16::MAX as usize).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            zlib.push((i + 1 == blocks.len()) as u8);
+            zlib.extend((block.len() as u16).to_le_bytes());
+            zlib.extend((!(block.len() as u16)).to_le_bytes());
+            zlib.extend(*block);
+        }
+        let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), &v| {
+            let a = (a + v as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        zlib.extend((b << 16 | a).to_be_bytes());
+
+        let mut ihdr = vec![];
+        ihdr.extend((WIDTH as u32).to_be_bytes());
+        ihdr.extend((HEIGHT as u32).to_be_bytes());
+        // Bit depth, color type, compression, filter, interlace
+        ihdr.extend([8, 0, 0, 0, 1]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &ihdr);
+        chunk(&mut png, b"IDAT", &zlib);
+        chunk(&mut png, b"IEND", &[]);
+
+        let path = temp_path(name);
+        std::fs::write(&path, png).unwrap();
+        path
+    }
+
+    /// `print_png_from` and `print_bitmap_from` of the fully loaded image, from row `start`
+    fn both_ways(path: &Path, command: RasterCommand, start: usize) -> (Vec<u8>, Vec<u8>) {
+        let dither = Dither::FloydSteinberg;
+        let mut streamed = vec![];
+        print_png_from(
+            &mut streamed,
+            path,
+            WIDTH,
+            dither,
+            command,
+            None,
+            start,
+            |_| {},
+        )
+        .unwrap();
+
+        let bitmap = load_png(path, WIDTH, dither).unwrap();
+        let mut loaded = vec![];
+        print_bitmap_from(&mut loaded, &bitmap, WIDTH, command, None, start, |_| {}).unwrap();
+        (streamed, loaded)
+    }
+
+    #[test]
+    fn streaming_matches_loading() {
+        let gray = gray();
+        let rgba: Vec<u8> = gray.iter().flat_map(|&l| [l, l, l, 255]).collect();
+        let paths = [
+            write_png("gray", ColorType::Grayscale, &gray),
+            write_png("rgba", ColorType::Rgba, &rgba),
+            write_interlaced_png("interlaced", &gray),
+        ];
+
+        let bitmap = load_png(&paths[0], WIDTH, Dither::FloydSteinberg).unwrap();
+        for path in &paths {
+            assert_eq!(
+                load_png(path, WIDTH, Dither::FloydSteinberg).unwrap(),
+                bitmap
+            );
+
+            for command in ALL_RASTER_COMMANDS {
+                let (streamed, loaded) = both_ways(path, command, 0);
+                assert!(streamed == loaded, "{} {}", path.display(), command.name());
+
+                let mut whole = vec![];
+                print_bitmap(&mut whole, &bitmap, WIDTH, command).unwrap();
+                assert!(streamed == whole, "{} {}", path.display(), command.name());
+            }
+        }
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn streaming_from_a_row_matches_loading() {
+        let paths = [
+            write_png("start", ColorType::Grayscale, &gray()),
+            write_interlaced_png("start-interlaced", &gray()),
+        ];
+        for path in &paths {
+            for command in ALL_RASTER_COMMANDS {
+                for start in [1, 35, 50] {
+                    let (streamed, loaded) = both_ways(path, command, start);
+                    assert!(streamed == loaded, "{} from row {}", command.name(), start);
+                }
+            }
+        }
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn progress_ends_at_the_height() {
+        let path = write_png("progress", ColorType::Grayscale, &gray());
+        let mut seen = vec![];
+        let command = RasterCommand::Raster;
+        print_png_from(
+            std::io::sink(),
+            &path,
+            WIDTH,
+            Dither::Bayer,
+            command,
+            Some(16),
+            20,
+            |rows| seen.push(rows),
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]), "{:?}", seen);
+        assert!(seen.iter().all(|&rows| rows > 20), "{:?}", seen);
+        assert_eq!(seen.last(), Some(&HEIGHT));
+    }
+}
@@ -1,132 +1,2042 @@
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
-    path::Path,
-    time::Duration,
+    borrow::Cow,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use png::{BitDepth, ColorType};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use print::{
+    calibrate::test_page,
+    code::{parse_scene, Codes, StripCodes, StripId, SCENE_KEYWORD},
+    devices::{Devices, DEFAULT_DEVICES_PATH},
+    dispatch::Dispatcher,
+    dither::Dither,
+    emulator::Emulator,
+    error::ErrorPolicy,
+    heat::Heat,
+    journal::{hash_bitmap, hash_file, now, Journal, Progress, Record, DEFAULT_JOURNAL_PATH},
+    label::{Label, LabelFont, LabelPlacement},
+    load_luma, load_png,
+    manifest::{is_manifest, Manifest},
+    pacing::Pacing,
+    png_height, png_size, png_text,
+    poster::Poster,
+    preview::{render, PreviewStyle},
+    print_bitmap_from, print_png_from,
+    profile::{load_profiles, select_profile, Profile},
+    queue::{Queue, Settings},
+    raster::RasterCommand,
+    reconnect::Backoff,
+    save_bitmap_png,
+    separator::{Cut, Separator},
+    serve::{Listen, Uploads, DEFAULT_LISTEN},
+    sink::Sink,
+    status::{Monitor, Status, Transport},
+    transform::{on_its_side, parse_file_transform, parse_mirror, parse_rotation, Step, Transform},
+    usb::{list_printers, UsbSelector},
+    PngRows,
+};
 
-const PIXELS_PER_BYTE: usize = 8;
+/// Pause before trying a failed strip again
+const RETRY_DELAY: Duration = Duration::from_secs(2);
 
-/// Number of bytes per printer row
-const PRINTER_BYTES_PER_ROW: usize = 48;
+/// Timeout for reading USB printers' serial numbers and names
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(1);
 
-/// Horizontal pixels per row for the printer
-const PRINTER_HORIZ_RES: usize = PRINTER_BYTES_PER_ROW * PIXELS_PER_BYTE;
+/// Minimum time between progress records in the journal
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(1);
 
-pub const BITMAP_D24: &[u8] = b"\x1b\x2a\x21"; // 32: 24 dots double density,203dpi
-pub const LS_SET: &[u8] = b"\x1b\x33";
+/// Print collage strips on ESC/POS thermal printers
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-fn main() -> Result<()> {
-    let args = std::env::args().skip(1);
+    #[command(flatten)]
+    print: PrintArgs,
+
+    /// Printer profile name; defaults to the first profile
+    // The flattened argument groups set `next_help_heading`; keep the global options under
+    // Options rather than the last group's heading
+    #[arg(short, long, global = true, help_heading = None)]
+    profile: Option<String>,
+
+    /// Printer profiles file; defaults to ./printers.ron, then the built-in profiles
+    #[arg(long, global = true, help_heading = None)]
+    profiles: Option<PathBuf>,
+
+    /// USB printer to print on: BUS:ADDRESS, VID:PID or serial number, as `print devices` lists
+    /// them. It is remembered for the profile in ./print-devices.ron; "any" forgets it and takes
+    /// the first printer found.
+    #[arg(long, global = true, help_heading = None)]
+    device: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print strips (the default)
+    Print(Box<PrintArgs>),
+    /// Show strips in the terminal, dithered as they would be printed
+    Preview(PreviewArgs),
+    /// Print a test page of gray ramps, rulers and resolution wedges for tuning dithering, heat
+    /// and alignment
+    Calibrate(CalibrateArgs),
+    /// Render a captured byte stream as the paper a printer would produce
+    Emulate(EmulateArgs),
+    /// Own the printer and print jobs queued over HTTP by anyone on this machine
+    Serve(Box<ServeArgs>),
+    /// List the USB printers connected, and which profiles they are chosen for
+    Devices,
+}
+
+#[derive(Args)]
+struct PrintArgs {
+    /// PNG files, directories of numbered PNGs as written by strip_gui, or job manifests (.ron or
+    /// .json) listing strips with their settings. With --poster, PNGs of any size.
+    files: Vec<PathBuf>,
+
+    /// Don't wait for enter before each strip
+    #[arg(short, long)]
+    batch: bool,
+
+    /// Seconds to wait between strips
+    #[arg(long, default_value_t = 0.)]
+    delay: f64,
+
+    /// Copies of each strip, unless its manifest says otherwise
+    #[arg(short, long, default_value_t = 1)]
+    copies: usize,
+
+    /// First strip index to print. A strip's index is its numeric file name (`57.png`), or its
+    /// position in the list otherwise.
+    #[arg(long)]
+    from: Option<usize>,
+
+    /// Last strip index to print (inclusive)
+    #[arg(long)]
+    to: Option<usize>,
+
+    /// Where to send the job: usb (the first USB printer), usb:BUS:ADDRESS, usb:VID:PID or
+    /// usb:SERIAL, stdout (or -), file:PATH, tcp:HOST[:PORT], serial:PATH, or
+    /// mock:PNG[,STATE@POLL[xCOUNT]...] for a scripted mock printer (paper-out, cover-open,
+    /// near-end, error, disconnect, fail). Give it more than once to print on several printers
+    /// at once: each takes the next strip when it is free, without waiting for enter, and a
+    /// printer that fails leaves its strips to the others.
+    #[arg(short, long, default_value = "usb")]
+    output: Vec<Sink>,
+
+    /// Dithering for images that aren't black and white
+    #[arg(short, long, default_value = "floyd-steinberg")]
+    dither: Dither,
+
+    /// Raster command; defaults to the profile's preferred one
+    #[arg(short, long)]
+    raster: Option<RasterCommand>,
+
+    /// Blank paper before each strip, in mm, so it starts clear of the tear bar
+    #[arg(long, default_value_t = 0.)]
+    margin: f32,
+
+    /// Paper to feed after each strip, in mm
+    #[arg(long, default_value_t = 0.)]
+    feed: f32,
+
+    /// Print a dashed line after each strip to cut along
+    #[arg(long)]
+    cut_line: bool,
+
+    /// Cut after each strip: full or partial. The profile must have a cutter.
+    #[arg(long)]
+    cut: Option<Cut>,
+
+    /// Seconds before a transfer to the printer or a status request times out
+    #[arg(long, default_value_t = 2.)]
+    timeout: f64,
+
+    /// Don't ask the printer for its status between bands
+    #[arg(long)]
+    no_status: bool,
+
+    /// Job journal recording what has been printed, for --resume
+    #[arg(long, default_value = DEFAULT_JOURNAL_PATH)]
+    journal: PathBuf,
+
+    /// Skip strips the journal says were printed, and finish a partly printed one where it
+    /// stopped
+    #[arg(long)]
+    resume: bool,
+
+    /// Start a new journal even if the last job left strips unfinished
+    #[arg(long, conflicts_with = "resume")]
+    fresh: bool,
+
+    /// With --resume, restart the partly printed strip this many mm from its top instead
+    #[arg(long, requires = "resume")]
+    offset: Option<f32>,
+
+    /// Print a label with the strip index, job name, length and an arrow to the top: header,
+    /// footer or both
+    #[arg(long)]
+    label: Option<LabelPlacement>,
+
+    /// Label font: printer (built-in) or bitmap
+    #[arg(long, default_value = "printer")]
+    label_font: LabelFont,
+
+    /// Job name for labels; defaults to the name of the first file or directory
+    #[arg(long)]
+    job: Option<String>,
+
+    /// Print machine-readable codes with the strip index and scene hash: qr, barcode or both
+    #[arg(long)]
+    code: Option<Codes>,
+
+    /// Where to print the codes: header, footer or both
+    #[arg(long, default_value = "header")]
+    code_at: LabelPlacement,
+
+    /// Send codes as images even if the printer can draw them itself
+    #[arg(long)]
+    code_bitmap: bool,
+
+    /// Scene hash for the codes, in hex; defaults to the one strip_gui stores in each PNG
+    #[arg(long, value_parser = parse_scene)]
+    scene: Option<u32>,
+
+    /// Decode and dither every strip, but don't open the output or print anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// When a strip fails: skip it, abort the job, or retry printer errors before skipping
+    #[arg(long, default_value = "skip")]
+    on_error: ErrorPolicy,
+
+    /// Attempts per strip with --on-error retry
+    #[arg(long, default_value_t = 3)]
+    attempts: usize,
+
+    /// Seconds to keep trying to reconnect to a printer that was unplugged or switched off,
+    /// before the strip fails. It then carries on from the last band the printer took. 0 to not
+    /// reconnect.
+    #[arg(long, default_value_t = 60.)]
+    reconnect: f64,
+
+    #[command(flatten)]
+    heat: HeatArgs,
+
+    #[command(flatten)]
+    pacing: PacingArgs,
+
+    #[command(flatten)]
+    transform: TransformArgs,
+
+    #[command(flatten)]
+    poster: PosterArgs,
+}
 
-    let mut ctx = libusb::Context::new()?;
+/// Print head settings, overriding the profile's
+#[derive(Args)]
+#[command(next_help_heading = "Print head")]
+struct HeatArgs {
+    /// Most dots heated at once (ESC 7), a multiple of 8
+    #[arg(long)]
+    heating_dots: Option<usize>,
 
-    let printer = pos58_usb::POS58USB::new(&mut ctx, Duration::from_secs(2))?;
-    let mut writer = BufWriter::new(printer);
+    /// Heating time in microseconds (ESC 7); longer prints darker
+    #[arg(long)]
+    heating_time: Option<u32>,
 
-    //let mut printer = vec![];
-    //let mut writer = BufWriter::new(&mut printer);
+    /// Heating interval in microseconds (ESC 7)
+    #[arg(long)]
+    heating_interval: Option<u32>,
 
-    for path in args {
-        println!("Press enter when ready to print {}", path);
-        let _ = std::io::stdin().read_line(&mut String::new());
+    /// Print density in percent (DC2 #)
+    #[arg(long)]
+    density: Option<u32>,
 
-        let image = load_bitmap_png(&path).context(path.clone())?;
-        let image = bits_to_bools(&image);
-        print_bitmap(&mut writer, &image)?;
+    /// Print break time in microseconds (DC2 #)
+    #[arg(long)]
+    break_time: Option<u32>,
+
+    /// Print speed from 1 (slowest) to 9 (GS ( K)
+    #[arg(long)]
+    speed: Option<u8>,
+}
+
+impl HeatArgs {
+    fn heat(&self) -> Heat {
+        Heat {
+            heating_dots: self.heating_dots,
+            heating_time: self.heating_time,
+            heating_interval: self.heating_interval,
+            density: self.density,
+            break_time: self.break_time,
+            speed: self.speed,
+        }
     }
+}
 
-    drop(writer);
-    //std::io::stdout().write_all(&printer)?;
-    //std::io::stdout().flush()?;
+/// How fast to send data, overriding the profile's
+#[derive(Args)]
+#[command(next_help_heading = "Pacing")]
+struct PacingArgs {
+    /// Most bytes sent to the printer per second, for printers whose buffer overflows
+    #[arg(long)]
+    max_rate: Option<u32>,
 
-    Ok(())
+    /// Bands sent between status polls, which wait for the printer to catch up
+    #[arg(long)]
+    poll_every: Option<usize>,
+
+    /// Rows per raster or graphics band
+    #[arg(long)]
+    band_rows: Option<usize>,
+}
+
+impl PacingArgs {
+    fn pacing(&self) -> Pacing {
+        Pacing {
+            max_rate: self.max_rate,
+            poll_every: self.poll_every,
+            band_rows: self.band_rows,
+        }
+    }
+}
+
+/// Orientation and tone of strips
+#[derive(Args)]
+#[command(next_help_heading = "Orientation")]
+struct TransformArgs {
+    /// Turn every strip clockwise: 90, 180 or 270 degrees
+    #[arg(long, value_parser = parse_rotation)]
+    rotate: Option<Step>,
+
+    /// Mirror every strip: horizontal or vertical; give it twice for both
+    #[arg(long, value_parser = parse_mirror)]
+    mirror: Vec<Step>,
+
+    /// Swap black and white
+    #[arg(long)]
+    invert: bool,
+
+    /// Transform one file before the job's transforms are applied, with STEPS from rotate-90,
+    /// rotate-180, rotate-270, mirror-h, mirror-v and invert separated by commas. FILE matches
+    /// the end of a strip's path, so 3.png matches dir/3.png.
+    #[arg(long, value_name = "FILE=STEPS", value_parser = parse_file_transform)]
+    file_transform: Vec<(PathBuf, Transform)>,
+
+    /// Don't turn images that are as tall as the printer is wide, instead of as wide
+    #[arg(long)]
+    no_auto_rotate: bool,
+}
+
+impl TransformArgs {
+    /// Steps for the strip at `path`: its own, then the job's
+    fn transform(&self, path: &Path) -> Transform {
+        let mut steps = vec![];
+        for (file, transform) in &self.file_transform {
+            if path.ends_with(file) {
+                steps.extend(&transform.0);
+            }
+        }
+        steps.extend(self.rotate);
+        steps.extend(&self.mirror);
+        if self.invert {
+            steps.push(Step::Invert);
+        }
+        Transform(steps)
+    }
+
+    /// `own` steps from a manifest, then `transform`, after turning the image a quarter if it
+    /// is on its side: `width` tall but not `width` wide. A PNG that can't be read is left to
+    /// fail when it is printed.
+    fn strip_transform(&self, path: &Path, own: &Transform, width: usize) -> Transform {
+        let transform = own.then(&self.transform(path));
+        match png_size(path) {
+            Ok((w, h)) if !self.no_auto_rotate && on_its_side(w, h, width) => {
+                eprintln!(
+                    "{} is {} dots tall and {} wide; turning it a quarter clockwise",
+                    path.display(),
+                    h,
+                    w
+                );
+                Transform(vec![Step::Rotate90]).then(&transform)
+            }
+            _ => transform,
+        }
+    }
+}
+
+/// Cutting images into strips
+#[derive(Args)]
+#[command(next_help_heading = "Poster")]
+struct PosterArgs {
+    /// Scale each image to this width in mm and print it as strips the print width across, left
+    /// to right, instead of as one strip
+    #[arg(long, value_name = "MM")]
+    poster: Option<f32>,
+
+    /// Repeat this many mm of the image at the start of each strip, so strips can overlap when
+    /// pasted up
+    #[arg(long, value_name = "MM", requires = "poster")]
+    overlap: Option<f32>,
+
+    /// Leave this many mm of the image out between strips, so it stays in proportion when
+    /// strips are laid edge to edge; without a value, the paper's unprinted margins
+    #[arg(
+        long,
+        value_name = "MM",
+        num_args = 0..=1,
+        requires = "poster",
+        conflicts_with = "overlap"
+    )]
+    gutter: Option<Option<f32>>,
+}
+
+impl PosterArgs {
+    fn poster(&self, profile: &Profile) -> Option<Poster> {
+        Some(Poster {
+            width: self.poster?,
+            overlap: self.overlap.unwrap_or(0.),
+            gutter: match self.gutter {
+                Some(Some(gutter)) => gutter,
+                Some(None) => profile.paper_width - profile.print_width,
+                None => 0.,
+            },
+        })
+    }
+}
+
+#[derive(Args)]
+struct PreviewArgs {
+    /// PNG files, or directories of numbered PNGs as written by strip_gui
+    files: Vec<PathBuf>,
+
+    /// Dithering for images that aren't black and white
+    #[arg(short, long, default_value = "floyd-steinberg")]
+    dither: Dither,
+
+    /// Characters to draw with: braille or half-block
+    #[arg(long, default_value = "braille")]
+    style: PreviewStyle,
+
+    /// Width in characters; defaults to $COLUMNS, or 80
+    #[arg(long)]
+    columns: Option<usize>,
+
+    /// Show long strips a screen at a time, waiting for enter between screens
+    #[arg(long)]
+    scroll: bool,
+
+    /// Lines per screen with --scroll; defaults to $LINES, or 24
+    #[arg(long)]
+    lines: Option<usize>,
+}
+
+#[derive(Args)]
+struct CalibrateArgs {
+    /// Where to send the page, as for print
+    #[arg(short, long, default_value = "usb")]
+    output: Sink,
+
+    /// Raster command; defaults to the profile's preferred one
+    #[arg(short, long)]
+    raster: Option<RasterCommand>,
+
+    /// Also save the page as a PNG
+    #[arg(long)]
+    save: Option<PathBuf>,
+
+    /// Seconds before a transfer to the printer or a status request times out
+    #[arg(long, default_value_t = 2.)]
+    timeout: f64,
+
+    #[command(flatten)]
+    heat: HeatArgs,
+
+    #[command(flatten)]
+    pacing: PacingArgs,
 }
 
-fn load_bitmap_png(path: impl AsRef<Path>) -> Result<Vec<u8>> {
-    let decoder = png::Decoder::new(File::open(path)?);
-    let mut reader = decoder.read_info()?;
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).unwrap();
+#[derive(Args)]
+struct ServeArgs {
+    /// Address for the API: HOST:PORT, a port on localhost, or unix:PATH for a Unix socket
+    #[arg(short, long, default_value = DEFAULT_LISTEN)]
+    listen: Listen,
+
+    /// Where to send jobs, as for print
+    #[arg(short, long, default_value = "usb")]
+    output: Sink,
+
+    /// Dithering for images that aren't black and white
+    #[arg(short, long, default_value = "floyd-steinberg")]
+    dither: Dither,
+
+    /// Raster command; defaults to the profile's preferred one
+    #[arg(short, long)]
+    raster: Option<RasterCommand>,
+
+    /// Copies of each strip, unless the job says otherwise
+    #[arg(short, long, default_value_t = 1)]
+    copies: usize,
+
+    /// Blank paper before each strip, in mm
+    #[arg(long, default_value_t = 0.)]
+    margin: f32,
+
+    /// Paper to feed after each strip, in mm
+    #[arg(long, default_value_t = 0.)]
+    feed: f32,
 
-    assert_eq!(info.bit_depth, BitDepth::One);
-    assert_eq!(info.color_type, ColorType::Grayscale);
-    assert_eq!(info.width as usize, PRINTER_HORIZ_RES);
+    /// Print a dashed line after each strip to cut along
+    #[arg(long)]
+    cut_line: bool,
 
-    buf.truncate(info.buffer_size());
+    /// Cut after each strip: full or partial. The profile must have a cutter.
+    #[arg(long)]
+    cut: Option<Cut>,
 
-    Ok(buf)
+    /// Print a label with the strip index, job name, length and an arrow to the top: header,
+    /// footer or both
+    #[arg(long)]
+    label: Option<LabelPlacement>,
+
+    /// Label font: printer (built-in) or bitmap
+    #[arg(long, default_value = "printer")]
+    label_font: LabelFont,
+
+    /// Seconds before a transfer to the printer or a status request times out
+    #[arg(long, default_value_t = 2.)]
+    timeout: f64,
+
+    /// Don't ask the printer for its status between bands
+    #[arg(long)]
+    no_status: bool,
+
+    /// Seconds to keep trying to reconnect to a printer that was unplugged or switched off,
+    /// before the job fails. It then carries on from the last band the printer took. 0 to not
+    /// reconnect.
+    #[arg(long, default_value_t = 60.)]
+    reconnect: f64,
+
+    /// Directory that paths in uploaded manifests are relative to. Manifests can't name files
+    /// outside it.
+    #[arg(long, default_value = ".")]
+    dir: PathBuf,
+
+    #[command(flatten)]
+    heat: HeatArgs,
+
+    #[command(flatten)]
+    pacing: PacingArgs,
 }
 
-fn bits_to_bools(image: &[u8]) -> Vec<bool> {
-    image
-        .iter()
-        .map(|b| (0..8).map(move |i| (b << i) & 0x80 == 0))
-        .flatten()
-        .collect()
+#[derive(Args)]
+struct EmulateArgs {
+    /// Captured byte stream, or - for stdin
+    input: String,
+
+    /// PNG to write
+    output: PathBuf,
 }
 
-fn print_bitmap<W: Write>(mut printer: W, bitmap: &[bool]) -> Result<()> {
-    // Sanity check, determine height
-    let width = PRINTER_HORIZ_RES;
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let profiles = load_profiles(cli.profiles.as_deref())?;
+    let profile = select_profile(&profiles, cli.profile.as_deref())?;
+
+    let device = || usb_device(cli.device.as_deref(), &profile);
+    match cli.command {
+        Some(Command::Print(args)) => print(*args, &profile, device()?.as_ref()),
+        Some(Command::Preview(args)) => preview(args, &profile),
+        Some(Command::Calibrate(args)) => calibrate(args, &profile, device()?.as_ref()),
+        Some(Command::Emulate(args)) => emulate(args, &profile),
+        Some(Command::Serve(args)) => serve(*args, &profile, device()?.as_ref()),
+        Some(Command::Devices) => devices(),
+        None => print(cli.print, &profile, device()?.as_ref()),
+    }
+}
+
+/// The USB printer to print on with `profile`: the one given with `--device`, which is
+/// remembered for next time, or else the one remembered. `None` for the first one found.
+fn usb_device(device: Option<&str>, profile: &Profile) -> Result<Option<UsbSelector>> {
+    let mut devices = Devices::load(DEFAULT_DEVICES_PATH)?;
+    let Some(device) = device else {
+        return Ok(devices.get(&profile.name).cloned());
+    };
+
+    let device = match device {
+        "any" => None,
+        device => Some(identify(device.parse()?)),
+    };
+    if devices.set(&profile.name, device.clone()) {
+        match &device {
+            Some(device) => eprintln!(
+                "Printing on USB printer {} with profile \"{}\" from now on",
+                device, profile.name
+            ),
+            None => eprintln!(
+                "Printing on the first USB printer found with profile \"{}\" from now on",
+                profile.name
+            ),
+        }
+        if let Err(e) = devices.save(DEFAULT_DEVICES_PATH) {
+            eprintln!("Remembering the USB printer: {:#}", e);
+        }
+    }
+    Ok(device)
+}
+
+/// `selector` as the printer's serial number, if it picks a connected printer that has one, so
+/// it still finds the printer once it is plugged in again somewhere else
+fn identify(selector: UsbSelector) -> UsbSelector {
+    list_printers(DEVICE_TIMEOUT)
+        .ok()
+        .and_then(|printers| printers.into_iter().find(|printer| printer.is(&selector)))
+        .map(|printer| printer.selector())
+        .unwrap_or(selector)
+}
+
+/// `print devices`
+fn devices() -> Result<()> {
+    let printers = list_printers(DEVICE_TIMEOUT)?;
+    if printers.is_empty() {
+        eprintln!("No USB printers found");
+        return Ok(());
+    }
+
+    let devices = Devices::load(DEFAULT_DEVICES_PATH)?;
+    for printer in &printers {
+        let name = [&printer.manufacturer, &printer.product]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let serial = match &printer.serial {
+            Some(serial) => format!("serial {}", serial),
+            None => "no serial".into(),
+        };
+        let chosen = devices.chosen(printer);
+        let chosen = if chosen.is_empty() {
+            String::new()
+        } else {
+            format!(" (chosen for {})", chosen.join(", "))
+        };
+        println!(
+            "usb:{}:{}  {:04x}:{:04x}  {}  {}{}",
+            printer.bus,
+            printer.address,
+            printer.vendor_id,
+            printer.product_id,
+            serial,
+            name,
+            chosen
+        );
+    }
+    Ok(())
+}
+
+fn print(mut args: PrintArgs, profile: &Profile, device: Option<&UsbSelector>) -> Result<()> {
+    args.output = args
+        .output
+        .into_iter()
+        .map(|sink| sink.or_device(device))
+        .collect();
+    let command = profile.raster_command(args.raster)?;
+
+    if let Some(cut) = args.cut {
+        ensure!(
+            profile.cutter,
+            "Printer profile \"{}\" has no cutter for a {} cut",
+            profile.name,
+            cut.name()
+        );
+    }
+    let heat = args.heat.heat().or(profile.heat);
+    heat.check()?;
+    let pacing = args.pacing.pacing().or(profile.pacing);
+    pacing.check()?;
+
+    let job = Job {
+        name: args.job.clone().unwrap_or_else(|| job_name(&args.files)),
+        profile,
+        args: &args,
+        command,
+        separator: Separator {
+            margin: profile.mm_to_dots(args.margin),
+            feed: profile.mm_to_dots(args.feed),
+            cut_line: args.cut_line,
+            cut: args.cut,
+        },
+        codes: args.code.map(|codes| StripCodes {
+            codes,
+            native_qr: profile.qr && !args.code_bitmap,
+            native_barcode: !args.code_bitmap,
+        }),
+        heat,
+        pacing,
+    };
+
+    let strips: Vec<Strip> = match args.poster.poster(profile) {
+        Some(poster) => poster_strips(&args, &poster, profile)?,
+        None => file_strips(&args, profile)?,
+    }
+    .into_iter()
+    .filter(|strip| args.from.is_none_or(|from| strip.index >= from))
+    .filter(|strip| args.to.is_none_or(|to| strip.index <= to))
+    .collect();
+
+    if strips.is_empty() {
+        bail!("Nothing to print");
+    }
+
+    // Strips that failed and were skipped
+    let mut failed = vec![];
+
+    if args.dry_run {
+        for strip in &strips {
+            let Strip {
+                index: idx, path, ..
+            } = strip;
+            let height = match strip.bitmap(profile.dots_per_row, args.dither) {
+                Ok(Some(dots)) => Ok(dots.len() / profile.dots_per_row),
+                Ok(None) => check_png(path, profile.dots_per_row, args.dither),
+                Err(e) => Err(e),
+            };
+            let height = match height {
+                Ok(height) => height,
+                Err(e) => {
+                    eprintln!("Strip {}: {}: {}", idx, path.display(), e);
+                    if args.on_error == ErrorPolicy::Abort {
+                        return Err(e).with_context(|| path.display().to_string());
+                    }
+                    failed.push(*idx);
+                    continue;
+                }
+            };
+            let transform = if strip.transform.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", strip.transform)
+            };
+            eprintln!(
+                "Strip {}: {} ({} rows, {:.1} mm{}) x {}",
+                idx,
+                path.display(),
+                height,
+                height as f32 / profile.dots_per_mm(),
+                transform,
+                strip.copies
+            );
+        }
+        return check_failed(&failed, strips.len());
+    }
+
+    let mut journal = if args.resume {
+        Journal::open(&args.journal)?
+    } else {
+        if !args.fresh {
+            let unfinished: Vec<String> = Journal::unfinished(&args.journal)?
+                .iter()
+                .map(|record| record.index.to_string())
+                .collect();
+            ensure!(
+                unfinished.is_empty(),
+                "{} has unfinished strips: {}. Give --resume to finish them, or --fresh to start \
+                 a new journal",
+                args.journal.display(),
+                unfinished.join(", ")
+            );
+        }
+        Journal::create(&args.journal)?
+    };
+    if args.output.len() > 1 {
+        return print_parallel(&job, &strips, journal);
+    }
+    let mut offset = args.offset;
 
-    let total_pixels = bitmap.len();
-    assert_eq!(total_pixels % width, 0);
-    assert!(bitmap.len() > 0);
-    //let height = total_pixels / width;
+    let query = if args.no_status { None } else { profile.status };
+    let mut ctx = None;
+    let printer = args.output[0].open(
+        &mut ctx,
+        profile.dots_per_row,
+        Duration::from_secs_f64(args.timeout),
+    )?;
+    let mut writer = Monitor::new(job.pacing.pace(printer), query, |status| {
+        eprintln!(
+            "Printer stopped: {}. Waiting for it to be ready...",
+            status.describe()
+        )
+    })
+    .poll_every(job.pacing.poll_every());
 
-    printer.write_all(LS_SET)?;
-    printer.write(&[0])?;
+    let total: usize = strips.iter().map(|strip| strip.copies).sum();
+    let mut done = 0;
+    'strips: for strip in &strips {
+        let Strip {
+            index: idx, path, ..
+        } = strip;
+        let hash = strip.hash();
 
-    let bytes_per_line = 3 * 8 * width;
-    for window in bitmap.chunks(bytes_per_line) {
-        printer.write_all(BITMAP_D24)?;
-        printer.write_all(&u16::to_le_bytes(3 * width as u16))?;
+        for copy in 0..strip.copies {
+            // Where to start the strip, in rows
+            let mut start = 0;
+            if args.resume {
+                match journal.progress(*idx, copy, path, hash) {
+                    Progress::Changed => {
+                        eprintln!(
+                            "Strip {} (copy {}) changed since it was printed; printing it again",
+                            idx,
+                            copy + 1
+                        );
+                    }
+                    Progress::Finished => {
+                        eprintln!(
+                            "[{}/{}] Strip {} (copy {}) already printed",
+                            done + 1,
+                            total,
+                            idx,
+                            copy + 1
+                        );
+                        done += 1;
+                        continue;
+                    }
+                    Progress::Partial(rows) => {
+                        start = match offset.take() {
+                            Some(mm) => profile.mm_to_dots(mm),
+                            None => rows,
+                        };
+                        eprintln!(
+                            "Resuming strip {} (copy {}) {:.1} mm from the top",
+                            idx,
+                            copy + 1,
+                            start as f32 / profile.dots_per_mm()
+                        );
+                    }
+                    Progress::NotStarted => {}
+                }
+            }
+
+            if done > 0 && args.delay > 0. {
+                thread::sleep(Duration::from_secs_f64(args.delay));
+            }
+
+            // Prompt on stderr; stdout may be the sink
+            if args.batch {
+                eprintln!(
+                    "[{}/{}] Printing strip {}: {}",
+                    done + 1,
+                    total,
+                    idx,
+                    path.display()
+                );
+            } else {
+                eprintln!(
+                    "[{}/{}] Press enter when ready to print strip {} (copy {}): {}",
+                    done + 1,
+                    total,
+                    idx,
+                    copy + 1,
+                    path.display()
+                );
+                let _ = std::io::stdin().read_line(&mut String::new());
+            }
 
-        for x in 0..width {
-            for set in 0..3 {
-                let mut b = 0;
-                for bit in 0..8 {
-                    let row = set * 8 + bit;
-                    let idx = row * width + x;
-                    let w = window.get(idx).copied().unwrap_or(false);
+            let mut record = Record {
+                index: *idx,
+                copy,
+                path: path.clone(),
+                hash,
+                rows: start,
+                finished: None,
+                printer: None,
+            };
+            write_journal(&mut journal, &record);
 
-                    b <<= 1;
-                    if w {
-                        b |= 1;
-                    };
+            let result = job.print_copy(&mut writer, &args.output[0], strip, &mut record, |r| {
+                write_journal(&mut journal, r)
+            });
+            let printed = match result {
+                Ok(()) => true,
+                Err(e) if args.on_error == ErrorPolicy::Abort => {
+                    return Err(e).with_context(|| path.display().to_string())
                 }
-                printer.write(&[b])?;
+                Err(e) => {
+                    if failed.last() != Some(idx) {
+                        failed.push(*idx);
+                    }
+                    // The other copies of a bad file would fail the same way
+                    if !e.is_retryable() {
+                        done += strip.copies - copy;
+                        continue 'strips;
+                    }
+                    false
+                }
+            };
+
+            if printed {
+                record.finished = Some(now());
+                write_journal(&mut journal, &record);
             }
+            done += 1;
         }
+    }
 
-        printer.write(b"\n")?;
+    writer.flush()?;
+
+    check_failed(&failed, strips.len())
+}
+
+/// One copy of a strip, as handed to a printer
+struct Piece<'a> {
+    /// Position in the job, from 1, for progress messages
+    number: usize,
+    strip: &'a Strip,
+    copy: usize,
+    /// `Strip::hash`
+    hash: u64,
+}
+
+/// What the printers sharing a job share
+struct Shared<'a> {
+    dispatcher: Dispatcher<Piece<'a>>,
+    journal: Mutex<Journal>,
+    /// Strips that failed and were skipped
+    failed: Mutex<Vec<usize>>,
+    total: usize,
+}
+
+/// Print on every output at once, each taking the next copy of a strip as soon as it is free
+fn print_parallel(job: &Job, strips: &[Strip], journal: Journal) -> Result<()> {
+    let args = job.args;
+    ensure!(
+        args.offset.is_none(),
+        "--offset needs a single output, since a strip is resumed on the printer it started on"
+    );
+    for (pos, sink) in args.output.iter().enumerate() {
+        ensure!(
+            !args.output[..pos].contains(sink),
+            "Output {} is given twice",
+            sink
+        );
+        let usb = |sink: &Sink| matches!(sink, Sink::Usb(_));
+        ensure!(
+            *sink != Sink::Usb(None) || args.output.iter().filter(|s| usb(s)).count() == 1,
+            "Select each USB printer as usb:BUS:ADDRESS or usb:SERIAL when printing to several"
+        );
     }
 
-    printer.flush()?;
+    let mut pieces = vec![];
+    for strip in strips {
+        let hash = strip.hash();
+        for copy in 0..strip.copies {
+            if args.resume {
+                match journal.progress(strip.index, copy, &strip.path, hash) {
+                    Progress::Changed => {
+                        eprintln!(
+                            "Strip {} (copy {}) changed since it was printed; printing it again",
+                            strip.index,
+                            copy + 1
+                        );
+                    }
+                    Progress::Finished => {
+                        eprintln!("Strip {} (copy {}) already printed", strip.index, copy + 1);
+                        continue;
+                    }
+                    // Any printer may take it, so it can't carry on where it stopped
+                    Progress::Partial(_) => {
+                        eprintln!(
+                            "Strip {} (copy {}) was partly printed; printing it again from the top",
+                            strip.index,
+                            copy + 1
+                        );
+                    }
+                    Progress::NotStarted => {}
+                }
+            }
+            pieces.push(Piece {
+                number: pieces.len() + 1,
+                strip,
+                copy,
+                hash,
+            });
+        }
+    }
+
+    let shared = Shared {
+        total: pieces.len(),
+        dispatcher: Dispatcher::new(pieces, args.output.len()),
+        journal: Mutex::new(journal),
+        failed: Mutex::new(vec![]),
+    };
+    let results: Vec<Result<()>> = thread::scope(|scope| {
+        let printers: Vec<_> = args
+            .output
+            .iter()
+            .map(|sink| scope.spawn(|| print_shared(job, sink, &shared)))
+            .collect();
+        printers
+            .into_iter()
+            .map(|printer| printer.join().expect("Printer thread panicked"))
+            .collect()
+    });
+    // The first error that stopped the job, after every printer finished its strip
+    results.into_iter().collect::<Result<()>>()?;
+
+    let mut failed = shared
+        .failed
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    let remaining = shared.dispatcher.into_remaining();
+    if !remaining.is_empty() {
+        eprintln!("No printers left for {} copies", remaining.len());
+    }
+    for piece in remaining {
+        if !failed.contains(&piece.strip.index) {
+            failed.push(piece.strip.index);
+        }
+    }
+    failed.sort_unstable();
+    check_failed(&failed, strips.len())
+}
+
+/// Print the pieces `sink` takes from `shared` until there are none left, it fails, or the job
+/// is aborted
+fn print_shared(job: &Job, sink: &Sink, shared: &Shared) -> Result<()> {
+    let args = job.args;
+    let profile = job.profile;
+    let mut ctx = None;
+    let printer = match sink.open(
+        &mut ctx,
+        profile.dots_per_row,
+        Duration::from_secs_f64(args.timeout),
+    ) {
+        Ok(printer) => printer,
+        Err(e) => {
+            let left = shared.dispatcher.leave();
+            eprintln!("{}: {}; {} printers left", sink, e, left);
+            return Ok(());
+        }
+    };
+    let query = if args.no_status { None } else { profile.status };
+    let mut writer = Monitor::new(job.pacing.pace(printer), query, |status| {
+        eprintln!(
+            "{}: printer stopped: {}. Waiting for it to be ready...",
+            sink,
+            status.describe()
+        )
+    })
+    .poll_every(job.pacing.poll_every());
+
+    let mut printed = 0;
+    while let Some(piece) = shared.dispatcher.next() {
+        let Piece {
+            number,
+            strip,
+            copy,
+            hash,
+        } = piece;
+        let (idx, path) = (strip.index, &strip.path);
+        if printed > 0 && args.delay > 0. {
+            thread::sleep(Duration::from_secs_f64(args.delay));
+        }
+        eprintln!(
+            "[{}/{}] {}: printing strip {} (copy {}): {}",
+            number,
+            shared.total,
+            sink,
+            idx,
+            copy + 1,
+            path.display()
+        );
+
+        let journal = |record: &Record| {
+            write_journal(
+                &mut shared
+                    .journal
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner),
+                record,
+            )
+        };
+        let mut record = Record {
+            index: idx,
+            copy,
+            path: path.clone(),
+            hash,
+            rows: 0,
+            finished: None,
+            printer: Some(sink.to_string()),
+        };
+        journal(&record);
+
+        let result = job.print_copy(&mut writer, sink, strip, &mut record, journal);
+        match result {
+            Ok(()) => {
+                record.finished = Some(now());
+                journal(&record);
+                printed += 1;
+                shared.dispatcher.done();
+            }
+            Err(e) => {
+                if args.on_error == ErrorPolicy::Abort {
+                    shared.dispatcher.abort();
+                    shared.dispatcher.done();
+                    return Err(e).with_context(|| format!("{}: {}", sink, path.display()));
+                }
+                // The printer is at fault, so another one should print the strip
+                if e.is_retryable() {
+                    let left = shared.dispatcher.give_up(piece);
+                    eprintln!(
+                        "{}: giving up on this printer; {} printers left",
+                        sink, left
+                    );
+                    return Ok(());
+                }
+                let mut failed = shared.failed.lock().unwrap_or_else(PoisonError::into_inner);
+                if !failed.contains(&idx) {
+                    failed.push(idx);
+                }
+                shared.dispatcher.done();
+            }
+        }
+    }
 
+    writer.flush()?;
     Ok(())
 }
 
-fn write_pbm(path: impl AsRef<Path>, width: usize, height: usize, data: &[u8]) -> Result<()> {
-    let mut f = BufWriter::new(File::create(path)?);
-    assert!(width % 8 == 0);
-    writeln!(f, "P1")?;
-    writeln!(f, "{} {}", width, height)?;
-    for row in data.chunks_exact(width / 8) {
-        for &k in row {
-            for bit in 0..8 {
-                if k & (0x80 << bit) != 0 {
-                    write!(f, "0")?;
-                } else {
-                    write!(f, "1")?;
+/// One strip of a job
+struct Strip {
+    /// Index shown when printing, and used by --from, --to and the journal
+    index: usize,
+    /// PNG the strip is printed from
+    path: PathBuf,
+    /// Dots of a strip cut from a poster, `dots_per_row` wide (true = black); `None` to print
+    /// the PNG
+    dots: Option<Vec<bool>>,
+    /// Applied to the PNG before it is printed
+    transform: Transform,
+    copies: usize,
+    /// Label text in place of the job name
+    label: Option<String>,
+    /// Print density in place of the job's
+    density: Option<u32>,
+    /// Cut in place of the job's
+    cut: Option<Cut>,
+}
+
+impl Strip {
+    /// The strip's dots, `width` per row (true = black), or `None` if the PNG prints as it is
+    /// and can be streamed
+    fn bitmap(
+        &self,
+        width: usize,
+        dither: Dither,
+    ) -> print::error::Result<Option<Cow<'_, [bool]>>> {
+        if let Some(dots) = &self.dots {
+            return Ok(Some(Cow::Borrowed(dots)));
+        }
+        if self.transform.is_empty() {
+            return Ok(None);
+        }
+
+        let (w, luma) = load_luma(&self.path)?;
+        let (w, luma) = self.transform.apply(w, luma);
+        if w != width {
+            return Err(print::error::Error::WrongWidth {
+                expected: width,
+                actual: w,
+            });
+        }
+        Ok(Some(Cow::Owned(dither.apply(&luma, width))))
+    }
+
+    /// Hash recorded in the journal, to notice when the strip changes
+    fn hash(&self) -> u64 {
+        match &self.dots {
+            Some(dots) => hash_bitmap(dots),
+            None => hash_file(&self.path).unwrap_or_default(),
+        }
+    }
+}
+
+/// Settings shared by every strip of a job
+struct Job<'a> {
+    /// Name printed on labels
+    name: String,
+    profile: &'a Profile,
+    args: &'a PrintArgs,
+    command: RasterCommand,
+    separator: Separator,
+    /// Machine-readable codes printed with each strip
+    codes: Option<StripCodes>,
+    heat: Heat,
+    pacing: Pacing,
+}
+
+impl Job<'_> {
+    /// Print copy `record.copy` of `strip` on `sink` from row `record.rows`, keeping `record`
+    /// up to date and handing it to `journal` now and then and after each failure. A printer
+    /// that goes away is reconnected and the strip carried on where it stopped, unless it went
+    /// away again before taking another band; with `--on-error retry`, other printer errors
+    /// start it again. Returns the error it gave up on.
+    fn print_copy<T: Transport, F: FnMut(&Status)>(
+        &self,
+        writer: &mut Monitor<T, F>,
+        sink: &Sink,
+        strip: &Strip,
+        record: &mut Record,
+        mut journal: impl FnMut(&Record),
+    ) -> print::error::Result<()> {
+        let args = self.args;
+        let (idx, copy) = (strip.index, record.copy);
+        let mut start = record.rows;
+        let mut attempt = 1;
+        // Row the strip was last resumed from, so a printer that goes away again before taking
+        // another band isn't reconnected forever
+        let mut resumed = None;
+        let mut last_write = Instant::now();
+        loop {
+            let result = self.print_strip(&mut *writer, strip, start, |rows| {
+                record.rows = rows;
+                if last_write.elapsed() >= JOURNAL_INTERVAL {
+                    journal(record);
+                    last_write = Instant::now();
+                }
+            });
+            let Err(e) = result else {
+                return Ok(());
+            };
+            eprintln!(
+                "{}: strip {} failed: {}: {}",
+                sink,
+                idx,
+                strip.path.display(),
+                e
+            );
+            // Keep how far it got, for --resume
+            journal(record);
+
+            if e.is_disconnect() && resumed != Some(record.rows) && reconnect(writer, args, sink) {
+                start = record.rows;
+                resumed = Some(start);
+                eprintln!(
+                    "{}: resuming strip {} (copy {}) {:.1} mm from the top",
+                    sink,
+                    idx,
+                    copy + 1,
+                    start as f32 / self.profile.dots_per_mm()
+                );
+            } else if args.on_error == ErrorPolicy::Retry
+                && e.is_retryable()
+                && attempt < args.attempts
+            {
+                attempt += 1;
+                eprintln!("Retrying (attempt {}/{})", attempt, args.attempts);
+                thread::sleep(RETRY_DELAY);
+            } else {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Print one strip from row `start`, with its labels and the paper handling around it
+    fn print_strip<W: Write>(
+        &self,
+        mut printer: W,
+        strip: &Strip,
+        start: usize,
+        progress: impl FnMut(usize),
+    ) -> print::error::Result<()> {
+        let (idx, path) = (strip.index, &strip.path);
+        let width = self.profile.dots_per_row;
+        let dots = strip.bitmap(width, self.args.dither)?;
+        let height = match &dots {
+            Some(dots) => dots.len() / width,
+            None => png_height(path)?,
+        };
+        let label = Label {
+            index: idx,
+            job: strip.label.clone().unwrap_or_else(|| self.name.clone()),
+            length_mm: height as f32 / self.profile.dots_per_mm(),
+        };
+        let heat = Heat {
+            density: strip.density,
+            ..Heat::default()
+        }
+        .or(self.heat);
+        let separator = Separator {
+            cut: strip.cut.or(self.separator.cut),
+            ..self.separator.clone()
+        };
+        let id = StripId {
+            index: idx,
+            scene: match self.args.scene {
+                Some(scene) => Some(scene),
+                None => png_text(path, SCENE_KEYWORD)?.and_then(|text| parse_scene(&text).ok()),
+            },
+        };
+        let placement = self.args.label;
+        let code_at = self.args.code_at;
+
+        // Sent with every strip, in case the printer was reset in between
+        heat.send(&mut printer)?;
+        // A resumed strip already has its margin and header
+        if start == 0 {
+            separator.before(&mut printer)?;
+            if placement.is_some_and(|p| p.header()) {
+                label.header(&mut printer, self.args.label_font, self.command, width)?;
+            }
+            if let Some(codes) = self.codes.filter(|_| code_at.header()) {
+                codes.header(&mut printer, &id, self.command, width)?;
+            }
+        }
+
+        match &dots {
+            Some(dots) => print_bitmap_from(
+                &mut printer,
+                dots,
+                width,
+                self.command,
+                self.pacing.band_rows,
+                start,
+                progress,
+            )?,
+            None => print_png_from(
+                &mut printer,
+                path,
+                width,
+                self.args.dither,
+                self.command,
+                self.pacing.band_rows,
+                start,
+                progress,
+            )?,
+        }
+
+        if let Some(codes) = self.codes.filter(|_| code_at.footer()) {
+            codes.footer(&mut printer, &id, self.command, width)?;
+        }
+        if placement.is_some_and(|p| p.footer()) {
+            label.footer(&mut printer, self.args.label_font, self.command, width)?;
+        }
+        separator.after(&mut printer, self.command, width)
+    }
+}
+
+/// Wait for a printer that went away to come back, for up to `--reconnect` seconds, and
+/// connect to it again. Returns whether it did.
+fn reconnect<T: Transport, F: FnMut(&Status)>(
+    writer: &mut Monitor<T, F>,
+    args: &PrintArgs,
+    sink: &Sink,
+) -> bool {
+    if args.reconnect <= 0. {
+        return false;
+    }
+
+    eprintln!(
+        "{}: printer disconnected; trying to reconnect for {} s",
+        sink, args.reconnect
+    );
+    let backoff = Backoff::new(Duration::from_secs_f64(args.reconnect));
+    let result = writer.reconnect(&backoff, |e, delay| {
+        eprintln!(
+            "{}: {}; trying again in {:.1} s",
+            sink,
+            e,
+            delay.as_secs_f32()
+        )
+    });
+    match result {
+        Ok(()) => {
+            eprintln!("{}: reconnected", sink);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: giving up reconnecting: {}", sink, e);
+            false
+        }
+    }
+}
+
+/// Record progress in the journal. Failing to is only worth a warning; the print goes on.
+fn write_journal(journal: &mut Journal, record: &Record) {
+    if let Err(e) = journal.record(record.clone()) {
+        eprintln!("Writing journal: {:#}", e);
+    }
+}
+
+/// The strips of the files, directories and manifests given, numbered as `strip_paths` does.
+/// Strips from manifests without an index are numbered by their position in the job.
+fn file_strips(args: &PrintArgs, profile: &Profile) -> Result<Vec<Strip>> {
+    let width = profile.dots_per_row;
+    let mut strips = vec![];
+    for (index, path) in strip_paths(&args.files)? {
+        if !is_manifest(&path) {
+            strips.push(Strip {
+                index,
+                transform: args
+                    .transform
+                    .strip_transform(&path, &Transform::default(), width),
+                path,
+                dots: None,
+                copies: args.copies,
+                label: None,
+                density: None,
+                cut: None,
+            });
+            continue;
+        }
+
+        let manifest = Manifest::load(&path)?;
+        manifest.check(profile)?;
+        for entry in manifest.strips {
+            strips.push(Strip {
+                index: entry
+                    .index
+                    .or_else(|| numeric_stem(&entry.path))
+                    .unwrap_or(strips.len()),
+                transform: args
+                    .transform
+                    .strip_transform(&entry.path, &entry.transform, width),
+                path: entry.path,
+                dots: None,
+                copies: entry.copies.unwrap_or(args.copies),
+                label: entry.label.or_else(|| manifest.name.clone()),
+                density: entry.density,
+                cut: entry.cut,
+            });
+        }
+    }
+    Ok(strips)
+}
+
+/// Cut each file given into poster strips, numbering them across all the files
+fn poster_strips(args: &PrintArgs, poster: &Poster, profile: &Profile) -> Result<Vec<Strip>> {
+    poster.check(profile)?;
+
+    let mut strips = vec![];
+    for (_, path) in strip_paths(&args.files)? {
+        let (width, luma) = load_luma(&path).with_context(|| path.display().to_string())?;
+        let (width, luma) = args.transform.transform(&path).apply(width, luma);
+        let dots = poster.strips(width, &luma, profile, args.dither);
+        let length = dots[0].len() / profile.dots_per_row;
+        eprintln!(
+            "Poster {}: {} strips of {:.1} mm",
+            path.display(),
+            dots.len(),
+            length as f32 / profile.dots_per_mm()
+        );
+        for dots in dots {
+            strips.push(Strip {
+                index: strips.len(),
+                path: path.clone(),
+                dots: Some(dots),
+                transform: Transform::default(),
+                copies: args.copies,
+                label: None,
+                density: None,
+                cut: None,
+            });
+        }
+    }
+    Ok(strips)
+}
+
+/// Decode and dither a strip without printing it, returning its height in rows
+fn check_png(path: &Path, width: usize, dither: Dither) -> print::error::Result<usize> {
+    let rows = PngRows::open(path, width, dither)?;
+    let height = rows.height();
+    for row in rows {
+        row?;
+    }
+    Ok(height)
+}
+
+/// Fail the job if any strips were skipped, listing them so they can be reprinted
+fn check_failed(failed: &[usize], total: usize) -> Result<()> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    let indices: Vec<String> = failed.iter().map(|idx| idx.to_string()).collect();
+    bail!(
+        "{} of {} strips failed: {}",
+        failed.len(),
+        total,
+        indices.join(", ")
+    )
+}
+
+/// Default job name: the name of the first directory or file given, or the directory of a
+/// manifest
+fn job_name(paths: &[PathBuf]) -> String {
+    paths
+        .first()
+        .and_then(|path| {
+            let path = if path.is_dir() {
+                path.canonicalize().ok()?
+            } else if is_manifest(path) {
+                path.canonicalize().ok()?.parent()?.to_path_buf()
+            } else {
+                path.clone()
+            };
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+}
+
+/// Expand directories into their PNGs in numeric order, and index every strip
+fn strip_paths(paths: &[PathBuf]) -> Result<Vec<(usize, PathBuf)>> {
+    let mut files = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut pngs = vec![];
+        for entry in std::fs::read_dir(path).with_context(|| path.display().to_string())? {
+            let entry = entry?.path();
+            if entry
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            {
+                pngs.push(entry);
+            }
+        }
+        pngs.sort_by_key(|p| (numeric_stem(p), p.clone()));
+        files.extend(pngs);
+    }
+
+    Ok(files
+        .into_iter()
+        .enumerate()
+        .map(|(pos, path)| (numeric_stem(&path).unwrap_or(pos), path))
+        .collect())
+}
+
+/// Number in a file name like `57.png`
+fn numeric_stem(path: &Path) -> Option<usize> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// `print preview FILES...`
+fn preview(args: PreviewArgs, profile: &Profile) -> Result<()> {
+    let width = profile.dots_per_row;
+    let env_size = |name: &str, default: usize| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let columns = args.columns.unwrap_or_else(|| env_size("COLUMNS", 80));
+    // Leave a line for the prompt
+    let lines = args.lines.unwrap_or_else(|| env_size("LINES", 24)).max(2) - 1;
+
+    let strips = strip_paths(&args.files)?;
+    if strips.is_empty() {
+        bail!("Nothing to preview");
+    }
+
+    let mut total_rows = 0;
+    for (idx, path) in &strips {
+        let bitmap =
+            load_png(path, width, args.dither).with_context(|| path.display().to_string())?;
+        let rows = bitmap.len() / width;
+        let black = bitmap.iter().filter(|&&b| b).count();
+        total_rows += rows;
+
+        println!("Strip {}: {}", idx, path.display());
+        let text = render(&bitmap, width, columns, args.style);
+        for (i, chunk) in text.chunks(lines).enumerate() {
+            for line in chunk {
+                println!("{}", line);
+            }
+
+            let shown = (i + 1) * lines;
+            if args.scroll && shown < text.len() {
+                eprint!(
+                    "-- {:.0}% -- enter for more, q to skip",
+                    100. * shown as f32 / text.len() as f32
+                );
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if answer.trim() == "q" {
+                    break;
                 }
             }
         }
-        writeln!(f)?;
+
+        println!(
+            "{} rows, {:.1} mm, {:.1}% black, about {} to print",
+            rows,
+            rows as f32 / profile.dots_per_mm(),
+            100. * black as f32 / bitmap.len().max(1) as f32,
+            format_duration(profile.print_time(rows))
+        );
+        println!();
+    }
+
+    if strips.len() > 1 {
+        println!(
+            "{} strips, {:.1} mm, about {} to print",
+            strips.len(),
+            total_rows as f32 / profile.dots_per_mm(),
+            format_duration(profile.print_time(total_rows))
+        );
+    }
+
+    Ok(())
+}
+
+/// Duration in seconds, minutes and seconds, or hours and minutes, for estimates
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f32().round() as u64;
+    if secs < 60 {
+        format!("{:.1} s", duration.as_secs_f32())
+    } else if secs < 3600 {
+        format!("{} min {} s", secs / 60, secs % 60)
+    } else {
+        let mins = (secs as f32 / 60.).round() as u64;
+        format!("{} h {} min", mins / 60, mins % 60)
+    }
+}
+
+/// `print calibrate`
+fn calibrate(args: CalibrateArgs, profile: &Profile, device: Option<&UsbSelector>) -> Result<()> {
+    let command = profile.raster_command(args.raster)?;
+    let heat = args.heat.heat().or(profile.heat);
+    heat.check()?;
+    let pacing = args.pacing.pacing().or(profile.pacing);
+    pacing.check()?;
+
+    let width = profile.dots_per_row;
+    let page = test_page(profile, &heat)?;
+    eprintln!(
+        "Test page: {} x {} dots, {:.1} mm",
+        width,
+        page.len() / width,
+        (page.len() / width) as f32 / profile.dots_per_mm()
+    );
+
+    if let Some(path) = &args.save {
+        save_bitmap_png(path, width, &page).with_context(|| path.display().to_string())?;
     }
+
+    let mut ctx = None;
+    let printer = args.output.clone().or_device(device).open(
+        &mut ctx,
+        width,
+        Duration::from_secs_f64(args.timeout),
+    )?;
+    let mut writer = Monitor::new(pacing.pace(printer), profile.status, |status| {
+        eprintln!(
+            "Printer stopped: {}. Waiting for it to be ready...",
+            status.describe()
+        )
+    })
+    .poll_every(pacing.poll_every());
+    heat.send(&mut writer)?;
+    print_bitmap_from(
+        &mut writer,
+        &page,
+        width,
+        command,
+        pacing.band_rows,
+        0,
+        |_| {},
+    )?;
+    writer.flush()?;
+
     Ok(())
 }
+
+/// `print serve`
+fn serve(args: ServeArgs, profile: &Profile, device: Option<&UsbSelector>) -> Result<()> {
+    let command = profile.raster_command(args.raster)?;
+    if let Some(cut) = args.cut {
+        ensure!(
+            profile.cutter,
+            "Printer profile \"{}\" has no cutter for a {} cut",
+            profile.name,
+            cut.name()
+        );
+    }
+    let heat = args.heat.heat().or(profile.heat);
+    heat.check()?;
+    let pacing = args.pacing.pacing().or(profile.pacing);
+    pacing.check()?;
+
+    let settings = Settings {
+        width: profile.dots_per_row,
+        dots_per_mm: profile.dots_per_mm(),
+        command,
+        separator: Separator {
+            margin: profile.mm_to_dots(args.margin),
+            feed: profile.mm_to_dots(args.feed),
+            cut_line: args.cut_line,
+            cut: args.cut,
+        },
+        heat,
+        band_rows: pacing.band_rows,
+        label: args.label,
+        label_font: args.label_font,
+        reconnect: (args.reconnect > 0.)
+            .then(|| Backoff::new(Duration::from_secs_f64(args.reconnect))),
+    };
+    let uploads = Uploads {
+        profile: profile.clone(),
+        dither: args.dither,
+        copies: args.copies,
+        dir: args.dir.clone(),
+    };
+
+    // The printer is opened once and kept, so nothing else can take it while jobs wait
+    let mut ctx = None;
+    let printer = args.output.clone().or_device(device).open(
+        &mut ctx,
+        profile.dots_per_row,
+        Duration::from_secs_f64(args.timeout),
+    )?;
+    let server = args.listen.bind()?;
+    eprintln!("Listening on {}", args.listen);
+
+    let queue = Queue::new();
+    let query = if args.no_status { None } else { profile.status };
+    thread::scope(|scope| {
+        scope.spawn(|| print::serve::serve(&server, &queue, &uploads));
+
+        let writer = Monitor::new(queue.cancellable(pacing.pace(printer)), query, |status| {
+            eprintln!(
+                "Printer stopped: {}. Waiting for it to be ready...",
+                status.describe()
+            );
+            queue.printer_stopped(status);
+        })
+        .poll_every(pacing.poll_every());
+        queue.run(writer, &settings)
+    })
+}
+
+/// `print emulate INPUT OUTPUT.png`
+fn emulate(args: EmulateArgs, profile: &Profile) -> Result<()> {
+    let EmulateArgs { input, output } = args;
+
+    let bytes = if input == "-" {
+        let mut buf = vec![];
+        std::io::stdin().read_to_end(&mut buf)?;
+        buf
+    } else {
+        std::fs::read(&input).context(input.clone())?
+    };
+
+    let emu = Emulator::decode(profile.dots_per_row, &bytes).context(input.clone())?;
+    emu.save_png(&output)
+        .with_context(|| output.display().to_string())?;
+    eprintln!(
+        "Rendered {} x {} dots, {} cuts",
+        emu.width(),
+        emu.height(),
+        emu.cuts().len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use print::{profile::builtin_profiles, save_bitmap_png};
+
+    use super::*;
+
+    /// Rows per test strip: two `ESC *` bands
+    const HEIGHT: usize = 48;
+
+    fn profile() -> Profile {
+        builtin_profiles().remove(0)
+    }
+
+    /// Test strip `idx`, a diagonal pattern that differs from strip to strip
+    fn strip(idx: usize) -> Vec<bool> {
+        let width = profile().dots_per_row;
+        (0..width * HEIGHT)
+            .map(|i| (i % width + i / width + 5 * idx).is_multiple_of(11))
+            .collect()
+    }
+
+    /// A directory for test `name` with `count` strips in `strips/`
+    fn job_dir(name: &str, count: usize) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("print-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("strips")).unwrap();
+        for idx in 0..count {
+            let path = dir.join("strips").join(format!("{}.png", idx));
+            save_bitmap_png(path, profile().dots_per_row, &strip(idx)).unwrap();
+        }
+        dir
+    }
+
+    /// `print ARGS... DIR/strips`, journalling to `DIR/journal.ron`
+    fn print_job(dir: &Path, args: &[String]) -> Result<()> {
+        let mut argv = vec!["print".to_string()];
+        argv.push(format!("--journal={}", dir.join("journal.ron").display()));
+        argv.extend_from_slice(args);
+        argv.push(dir.join("strips").display().to_string());
+        let cli = Cli::try_parse_from(argv).unwrap();
+        print(cli.print, &profile(), None)
+    }
+
+    /// `--output` for a mock printer saving its paper to `DIR/NAME`, following `script`
+    fn mock(dir: &Path, name: &str, script: &str) -> String {
+        let mut sink = format!("--output=mock:{}", dir.join(name).display());
+        if !script.is_empty() {
+            sink = format!("{},{}", sink, script);
+        }
+        sink
+    }
+
+    /// Paper saved by a mock printer, cut into strips
+    fn printed(path: &Path) -> Vec<Vec<bool>> {
+        let width = profile().dots_per_row;
+        let (w, luma) = load_luma(path).unwrap();
+        assert_eq!(w, width);
+        let paper: Vec<bool> = luma.iter().map(|&l| l < 0.5).collect();
+        paper.chunks(width * HEIGHT).map(<[bool]>::to_vec).collect()
+    }
+
+    #[test]
+    fn durations() {
+        for (secs, text) in [
+            (0., "0.0 s"),
+            (12.34, "12.3 s"),
+            (59.4, "59.4 s"),
+            (59.6, "1 min 0 s"),
+            (61., "1 min 1 s"),
+            (3599.4, "59 min 59 s"),
+            (3599.6, "1 h 0 min"),
+            (3600. + 29. * 60. + 29., "1 h 29 min"),
+            (3600. + 29. * 60. + 31., "1 h 30 min"),
+            (10. * 3600., "10 h 0 min"),
+        ] {
+            assert_eq!(format_duration(Duration::from_secs_f32(secs)), text);
+        }
+    }
+
+    #[test]
+    fn reconnecting_prints_the_same_paper() {
+        let dir = job_dir("reconnect", 3);
+        // Unplugged at the third band, the first of the second strip, and back at the first try
+        let args = [
+            "--batch".to_string(),
+            "--reconnect=5".into(),
+            mock(&dir, "mock.png", "disconnect@3"),
+        ];
+        let result = print_job(&dir, &args);
+        let paper = printed(&dir.join("mock.png"));
+        let journal = Journal::open(dir.join("journal.ron")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert!(paper == [strip(0), strip(1), strip(2)]);
+        for idx in 0..3 {
+            assert!(journal.get(idx, 0).unwrap().finished.is_some());
+        }
+    }
+
+    #[test]
+    fn reconnecting_without_status_prints_the_same_paper() {
+        let dir = job_dir("reconnect-no-status", 3);
+        // Never polled, so unplugged at the third write instead
+        let args = [
+            "--batch".to_string(),
+            "--no-status".into(),
+            "--reconnect=5".into(),
+            mock(&dir, "mock.png", "disconnect@3"),
+        ];
+        let result = print_job(&dir, &args);
+        let paper = printed(&dir.join("mock.png"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert!(paper == [strip(0), strip(1), strip(2)]);
+    }
+
+    #[test]
+    fn printers_that_keep_going_away_fail_the_strip() {
+        let dir = job_dir("unplugged", 2);
+        // Gone again straight after reconnecting, before taking another band
+        let args = [
+            "--batch".to_string(),
+            "--reconnect=5".into(),
+            mock(&dir, "mock.png", "disconnect@2,disconnect@3"),
+        ];
+        let result = print_job(&dir, &args);
+        let paper = printed(&dir.join("mock.png")).concat();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Strip 0 is skipped after its first band, and the next prints
+        assert!(result.is_err());
+        assert_eq!(paper.len(), strip(0).len() / 2 + strip(1).len());
+        assert!(paper.ends_with(&strip(1)));
+    }
+
+    /// Three strips, the second of them not a PNG
+    fn job_with_bad_file(name: &str) -> PathBuf {
+        let dir = job_dir(name, 3);
+        fs::write(dir.join("strips").join("1.png"), "not a PNG").unwrap();
+        dir
+    }
+
+    /// Strips finished according to the journal
+    fn finished(dir: &Path, count: usize) -> Vec<usize> {
+        let journal = Journal::open(dir.join("journal.ron")).unwrap();
+        (0..count)
+            .filter(|&idx| journal.get(idx, 0).is_some_and(|r| r.finished.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn skip_moves_on_to_the_next_strip() {
+        let dir = job_with_bad_file("skip");
+        // The printer fails during the first strip, and the second is a bad file
+        let args = [
+            "--batch".to_string(),
+            "--on-error=skip".into(),
+            mock(&dir, "mock.png", "fail@1"),
+        ];
+        let result = print_job(&dir, &args);
+        let paper = printed(&dir.join("mock.png"));
+        let finished = finished(&dir, 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "2 of 3 strips failed: 0, 1"
+        );
+        assert!(paper == [strip(2)]);
+        assert_eq!(finished, [2]);
+    }
+
+    #[test]
+    fn abort_stops() {
+        let dir = job_with_bad_file("abort");
+        let args = [
+            "--batch".to_string(),
+            "--on-error=abort".into(),
+            mock(&dir, "mock.png", ""),
+        ];
+        let result = print_job(&dir, &args);
+        let paper = printed(&dir.join("mock.png"));
+        let finished = finished(&dir, 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(paper == [strip(0)]);
+        assert_eq!(finished, [0]);
+    }
+
+    #[test]
+    fn retry_retries_printer_errors_only() {
+        let dir = job_with_bad_file("retry");
+        let args = [
+            "--batch".to_string(),
+            "--on-error=retry".into(),
+            "--attempts=3".into(),
+            mock(&dir, "mock.png", "fail@1"),
+        ];
+        let start = Instant::now();
+        let result = print_job(&dir, &args);
+        let elapsed = start.elapsed();
+        let paper = printed(&dir.join("mock.png"));
+        let finished = finished(&dir, 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The first strip prints on the second attempt, and the bad file is skipped without
+        // waiting to try it again
+        assert_eq!(result.unwrap_err().to_string(), "1 of 3 strips failed: 1");
+        assert!(paper == [strip(0), strip(2)]);
+        assert_eq!(finished, [0, 2]);
+        assert!(elapsed < RETRY_DELAY * 2, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn unfinished_journals_need_resume_or_fresh() {
+        let dir = job_dir("fresh", 2);
+        let aborted = [
+            "--batch".to_string(),
+            "--on-error=abort".into(),
+            mock(&dir, "a.png", "fail@3"),
+        ];
+        assert!(print_job(&dir, &aborted).is_err());
+
+        // Not replaced unless asked to
+        let again = ["--batch".to_string(), mock(&dir, "b.png", "")];
+        let refused = print_job(&dir, &again);
+        let unfinished = Journal::unfinished(dir.join("journal.ron")).unwrap();
+
+        let fresh = [
+            "--batch".to_string(),
+            "--fresh".into(),
+            mock(&dir, "c.png", ""),
+        ];
+        let result = print_job(&dir, &fresh);
+        let paper = printed(&dir.join("c.png"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(refused
+            .unwrap_err()
+            .to_string()
+            .contains("unfinished strips: 1"));
+        assert_eq!(unfinished.len(), 1);
+        result.unwrap();
+        assert!(paper == [strip(0), strip(1)]);
+    }
+
+    #[test]
+    fn failed_printers_leave_their_strips_to_the_others() {
+        let dir = job_dir("parallel", 6);
+        let sinks = [
+            mock(&dir, "a.png", "disconnect@2"),
+            mock(&dir, "b.png", ""),
+            mock(&dir, "c.png", ""),
+        ];
+        let mut args = vec!["--reconnect=0".to_string()];
+        args.extend_from_slice(&sinks);
+        print_job(&dir, &args).unwrap();
+
+        // The first printer went away part way through its first strip
+        let a = printed(&dir.join("a.png"));
+        assert!(a.len() == 1 && a[0].len() < strip(0).len());
+
+        // The others printed every strip once between them
+        let mut printers = HashMap::new();
+        for (sink, mock) in sinks.iter().zip(["a.png", "b.png", "c.png"]).skip(1) {
+            for paper in printed(&dir.join(mock)) {
+                let idx = (0..6).find(|&idx| paper == strip(idx)).unwrap();
+                let sink = sink.trim_start_matches("--output=").to_string();
+                assert_eq!(printers.insert(idx, sink), None, "strip {}", idx);
+            }
+        }
+        assert_eq!(printers.len(), 6);
+
+        // And the journal says which printed each
+        let journal = Journal::open(dir.join("journal.ron")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        for (idx, sink) in printers {
+            let record = journal.get(idx, 0).unwrap();
+            assert!(record.finished.is_some(), "strip {}", idx);
+            assert_eq!(record.printer.as_ref(), Some(&sink), "strip {}", idx);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{heat::Heat, profile::Profile, separator::Cut, transform::Transform};
+
+/// Manifest strip_gui writes next to the strips it saves
+pub use strip_common::manifest::MANIFEST_NAME;
+
+/// A print job listing its strips and how to print each, as RON, or as JSON if the file name ends
+/// in `.json`. Anything a strip leaves out comes from the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Manifest {
+    /// Job name for labels
+    pub name: Option<String>,
+    pub strips: Vec<Entry>,
+}
+
+/// One strip of a manifest
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Entry {
+    /// PNG to print, relative to the manifest
+    pub path: PathBuf,
+    /// Index shown when printing; defaults to the file's number, or its position in the job
+    pub index: Option<usize>,
+    /// Copies to print
+    pub copies: Option<usize>,
+    /// Rotation, mirroring and inversion, before the job's, e.g. `"rotate-90,invert"`
+    pub transform: Transform,
+    /// Text printed on the label in place of the job name
+    pub label: Option<String>,
+    /// Print density in percent
+    pub density: Option<u32>,
+    /// Cut after the strip: `Full` or `Partial`
+    pub cut: Option<Cut>,
+}
+
+impl Manifest {
+    /// Read a manifest, making strip paths relative to its directory
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let f = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::read(f, is_json(path), dir).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    /// Read a manifest in JSON or RON from `reader`, making strip paths relative to `dir`
+    pub fn read(reader: impl Read, json: bool, dir: &Path) -> Result<Self> {
+        let mut manifest: Self = if json {
+            serde_json::from_reader(reader)?
+        } else {
+            ron::de::from_reader(reader)?
+        };
+
+        for entry in &mut manifest.strips {
+            entry.path = dir.join(&entry.path);
+        }
+        Ok(manifest)
+    }
+
+    /// Check that every strip's settings work on `profile`
+    pub fn check(&self, profile: &Profile) -> Result<()> {
+        for entry in &self.strips {
+            let strip = || format!("Strip {}", entry.path.display());
+            Heat {
+                density: entry.density,
+                ..Heat::default()
+            }
+            .check()
+            .with_context(strip)?;
+            if let Some(cut) = entry.cut {
+                ensure!(
+                    profile.cutter,
+                    "{}: printer profile \"{}\" has no cutter for a {} cut",
+                    strip(),
+                    profile.name,
+                    cut.name()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` names a manifest rather than a strip, from its extension
+pub fn is_manifest(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    is_json(path)
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ron"))
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_gui_manifests_load() {
+        let dir = std::env::temp_dir().join(format!("print-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let written = strip_common::manifest::Manifest::new(Some("collage".into()), 3);
+        written.save(&dir).unwrap();
+
+        let manifest = Manifest::load(dir.join(MANIFEST_NAME));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let manifest = manifest.unwrap();
+
+        assert_eq!(manifest.name.as_deref(), Some("collage"));
+        let expected: Vec<Entry> = (0..3)
+            .map(|idx| Entry {
+                path: dir.join(format!("{}.png", idx)),
+                index: Some(idx),
+                ..Entry::default()
+            })
+            .collect();
+        assert_eq!(manifest.strips, expected);
+    }
+
+    #[test]
+    fn entries_take_their_own_settings() {
+        let text = r#"(strips: [(path: "a.png", copies: Some(2), transform: "invert", cut: Some(Partial))])"#;
+        let manifest = Manifest::read(text.as_bytes(), false, Path::new("job")).unwrap();
+        let entry = &manifest.strips[0];
+        assert_eq!(entry.path, Path::new("job/a.png"));
+        assert_eq!(entry.copies, Some(2));
+        assert_eq!(entry.transform.to_string(), "invert");
+        assert_eq!(entry.cut, Some(Cut::Partial));
+        assert_eq!(manifest.name, None);
+
+        let json = r#"{"name": "x", "strips": [{"path": "b.png", "density": 120}]}"#;
+        let manifest = Manifest::read(json.as_bytes(), true, Path::new("")).unwrap();
+        assert_eq!(manifest.strips[0].density, Some(120));
+    }
+
+    #[test]
+    fn manifests_are_known_by_extension() {
+        assert!(is_manifest("job.ron"));
+        assert!(is_manifest("JOB.JSON"));
+        assert!(!is_manifest("0.png"));
+        assert!(!is_manifest("strips"));
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    emulator::Emulator,
+    status::{Status, Transport, DLE_EOT},
+};
+
+/// Printer state a mock printer can be scripted into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockState {
+    PaperOut,
+    CoverOpen,
+    NearEnd,
+    Error,
+    /// Unplugged: writes and status requests fail until the job reconnects
+    Disconnect,
+    /// A status request fails, losing what was sent since the last one, without unplugging
+    Fail,
+}
+
+impl MockState {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PaperOut => "paper-out",
+            Self::CoverOpen => "cover-open",
+            Self::NearEnd => "near-end",
+            Self::Error => "error",
+            Self::Disconnect => "disconnect",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+impl FromStr for MockState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "paper-out" => Self::PaperOut,
+            "cover-open" => Self::CoverOpen,
+            "near-end" => Self::NearEnd,
+            "error" => Self::Error,
+            "disconnect" => Self::Disconnect,
+            "fail" => Self::Fail,
+            _ => bail!(
+                "Unknown mock printer state \"{}\"; expected paper-out, cover-open, near-end, error, disconnect or fail",
+                s
+            ),
+        })
+    }
+}
+
+/// One step of a mock printer script: `state` is reported from status poll `at` (counting from
+/// 1) for `count` polls. Jobs poll after every band, and every second while paused.
+///
+/// A `Disconnect` instead unplugs the printer at poll `at`, losing the band just sent, and
+/// `count` is the reconnection attempt that finds it again. A printer that hasn't been polled
+/// yet, e.g. with `--no-status`, counts writes instead: it is unplugged at write `at`, which
+/// fails, after printing everything before it. A `Fail` fails polls `at` to `at + count - 1`
+/// without unplugging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MockEvent {
+    pub state: MockState,
+    pub at: usize,
+    pub count: usize,
+}
+
+impl FromStr for MockEvent {
+    type Err = anyhow::Error;
+
+    /// Parses `STATE@N` or `STATE@NxCOUNT`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some((state, when)) = s.split_once('@') else {
+            bail!("Mock printer event \"{}\" should be STATE@N[xCOUNT]", s);
+        };
+        let (at, count) = when.split_once('x').unwrap_or((when, "1"));
+
+        Ok(Self {
+            state: state.parse()?,
+            at: at
+                .parse()
+                .with_context(|| format!("Mock printer event \"{}\"", s))?,
+            count: count
+                .parse()
+                .with_context(|| format!("Mock printer event \"{}\"", s))?,
+        })
+    }
+}
+
+/// Emulated printer that answers status requests from a script, for trying out status handling
+/// without a real printer running out of paper. Data is only printed if the printer is ready at
+/// the status poll following it; anything sent while it is stopped is lost, as on paper that
+/// isn't there. The paper is saved as a PNG when the printer is dropped.
+pub struct MockPrinter {
+    emulator: Emulator,
+    script: Vec<MockEvent>,
+    /// Status polls answered so far
+    polls: usize,
+    /// Writes taken before the first status poll
+    writes: usize,
+    /// Data received since the last status poll
+    pending: Vec<u8>,
+    /// Where to save the paper
+    path: Option<PathBuf>,
+    /// While unplugged, reconnection attempts left before the printer is back
+    unplugged: Option<usize>,
+}
+
+impl MockPrinter {
+    /// Printer `width` dots wide
+    pub fn new(width: usize, script: Vec<MockEvent>, path: Option<PathBuf>) -> Self {
+        Self {
+            emulator: Emulator::new(width),
+            script,
+            polls: 0,
+            writes: 0,
+            pending: vec![],
+            path,
+            unplugged: None,
+        }
+    }
+
+    /// Everything printed so far
+    pub fn emulator(&mut self) -> io::Result<&Emulator> {
+        self.commit(true)?;
+        Ok(&self.emulator)
+    }
+
+    /// Status polls answered so far
+    pub fn polls(&self) -> usize {
+        self.polls
+    }
+
+    /// Status at the current poll
+    fn status(&self) -> Status {
+        let mut status = Status::default();
+        for event in &self.script {
+            if (event.at..event.at + event.count).contains(&self.polls) {
+                match event.state {
+                    MockState::PaperOut => status.paper_out = true,
+                    MockState::CoverOpen => status.cover_open = true,
+                    MockState::NearEnd => status.paper_near_end = true,
+                    MockState::Error => status.error = true,
+                    MockState::Disconnect | MockState::Fail => {}
+                }
+            }
+        }
+        status
+    }
+
+    /// Whether the script fails the current poll
+    fn fails(&self) -> bool {
+        self.script.iter().any(|event| {
+            event.state == MockState::Fail
+                && (event.at..event.at + event.count).contains(&self.polls)
+        })
+    }
+
+    /// Unplug the printer if the script says so at poll or write `at`, dropping the pending
+    /// data unless `print`
+    fn unplug(&mut self, at: usize, print: bool) -> io::Result<bool> {
+        let event = self
+            .script
+            .iter()
+            .find(|event| event.state == MockState::Disconnect && event.at == at);
+        let Some(event) = event else {
+            return Ok(false);
+        };
+        self.unplugged = Some(event.count);
+        self.commit(print)?;
+        Ok(true)
+    }
+
+    /// Print or drop the pending data
+    fn commit(&mut self, print: bool) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if print {
+            self.emulator.write_all(&pending)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error for anything sent to an unplugged mock printer
+fn unplugged() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "Mock printer unplugged")
+}
+
+impl Write for MockPrinter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.unplugged.is_some() {
+            return Err(unplugged());
+        }
+        if self.polls == 0 {
+            self.writes += 1;
+            if self.unplug(self.writes, self.status().ready())? {
+                return Err(unplugged());
+            }
+        }
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MockPrinter {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        if self.unplugged.is_some() {
+            return Err(unplugged());
+        }
+
+        // A status poll is `DLE EOT 2` then `DLE EOT 4`, or a single `GS r 1`
+        if request != [DLE_EOT, &[4]].concat() {
+            self.polls += 1;
+            if self.unplug(self.polls, false)? {
+                return Err(unplugged());
+            }
+            if self.fails() {
+                self.commit(false)?;
+                return Err(io::Error::other("Mock printer failed"));
+            }
+            let ready = self.status().ready();
+            self.commit(ready)?;
+        }
+        let status = self.status();
+
+        // Fixed bits, then the flags in the layout of the requested status
+        let bit = |set: bool, mask: u8| if set { mask } else { 0 };
+        let reply = match request {
+            [0x10, 0x04, 2] => {
+                0x12 | bit(status.cover_open, 0x04)
+                    | bit(status.paper_out, 0x20)
+                    | bit(status.error, 0x40)
+            }
+            [0x10, 0x04, 4] => {
+                0x12 | bit(status.paper_near_end, 0x0c) | bit(status.paper_out, 0x60)
+            }
+            [0x1d, b'r', 1] => bit(status.paper_near_end, 0x03) | bit(status.paper_out, 0x0c),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unsupported status request {:02x?}", request),
+                ))
+            }
+        };
+
+        Ok(Some(reply))
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        match self.unplugged {
+            Some(attempts) if attempts > 1 => {
+                self.unplugged = Some(attempts - 1);
+                Err(unplugged())
+            }
+            _ => {
+                self.unplugged = None;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for MockPrinter {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+
+        let result = self
+            .commit(true)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| self.emulator.save_png(&path));
+        if let Err(e) = result {
+            eprintln!("Saving {}: {:#}", path.display(), e);
+        }
+    }
+}
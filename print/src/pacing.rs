@@ -0,0 +1,229 @@
+use std::{
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+
+use crate::status::Transport;
+
+/// A rate-limited write sends at most this fraction of a second's worth of bytes at once
+const CHUNKS_PER_SECOND: usize = 20;
+
+/// How fast to feed a printer whose buffer overflows when sent to as fast as it takes data,
+/// which garbles output or skips bands. Anything left unset sends as fast as possible.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Pacing {
+    /// Most bytes sent per second
+    pub max_rate: Option<u32>,
+    /// Bands sent between status polls, which wait for the printer to be ready (`GS r` also
+    /// waits for its buffer to be printed). 1 polls after every band.
+    pub poll_every: Option<usize>,
+    /// Rows per `GS v 0` or `GS ( L` image; bit images always go a line (24 or 8 dots) at a time
+    pub band_rows: Option<usize>,
+}
+
+impl Pacing {
+    /// These settings, with any unset ones taken from `base`
+    pub fn or(self, base: Pacing) -> Pacing {
+        Pacing {
+            max_rate: self.max_rate.or(base.max_rate),
+            poll_every: self.poll_every.or(base.poll_every),
+            band_rows: self.band_rows.or(base.band_rows),
+        }
+    }
+
+    /// Check that every setting is in range
+    pub fn check(&self) -> anyhow::Result<()> {
+        if let Some(rate) = self.max_rate {
+            ensure!(rate > 0, "Maximum rate must be at least 1 byte per second");
+        }
+        if let Some(bands) = self.poll_every {
+            ensure!(bands > 0, "Bands between status polls must be at least 1");
+        }
+        if let Some(rows) = self.band_rows {
+            ensure!(
+                (1..=u16::MAX as usize).contains(&rows),
+                "Band rows must be from 1 to {}, got {}",
+                u16::MAX,
+                rows
+            );
+        }
+        Ok(())
+    }
+
+    /// Bands between status polls
+    pub fn poll_every(&self) -> usize {
+        self.poll_every.unwrap_or(1)
+    }
+
+    /// Wrap `transport` to send no faster than `max_rate`
+    pub fn pace<T: Transport>(&self, transport: T) -> Paced<T> {
+        Paced {
+            transport,
+            max_rate: self.max_rate,
+            since: Instant::now(),
+            sent: 0,
+        }
+    }
+}
+
+/// Transport sending at most `max_rate` bytes per second on average. Time spent idle, e.g.
+/// waiting for the next strip, doesn't earn a burst.
+pub struct Paced<T: Transport> {
+    transport: T,
+    max_rate: Option<u32>,
+    /// Start of the current run of writes
+    since: Instant,
+    /// Bytes written since `since`
+    sent: u64,
+}
+
+impl<T: Transport> Write for Paced<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(rate) = self.max_rate else {
+            return self.transport.write(buf);
+        };
+
+        // When the bytes sent so far should have finished going
+        let due = Duration::from_secs_f64(self.sent as f64 / rate as f64);
+        let elapsed = self.since.elapsed();
+        if elapsed > due {
+            self.since = Instant::now();
+            self.sent = 0;
+        } else {
+            thread::sleep(due - elapsed);
+        }
+
+        let chunk = (rate as usize / CHUNKS_PER_SECOND).max(1);
+        let n = self.transport.write(&buf[..buf.len().min(chunk)])?;
+        self.sent += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+impl<T: Transport> Transport for Paced<T> {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        self.transport.transact(request)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        self.transport.reconnect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transport recording the size of every write
+    #[derive(Default)]
+    struct Writes(Vec<usize>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Writes {}
+
+    fn paced(max_rate: Option<u32>) -> Paced<Writes> {
+        Pacing {
+            max_rate,
+            ..Pacing::default()
+        }
+        .pace(Writes::default())
+    }
+
+    /// Seconds taken to send `bytes` through `paced`
+    fn send(paced: &mut Paced<Writes>, bytes: usize) -> f32 {
+        let start = Instant::now();
+        paced.write_all(&vec![0; bytes]).unwrap();
+        start.elapsed().as_secs_f32()
+    }
+
+    #[test]
+    fn unpaced_writes_go_straight_through() {
+        let mut paced = paced(None);
+        assert!(send(&mut paced, 100_000) < 0.5);
+        assert_eq!(paced.transport.0, [100_000]);
+    }
+
+    #[test]
+    fn writes_keep_to_the_rate() {
+        // 2000 bytes at 4000 a second, in 200 byte chunks: the last is due after 0.45 s
+        let mut paced = paced(Some(4000));
+        let secs = send(&mut paced, 2000);
+        assert!((0.4..2.).contains(&secs), "{} s", secs);
+        assert_eq!(paced.transport.0, [200; 10]);
+    }
+
+    #[test]
+    fn idling_earns_no_burst() {
+        let mut paced = paced(Some(4000));
+        send(&mut paced, 200);
+        thread::sleep(Duration::from_millis(300));
+        // The last of 1000 bytes is due 0.2 s after the first
+        let secs = send(&mut paced, 1000);
+        assert!((0.15..2.).contains(&secs), "{} s", secs);
+    }
+
+    #[test]
+    fn unset_settings_come_from_the_base() {
+        let base = Pacing {
+            max_rate: Some(1000),
+            poll_every: Some(4),
+            band_rows: Some(24),
+        };
+        let own = Pacing {
+            poll_every: Some(2),
+            ..Pacing::default()
+        };
+        assert_eq!(
+            own.or(base),
+            Pacing {
+                poll_every: Some(2),
+                ..base
+            }
+        );
+        assert_eq!(Pacing::default().poll_every(), 1);
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        assert!(Pacing::default().check().is_ok());
+        for bad in [
+            Pacing {
+                max_rate: Some(0),
+                ..Pacing::default()
+            },
+            Pacing {
+                poll_every: Some(0),
+                ..Pacing::default()
+            },
+            Pacing {
+                band_rows: Some(0),
+                ..Pacing::default()
+            },
+            Pacing {
+                band_rows: Some(65536),
+                ..Pacing::default()
+            },
+        ] {
+            assert!(bad.check().is_err(), "{:?}", bad);
+        }
+    }
+}
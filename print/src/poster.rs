@@ -0,0 +1,262 @@
+use anyhow::ensure;
+
+use crate::{dither::Dither, profile::Profile};
+
+/// How to cut an image of any size into strips the width of the printable area, to be laid side
+/// by side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Poster {
+    /// Width of the whole poster, in mm
+    pub width: f32,
+    /// Image repeated at the start of each strip from the end of the one before, in mm, so they
+    /// can overlap when pasted up
+    pub overlap: f32,
+    /// Image left out between strips, in mm, so it stays in proportion across the unprinted
+    /// margins when strips are laid edge to edge
+    pub gutter: f32,
+}
+
+impl Poster {
+    /// Check that the strips advance across the image
+    pub fn check(&self, profile: &Profile) -> anyhow::Result<()> {
+        ensure!(
+            self.width > 0.,
+            "Poster width must be positive, got {} mm",
+            self.width
+        );
+        ensure!(
+            self.overlap >= 0. && self.gutter >= 0.,
+            "Overlap and gutter can't be negative"
+        );
+        ensure!(
+            self.overlap < profile.print_width,
+            "Overlap must be less than the print width of {} mm, got {} mm",
+            profile.print_width,
+            self.overlap
+        );
+        Ok(())
+    }
+
+    /// Image dots from the left edge of one strip to the left edge of the next
+    fn step(&self, profile: &Profile) -> usize {
+        profile.dots_per_row + profile.mm_to_dots(self.gutter) - profile.mm_to_dots(self.overlap)
+    }
+
+    /// Number of strips across a poster `width` dots wide
+    fn count(&self, width: usize, profile: &Profile) -> usize {
+        1 + width
+            .saturating_sub(profile.dots_per_row)
+            .div_ceil(self.step(profile))
+    }
+
+    /// Scale luminance `src_width` pixels per row (see `load_luma`) to the poster width, dither
+    /// it with `dither` and cut it into strips `profile.dots_per_row` wide (true = black), left
+    /// to right. The image is dithered whole, so the dots match where strips overlap; the last
+    /// strip is padded with white.
+    pub fn strips(
+        &self,
+        src_width: usize,
+        luma: &[f32],
+        profile: &Profile,
+        dither: Dither,
+    ) -> Vec<Vec<bool>> {
+        let src_height = luma.len() / src_width;
+
+        let width = profile.mm_to_dots(self.width).max(1);
+        let height = (src_height * width).div_ceil(src_width).max(1);
+        let dots = dither.apply(&resize(luma, src_width, src_height, width, height), width);
+
+        let strip_width = profile.dots_per_row;
+        let step = self.step(profile);
+        (0..self.count(width, profile))
+            .map(|i| {
+                let left = i * step;
+                dots.chunks(width)
+                    .flat_map(|row| (left..left + strip_width).map(|x| x < width && row[x]))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Resample luminance `width` by `height` to `new_width` by `new_height`, averaging the pixels
+/// under each new one when shrinking and interpolating between them when enlarging
+fn resize(
+    luma: &[f32],
+    width: usize,
+    height: usize,
+    new_width: usize,
+    new_height: usize,
+) -> Vec<f32> {
+    let across = weights(width, new_width);
+    let down = weights(height, new_height);
+
+    let rows: Vec<Vec<f32>> = luma
+        .chunks(width)
+        .map(|row| {
+            across
+                .iter()
+                .map(|w| w.iter().map(|&(x, weight)| row[x] * weight).sum())
+                .collect()
+        })
+        .collect();
+
+    down.iter()
+        .flat_map(|w| {
+            (0..new_width).map(|x| w.iter().map(|&(y, weight)| rows[y][x] * weight).sum())
+        })
+        .collect()
+}
+
+/// Source pixels and their weights for each of `new_len` pixels resampled from `len`
+fn weights(len: usize, new_len: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = len as f32 / new_len as f32;
+    (0..new_len)
+        .map(|i| {
+            if scale > 1. {
+                // Box filter over the span this pixel covers, counting partly covered pixels
+                // by how much of them it covers
+                let (start, end) = (i as f32 * scale, (i + 1) as f32 * scale);
+                let (first, last) = (start as usize, (end.ceil() as usize).min(len));
+                (first..last)
+                    .map(|x| {
+                        let cover = (end.min(x as f32 + 1.) - start.max(x as f32)) / scale;
+                        (x, cover)
+                    })
+                    .collect()
+            } else {
+                // Linear interpolation between the two nearest pixel centres
+                let pos = ((i as f32 + 0.5) * scale - 0.5).clamp(0., (len - 1) as f32);
+                let x = pos as usize;
+                let frac = pos - x as f32;
+                if x + 1 < len && frac > 0. {
+                    vec![(x, 1. - frac), (x + 1, frac)]
+                } else {
+                    vec![(x, 1.)]
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::builtin_profiles;
+
+    /// Printer 8 dots across at one dot per mm, so poster sizes in mm are in dots too
+    fn profile() -> Profile {
+        Profile {
+            print_width: 8.,
+            dots_per_row: 8,
+            dpi: 25.4,
+            ..builtin_profiles().remove(0)
+        }
+    }
+
+    /// Whether the synthetic image is black at `x`, `y`: a diagonal pattern, so every column
+    /// and row differs from its neighbours
+    fn black(x: usize, y: usize) -> bool {
+        (x + 2 * y).is_multiple_of(3)
+    }
+
+    /// Image `width` by 4 pixels of `black`
+    fn image(width: usize) -> Vec<f32> {
+        (0..4)
+            .flat_map(|y| (0..width).map(move |x| if black(x, y) { 0. } else { 1. }))
+            .collect()
+    }
+
+    /// Strips of an image `width` pixels wide printed `width` mm wide, so it isn't scaled
+    fn strips(width: usize, overlap: f32, gutter: f32) -> Vec<Vec<bool>> {
+        let poster = Poster {
+            width: width as f32,
+            overlap,
+            gutter,
+        };
+        poster.check(&profile()).unwrap();
+        poster.strips(width, &image(width), &profile(), Dither::Threshold)
+    }
+
+    /// Strip starting at image column `left`, padded with white past column `width`
+    fn expected(left: usize, width: usize) -> Vec<bool> {
+        (0..4)
+            .flat_map(|y| (left..left + 8).map(move |x| x < width && black(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn strips_tile_the_image() {
+        let strips = strips(16, 0., 0.);
+        assert_eq!(strips, [expected(0, 16), expected(8, 16)]);
+    }
+
+    #[test]
+    fn last_strip_is_padded() {
+        let strips = strips(19, 0., 0.);
+        assert_eq!(strips.len(), 3);
+        assert_eq!(strips[2], expected(16, 19));
+        // Only the first 3 columns of the last strip are image
+        assert!(strips[2].chunks(8).all(|row| row[3..].iter().all(|&b| !b)));
+    }
+
+    #[test]
+    fn overlap_repeats_columns() {
+        // Each strip starts 2 columns before the end of the one before: at 0, 6, 12 and 18
+        let strips = strips(21, 2., 0.);
+        let lefts = [0, 6, 12, 18];
+        assert_eq!(strips.len(), lefts.len());
+        for (strip, left) in strips.iter().zip(lefts) {
+            assert_eq!(strip, &expected(left, 21), "strip at {}", left);
+        }
+    }
+
+    #[test]
+    fn gutter_skips_columns() {
+        let strips = strips(21, 0., 1.);
+        assert_eq!(strips, [expected(0, 21), expected(9, 21), expected(18, 21)]);
+    }
+
+    #[test]
+    fn narrow_images_are_one_strip() {
+        assert_eq!(strips(5, 0., 0.), [expected(0, 5)]);
+    }
+
+    #[test]
+    fn images_are_scaled_to_the_poster_width() {
+        let poster = Poster {
+            width: 16.,
+            overlap: 0.,
+            gutter: 0.,
+        };
+        // 4 by 2, black on the left: scaled to 16 by 8, one strip black and the other white
+        let luma = [0., 0., 1., 1., 0., 0., 1., 1.];
+        let strips = poster.strips(4, &luma, &profile(), Dither::Threshold);
+        assert_eq!(strips.len(), 2);
+        assert_eq!(strips[0].len(), 8 * 8);
+
+        assert!(strips[0].iter().all(|&b| b));
+        assert!(strips[1].iter().all(|&b| !b));
+    }
+
+    #[test]
+    fn resizing_averages_and_interpolates() {
+        assert_eq!(resize(&[0., 1., 0., 1.], 4, 1, 2, 1), [0.5, 0.5]);
+        assert_eq!(resize(&[0.25, 0.75], 2, 1, 2, 1), [0.25, 0.75]);
+        assert_eq!(resize(&[0., 1.], 2, 1, 4, 1), [0., 0.25, 0.75, 1.]);
+    }
+
+    #[test]
+    fn checks_reject_strips_that_dont_advance() {
+        let poster = |width, overlap, gutter| Poster {
+            width,
+            overlap,
+            gutter,
+        };
+        assert!(poster(100., 2., 1.).check(&profile()).is_ok());
+        assert!(poster(0., 0., 0.).check(&profile()).is_err());
+        assert!(poster(100., -1., 0.).check(&profile()).is_err());
+        assert!(poster(100., 0., -1.).check(&profile()).is_err());
+        assert!(poster(100., 8., 0.).check(&profile()).is_err());
+    }
+}
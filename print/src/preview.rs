@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::dither::Dither;
+
+/// First braille pattern character; the dots are its low 8 bits
+const BRAILLE_BLANK: u32 = 0x2800;
+/// Bit of each braille dot, by row then column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Characters used to draw a bitmap in the terminal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreviewStyle {
+    /// Braille patterns, 2x4 dots per character
+    #[default]
+    Braille,
+    /// Half blocks, 1x2 dots per character; coarser, but works in more fonts
+    HalfBlock,
+}
+
+impl PreviewStyle {
+    /// Dots per character, across and down
+    fn cell(&self) -> (usize, usize) {
+        match self {
+            Self::Braille => (2, 4),
+            Self::HalfBlock => (1, 2),
+        }
+    }
+}
+
+impl FromStr for PreviewStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "braille" => Self::Braille,
+            "half-block" => Self::HalfBlock,
+            _ => bail!(
+                "Unknown preview style \"{}\"; expected braille or half-block",
+                s
+            ),
+        })
+    }
+}
+
+/// Draw `bitmap` (true = black), `width` dots per row, as lines of text at most `columns`
+/// characters wide. The bitmap is scaled down by a whole factor to fit, and dithered again so
+/// grays stay gray.
+pub fn render(bitmap: &[bool], width: usize, columns: usize, style: PreviewStyle) -> Vec<String> {
+    let (cell_w, cell_h) = style.cell();
+    let scale = width.div_ceil(cell_w * columns.max(1)).max(1);
+    let (dots, w) = downscale(bitmap, width, scale);
+    let h = dots.len() / w.max(1);
+    let black = |x: usize, y: usize| x < w && y < h && dots[y * w + x];
+
+    (0..h.div_ceil(cell_h))
+        .map(|row| {
+            let line: String = (0..w.div_ceil(cell_w))
+                .map(|col| {
+                    let (x, y) = (col * cell_w, row * cell_h);
+                    match style {
+                        PreviewStyle::Braille => {
+                            let mut bits = 0;
+                            for (dy, row_bits) in BRAILLE_DOTS.iter().enumerate() {
+                                for (dx, bit) in row_bits.iter().enumerate() {
+                                    if black(x + dx, y + dy) {
+                                        bits |= bit;
+                                    }
+                                }
+                            }
+                            char::from_u32(BRAILLE_BLANK + bits).unwrap_or(' ')
+                        }
+                        PreviewStyle::HalfBlock => match (black(x, y), black(x, y + 1)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                    }
+                })
+                .collect();
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Shrink a bitmap by `scale` in both directions, returning it and its width
+fn downscale(bitmap: &[bool], width: usize, scale: usize) -> (Vec<bool>, usize) {
+    if scale == 1 {
+        return (bitmap.to_vec(), width);
+    }
+
+    let height = bitmap.len() / width;
+    let (w, h) = (width.div_ceil(scale), height.div_ceil(scale));
+    let mut luma = vec![0.; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let (mut white, mut total) = (0, 0);
+            for sy in y * scale..((y + 1) * scale).min(height) {
+                for sx in x * scale..((x + 1) * scale).min(width) {
+                    total += 1;
+                    if !bitmap[sy * width + sx] {
+                        white += 1;
+                    }
+                }
+            }
+            luma[y * w + x] = white as f32 / total as f32;
+        }
+    }
+    (Dither::FloydSteinberg.apply(&luma, w), w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn braille_is_2_by_4_dots_a_character() {
+        let lines = render(&[true; 10 * 9], 10, 80, PreviewStyle::Braille);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "⣿⣿⣿⣿⣿");
+        assert_eq!(lines[2], "⠉⠉⠉⠉⠉");
+    }
+
+    #[test]
+    fn braille_dots() {
+        // Top left, then one down each column
+        let mut bitmap = vec![false; 2 * 4];
+        bitmap[0] = true;
+        assert_eq!(render(&bitmap, 2, 80, PreviewStyle::Braille), ["⠁"]);
+        bitmap[2 * 3 + 1] = true;
+        assert_eq!(render(&bitmap, 2, 80, PreviewStyle::Braille), ["⢁"]);
+    }
+
+    #[test]
+    fn half_blocks_are_1_by_2_dots_a_character() {
+        // Black top row, then a column at x = 1
+        let bitmap = [
+            true, true, true, //
+            false, true, false, //
+            false, true, false,
+        ];
+        let lines = render(&bitmap, 3, 80, PreviewStyle::HalfBlock);
+        assert_eq!(lines, ["▀█▀", " ▀"]);
+    }
+
+    #[test]
+    fn scales_down_to_fit() {
+        let lines = render(&[true; 384 * 100], 384, 48, PreviewStyle::Braille);
+        // Shrunk by 4 to 96 x 25 dots
+        assert_eq!(lines.len(), 7);
+        assert!(lines.iter().all(|line| line.chars().count() == 48));
+
+        let lines = render(&[true; 384 * 100], 384, 48, PreviewStyle::HalfBlock);
+        // Shrunk by 8 to 48 x 13 dots
+        assert_eq!(lines.len(), 7);
+        assert!(lines.iter().all(|line| line.chars().count() == 48));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use strip_common::profile::PrinterProfile;
+
+use crate::{
+    heat::Heat, pacing::Pacing, raster::RasterCommand, status::StatusQuery, PRINTER_HORIZ_RES,
+};
+
+/// Profiles are read from this file in the working directory when no path is given
+pub const DEFAULT_PROFILES_PATH: &str = "printers.ron";
+
+const MM_PER_INCH: f32 = 25.4;
+
+/// Paper speed of profiles that don't give one, in mm per second
+const DEFAULT_PAPER_SPEED: f32 = 50.;
+
+/// Geometry and capabilities of one printer model
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Name used to select the profile
+    pub name: String,
+    /// Width of the paper roll, in millimeters
+    pub paper_width: f32,
+    /// Width of the printable area, in millimeters
+    pub print_width: f32,
+    /// Dots across the printable area
+    pub dots_per_row: usize,
+    /// Resolution in dots per inch
+    pub dpi: f32,
+    /// Supported raster commands, most preferred first
+    pub raster: Vec<RasterCommand>,
+    /// Whether the printer has an auto-cutter for `GS V`
+    #[serde(default)]
+    pub cutter: bool,
+    /// How to ask the printer for its status between bands; `None` to print blind
+    #[serde(default)]
+    pub status: Option<StatusQuery>,
+    /// Whether the printer draws QR codes itself with `GS ( k`; otherwise they are sent as images
+    #[serde(default)]
+    pub qr: bool,
+    /// Print head heat, density and speed, sent before each strip
+    #[serde(default)]
+    pub heat: Heat,
+    /// Paper speed while printing, in mm per second, for time estimates
+    #[serde(default = "default_paper_speed")]
+    pub paper_speed: f32,
+    /// How fast to send data, for printers whose buffer overflows
+    #[serde(default)]
+    pub pacing: Pacing,
+}
+
+fn default_paper_speed() -> f32 {
+    DEFAULT_PAPER_SPEED
+}
+
+impl Profile {
+    /// Dots per millimeter along the paper
+    pub fn dots_per_mm(&self) -> f32 {
+        self.dpi / MM_PER_INCH
+    }
+
+    /// Preferred raster command, or `requested` if the printer supports it
+    pub fn raster_command(&self, requested: Option<RasterCommand>) -> Result<RasterCommand> {
+        match requested {
+            Some(command) if self.raster.contains(&command) => Ok(command),
+            Some(command) => bail!(
+                "Printer profile \"{}\" does not support {}",
+                self.name,
+                command.name()
+            ),
+            None => self.raster.first().copied().with_context(|| {
+                format!("Printer profile \"{}\" lists no raster commands", self.name)
+            }),
+        }
+    }
+
+    /// Convert a length in millimeters to dots
+    pub fn mm_to_dots(&self, mm: f32) -> usize {
+        (mm * self.dots_per_mm()).round() as usize
+    }
+
+    /// Rough time to print `rows` rows, from the paper speed
+    pub fn print_time(&self, rows: usize) -> Duration {
+        Duration::from_secs_f32(rows as f32 / self.dots_per_mm() / self.paper_speed)
+    }
+
+    /// The geometry strip_gui lays strips out with
+    pub fn printer(&self) -> PrinterProfile {
+        PrinterProfile {
+            name: self.name.clone(),
+            paper_width: self.paper_width,
+            print_width: self.print_width,
+            dots_per_row: self.dots_per_row,
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        builtin_profiles().remove(0)
+    }
+}
+
+/// Profiles for the printers we own, used when there is no config file. strip_gui starts with the
+/// same printers, from `strip_common::profile::builtin_profiles`.
+pub fn builtin_profiles() -> Vec<Profile> {
+    vec![
+        Profile {
+            name: "pos58".into(),
+            paper_width: 58.,
+            print_width: 48.,
+            dots_per_row: PRINTER_HORIZ_RES,
+            dpi: 203.,
+            raster: vec![
+                RasterCommand::BitImage24,
+                RasterCommand::Raster,
+                RasterCommand::Graphics,
+                RasterCommand::BitImage8,
+            ],
+            cutter: false,
+            status: Some(StatusQuery::DleEot),
+            qr: true,
+            heat: Heat::default(),
+            paper_speed: 60.,
+            pacing: Pacing::default(),
+        },
+        Profile {
+            name: "pos80".into(),
+            paper_width: 80.,
+            print_width: 72.,
+            dots_per_row: 576,
+            dpi: 203.,
+            raster: vec![
+                RasterCommand::Raster,
+                RasterCommand::Graphics,
+                RasterCommand::BitImage24,
+                RasterCommand::BitImage8,
+            ],
+            cutter: false,
+            status: Some(StatusQuery::DleEot),
+            qr: true,
+            heat: Heat::default(),
+            paper_speed: 80.,
+            pacing: Pacing::default(),
+        },
+        Profile {
+            name: "pos58-180dpi".into(),
+            paper_width: 58.,
+            print_width: 47.4,
+            dots_per_row: 336,
+            dpi: 180.,
+            raster: vec![RasterCommand::BitImage24, RasterCommand::BitImage8],
+            cutter: false,
+            status: Some(StatusQuery::DleEot),
+            qr: false,
+            heat: Heat::default(),
+            paper_speed: 50.,
+            pacing: Pacing::default(),
+        },
+    ]
+}
+
+/// Load profiles from `path`, or from `DEFAULT_PROFILES_PATH` if it exists, or the built-ins
+pub fn load_profiles(path: Option<&Path>) -> Result<Vec<Profile>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let path = PathBuf::from(DEFAULT_PROFILES_PATH);
+            if !path.exists() {
+                return Ok(builtin_profiles());
+            }
+            path
+        }
+    };
+
+    let f = File::open(&path).with_context(|| format!("Opening {}", path.display()))?;
+    let profiles: Vec<Profile> =
+        ron::de::from_reader(f).with_context(|| format!("Parsing {}", path.display()))?;
+
+    if profiles.is_empty() {
+        bail!("{} contains no profiles", path.display());
+    }
+
+    Ok(profiles)
+}
+
+/// Find a profile by name; the first profile is the default
+pub fn select_profile(profiles: &[Profile], name: Option<&str>) -> Result<Profile> {
+    let Some(name) = name else {
+        return profiles.first().cloned().context("No printer profiles");
+    };
+
+    match profiles.iter().find(|p| p.name == name) {
+        Some(profile) => Ok(profile.clone()),
+        None => {
+            let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+            bail!(
+                "Unknown printer profile \"{}\"; expected one of {}",
+                name,
+                names.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `printers.ron` shipped next to the crate
+    const PRINTERS_RON: &str = include_str!("../printers.ron");
+
+    #[test]
+    fn builtins_match_strip_gui() {
+        let printers: Vec<PrinterProfile> =
+            builtin_profiles().iter().map(Profile::printer).collect();
+        assert_eq!(printers, strip_common::profile::builtin_profiles());
+    }
+
+    #[test]
+    fn printers_ron_loads_in_both() {
+        let profiles: Vec<Profile> = ron::from_str(PRINTERS_RON).unwrap();
+        assert_eq!(profiles, builtin_profiles());
+
+        let printers: Vec<PrinterProfile> = ron::from_str(PRINTERS_RON).unwrap();
+        let expected: Vec<PrinterProfile> = profiles.iter().map(Profile::printer).collect();
+        assert_eq!(printers, expected);
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let text = ron::to_string(&builtin_profiles()).unwrap();
+        let profiles: Vec<Profile> = ron::from_str(&text).unwrap();
+        assert_eq!(profiles, builtin_profiles());
+
+        // strip_gui reads the same file for its printer list
+        let printers: Vec<PrinterProfile> = ron::from_str(&text).unwrap();
+        assert_eq!(printers, strip_common::profile::builtin_profiles());
+    }
+}
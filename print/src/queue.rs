@@ -0,0 +1,688 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::{
+    error,
+    heat::Heat,
+    journal::now,
+    label::{Label, LabelFont, LabelPlacement},
+    print_bitmap_from,
+    raster::RasterCommand,
+    reconnect::Backoff,
+    separator::{Cut, Separator},
+    status::{Monitor, Status, Transport},
+};
+
+/// Finished jobs kept for status requests
+const HISTORY: usize = 32;
+
+/// One strip of a queued job, ready to print
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueuedStrip {
+    /// Index shown on labels
+    pub index: usize,
+    /// Dots, `Settings::width` per row (true = black)
+    pub dots: Vec<bool>,
+    pub copies: usize,
+    /// Label text in place of the job name
+    pub label: Option<String>,
+    /// Print density in place of the queue's
+    pub density: Option<u32>,
+    /// Cut in place of the queue's
+    pub cut: Option<Cut>,
+}
+
+/// How the queue prints, the same for every job
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Dots per row
+    pub width: usize,
+    /// Dots per mm along the paper, for label lengths
+    pub dots_per_mm: f32,
+    pub command: RasterCommand,
+    pub separator: Separator,
+    pub heat: Heat,
+    /// Rows per raster or graphics band; `None` for the encoder's default
+    pub band_rows: Option<usize>,
+    /// Where to print labels, if at all
+    pub label: Option<LabelPlacement>,
+    pub label_font: LabelFont,
+    /// How to reconnect to a printer that went away before failing the job; `None` to fail it
+    /// straight away
+    pub reconnect: Option<Backoff>,
+}
+
+/// Where a job is in the queue
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    Queued,
+    Printing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of one job, as reported to clients
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct JobStatus {
+    pub id: u64,
+    pub name: String,
+    pub state: JobState,
+    /// Strips to print, counting every copy
+    pub strips: usize,
+    /// Strips printed so far, counting every copy
+    pub printed: usize,
+    /// Rows sent of the strip being printed
+    pub rows: usize,
+    /// Height of the strip being printed, in rows
+    pub height: usize,
+    /// Why the job failed
+    pub error: Option<String>,
+    /// When the job was submitted, in seconds since the Unix epoch
+    pub submitted: u64,
+}
+
+/// The whole queue, as reported to clients
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct QueueStatus {
+    /// Whether printing is paused; a job being printed stops before its next strip
+    pub paused: bool,
+    /// What the printer last reported when it stopped, or "ready"
+    pub printer: String,
+    /// The job being printed, then the waiting ones in print order, then finished ones from the
+    /// newest
+    pub jobs: Vec<JobStatus>,
+}
+
+struct Job {
+    status: JobStatus,
+    strips: Vec<QueuedStrip>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    paused: bool,
+    /// Printer status since it last stopped; `None` once it takes a band again
+    printer: Option<Status>,
+    /// Jobs waiting, in print order
+    queued: VecDeque<Job>,
+    /// The job being printed
+    current: Option<JobStatus>,
+    /// Whether the job being printed was cancelled
+    cancel: bool,
+    /// Finished jobs, newest first
+    finished: VecDeque<JobStatus>,
+}
+
+/// Jobs waiting for one printer. Clients submit, cancel, reorder and pause jobs from any
+/// thread, while `run` prints them in order on another.
+#[derive(Default)]
+pub struct Queue {
+    state: Mutex<State>,
+    /// Signalled whenever jobs are added, cancelled or resumed
+    changed: Condvar,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job to the end of the queue, returning its id
+    pub fn submit(&self, name: String, strips: Vec<QueuedStrip>) -> u64 {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        let status = JobStatus {
+            id,
+            name,
+            state: JobState::Queued,
+            strips: strips.iter().map(|strip| strip.copies).sum(),
+            printed: 0,
+            rows: 0,
+            height: 0,
+            error: None,
+            submitted: now(),
+        };
+        state.queued.push_back(Job { status, strips });
+        self.changed.notify_all();
+        id
+    }
+
+    /// Status of every job
+    pub fn status(&self) -> QueueStatus {
+        let state = self.lock();
+        QueueStatus {
+            paused: state.paused,
+            printer: match &state.printer {
+                Some(status) => status.describe(),
+                None => "ready".into(),
+            },
+            jobs: state
+                .current
+                .iter()
+                .chain(state.queued.iter().map(|job| &job.status))
+                .chain(&state.finished)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Status of one job, if it is known
+    pub fn job(&self, id: u64) -> Option<JobStatus> {
+        self.status().jobs.into_iter().find(|job| job.id == id)
+    }
+
+    /// Cancel a job. A waiting job is dropped; one being printed stops after the band being
+    /// sent, and the paper is fed past it.
+    pub fn cancel(&self, id: u64) -> Result<()> {
+        let mut state = self.lock();
+        if state.current.as_ref().is_some_and(|job| job.id == id) {
+            state.cancel = true;
+        } else if let Some(pos) = state.queued.iter().position(|job| job.status.id == id) {
+            let mut status = state.queued.remove(pos).expect("Queued job").status;
+            status.state = JobState::Cancelled;
+            state.finish(status);
+        } else {
+            bail!("Job {} is not queued or printing", id);
+        }
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Move a waiting job to `position` in the queue, 0 being next
+    pub fn move_job(&self, id: u64, position: usize) -> Result<()> {
+        let mut state = self.lock();
+        let Some(pos) = state.queued.iter().position(|job| job.status.id == id) else {
+            bail!("Job {} is not waiting in the queue", id);
+        };
+        let job = state.queued.remove(pos).expect("Queued job");
+        let position = position.min(state.queued.len());
+        state.queued.insert(position, job);
+        Ok(())
+    }
+
+    /// Stop before the next strip until `resume`
+    pub fn pause(&self) {
+        self.lock().paused = true;
+    }
+
+    pub fn resume(&self) {
+        self.lock().paused = false;
+        self.changed.notify_all();
+    }
+
+    /// Record that the printer stopped, e.g. for paper; pass to `Monitor::new`
+    pub fn printer_stopped(&self, status: &Status) {
+        self.lock().printer = Some(*status);
+    }
+
+    /// Wrap the transport under the `Monitor` passed to `run`, so cancelling a job also stops it
+    /// waiting for a stopped printer
+    pub fn cancellable<T: Transport>(&self, transport: T) -> Cancellable<'_, T> {
+        Cancellable {
+            transport,
+            queue: self,
+        }
+    }
+
+    /// Print jobs as they arrive, forever
+    pub fn run<T: Transport, F: FnMut(&Status)>(
+        &self,
+        mut printer: Monitor<T, F>,
+        settings: &Settings,
+    ) -> ! {
+        loop {
+            let job = self.next_job();
+            self.run_job(&mut printer, &job, settings);
+        }
+    }
+
+    /// Print the current job and move it to the finished list
+    fn run_job<T: Transport, F: FnMut(&Status)>(
+        &self,
+        printer: &mut Monitor<T, F>,
+        job: &Job,
+        settings: &Settings,
+    ) {
+        let status = &job.status;
+        eprintln!(
+            "Job {} ({}): printing {} strips",
+            status.id, status.name, status.strips
+        );
+
+        let result = self.print_job(printer, job, settings);
+        match &result {
+            Ok(()) => eprintln!("Job {} done", status.id),
+            Err(_) if self.lock().cancel => eprintln!("Job {} cancelled", status.id),
+            Err(e) => eprintln!("Job {} failed: {}", status.id, e),
+        }
+        self.finish(result);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait for a job while the queue is empty or paused, and make it the current one
+    fn next_job(&self) -> Job {
+        let mut state = self.lock();
+        loop {
+            if !state.paused {
+                if let Some(mut job) = state.queued.pop_front() {
+                    job.status.state = JobState::Printing;
+                    state.current = Some(job.status.clone());
+                    state.cancel = false;
+                    return job;
+                }
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn print_job<T: Transport, F: FnMut(&Status)>(
+        &self,
+        printer: &mut Monitor<T, F>,
+        job: &Job,
+        settings: &Settings,
+    ) -> error::Result<()> {
+        for strip in &job.strips {
+            for _ in 0..strip.copies {
+                self.wait_resumed()?;
+                self.update(|status| {
+                    status.rows = 0;
+                    status.height = strip.dots.len() / settings.width;
+                });
+                if let Err(e) = self.print_copy(printer, &job.status, strip, settings) {
+                    // Feed a strip cut short clear of the printer, so it can be torn off
+                    if self.lock().cancel {
+                        let _ = settings.separator.after(
+                            &mut *printer,
+                            settings.command,
+                            settings.width,
+                        );
+                    }
+                    return Err(e);
+                }
+                self.update(|status| status.printed += 1);
+            }
+        }
+        // Bands sent since the last status poll are checked before the job counts as done
+        printer.flush()?;
+        Ok(())
+    }
+
+    /// Print one copy of a strip, reconnecting and carrying on from the last band the printer
+    /// took if it goes away. Fails if it goes away again before taking another band.
+    fn print_copy<T: Transport, F: FnMut(&Status)>(
+        &self,
+        printer: &mut Monitor<T, F>,
+        job: &JobStatus,
+        strip: &QueuedStrip,
+        settings: &Settings,
+    ) -> error::Result<()> {
+        // Row to carry on from after reconnecting
+        let mut start = 0;
+        let mut resumed = None;
+        loop {
+            let result = print_strip(
+                Interruptible::new(&mut *printer, self),
+                &job.name,
+                strip,
+                settings,
+                start,
+                |rows| self.update(|status| status.rows = rows),
+            );
+            let e = match result {
+                Err(e) if e.is_disconnect() && !self.lock().cancel => e,
+                result => return result,
+            };
+            let Some(backoff) = &settings.reconnect else {
+                return Err(e);
+            };
+            start = self.current_rows();
+            if resumed == Some(start) {
+                return Err(e);
+            }
+
+            eprintln!("Job {}: printer disconnected: {}", job.id, e);
+            printer
+                .reconnect(backoff, |e, delay| {
+                    eprintln!("{}; trying again in {:.1} s", e, delay.as_secs_f32())
+                })
+                .map_err(|reconnect| {
+                    eprintln!("Job {}: giving up reconnecting: {}", job.id, reconnect);
+                    e
+                })?;
+            eprintln!("Job {}: reconnected; resuming at row {}", job.id, start);
+            resumed = Some(start);
+        }
+    }
+
+    /// Wait while the queue is paused. Fails if the job is cancelled meanwhile.
+    fn wait_resumed(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        while state.paused && !state.cancel {
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.cancel {
+            return Err(cancelled());
+        }
+        Ok(())
+    }
+
+    /// Rows sent of the strip being printed
+    fn current_rows(&self) -> usize {
+        self.lock().current.as_ref().map_or(0, |status| status.rows)
+    }
+
+    /// Change the status of the job being printed
+    fn update(&self, f: impl FnOnce(&mut JobStatus)) {
+        if let Some(status) = &mut self.lock().current {
+            f(status);
+        }
+    }
+
+    /// Move the job being printed to the finished list
+    fn finish(&self, result: error::Result<()>) {
+        let mut state = self.lock();
+        let Some(mut status) = state.current.take() else {
+            return;
+        };
+        status.state = match result {
+            Ok(()) => JobState::Done,
+            Err(_) if state.cancel => JobState::Cancelled,
+            Err(e) => {
+                status.error = Some(e.to_string());
+                JobState::Failed
+            }
+        };
+        state.cancel = false;
+        state.finish(status);
+    }
+}
+
+impl State {
+    fn finish(&mut self, status: JobStatus) {
+        self.finished.push_front(status);
+        self.finished.truncate(HISTORY);
+    }
+}
+
+/// Print one strip with its labels and the paper handling around it, from row `start`
+fn print_strip<W: Write>(
+    mut printer: W,
+    job: &str,
+    strip: &QueuedStrip,
+    settings: &Settings,
+    start: usize,
+    progress: impl FnMut(usize),
+) -> error::Result<()> {
+    let width = settings.width;
+    let label = Label {
+        index: strip.index,
+        job: strip.label.clone().unwrap_or_else(|| job.into()),
+        length_mm: (strip.dots.len() / width) as f32 / settings.dots_per_mm,
+    };
+    let heat = Heat {
+        density: strip.density,
+        ..Heat::default()
+    }
+    .or(settings.heat);
+    let separator = Separator {
+        cut: strip.cut.or(settings.separator.cut),
+        ..settings.separator.clone()
+    };
+    let placement = settings.label;
+
+    heat.send(&mut printer)?;
+    // A resumed strip already has its margin and header
+    if start == 0 {
+        separator.before(&mut printer)?;
+        if placement.is_some_and(|p| p.header()) {
+            label.header(&mut printer, settings.label_font, settings.command, width)?;
+        }
+    }
+    print_bitmap_from(
+        &mut printer,
+        &strip.dots,
+        width,
+        settings.command,
+        settings.band_rows,
+        start,
+        progress,
+    )?;
+    if placement.is_some_and(|p| p.footer()) {
+        label.footer(&mut printer, settings.label_font, settings.command, width)?;
+    }
+    separator.after(&mut printer, settings.command, width)
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Job cancelled")
+}
+
+/// Transport that fails status requests once the job being printed is cancelled, so a `Monitor`
+/// waiting for the printer to be ready again gives up; see `Queue::cancellable`
+pub struct Cancellable<'a, T: Transport> {
+    transport: T,
+    queue: &'a Queue,
+}
+
+impl<T: Transport> Write for Cancellable<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+impl<T: Transport> Transport for Cancellable<'_, T> {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        if self.queue.lock().cancel {
+            return Err(cancelled());
+        }
+        self.transport.transact(request)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        self.transport.reconnect()
+    }
+}
+
+/// Collects each band (everything up to a flush) and only sends it if the job hasn't been
+/// cancelled meanwhile, so a cancelled job stops between bands rather than part way through a
+/// command
+struct Interruptible<'a, W: Write> {
+    printer: W,
+    queue: &'a Queue,
+    band: Vec<u8>,
+}
+
+impl<'a, W: Write> Interruptible<'a, W> {
+    fn new(printer: W, queue: &'a Queue) -> Self {
+        Self {
+            printer,
+            queue,
+            band: vec![],
+        }
+    }
+}
+
+impl<W: Write> Write for Interruptible<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.band.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.queue.lock().cancel {
+            self.band.clear();
+            return Err(cancelled());
+        }
+        let band = std::mem::take(&mut self.band);
+        self.printer.write_all(&band)?;
+        self.printer.flush()?;
+        // The printer took the band, so it isn't stopped any more
+        self.queue.lock().printer = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{emulator::Emulator, mock::MockPrinter, status::StatusQuery};
+
+    const WIDTH: usize = 64;
+    const BAND_ROWS: usize = 8;
+
+    fn settings() -> Settings {
+        Settings {
+            width: WIDTH,
+            dots_per_mm: 8.,
+            command: RasterCommand::Raster,
+            separator: Separator {
+                margin: 4,
+                feed: 16,
+                ..Separator::default()
+            },
+            heat: Heat::default(),
+            band_rows: Some(BAND_ROWS),
+            label: None,
+            label_font: LabelFont::Bitmap,
+            reconnect: Some(Backoff {
+                first: Duration::ZERO,
+                max: Duration::ZERO,
+                limit: Duration::from_secs(1),
+            }),
+        }
+    }
+
+    /// Six bands of a diagonal pattern
+    fn strip() -> QueuedStrip {
+        QueuedStrip {
+            index: 0,
+            dots: (0..WIDTH * 6 * BAND_ROWS)
+                .map(|i| (i % WIDTH + i / WIDTH).is_multiple_of(7))
+                .collect(),
+            copies: 1,
+            label: None,
+            density: None,
+            cut: None,
+        }
+    }
+
+    /// Print a one strip job on a mock printer running `script`, cancelling it once the printer
+    /// stops if `cancel`. Returns how the job ended and the paper.
+    fn run(script: &str, cancel: bool) -> (JobStatus, Vec<bool>) {
+        let queue = Queue::new();
+        let script = script.split(',').map(|e| e.parse().unwrap()).collect();
+        let mut mock = MockPrinter::new(WIDTH, script, None);
+        let id = queue.submit("test".into(), vec![strip()]);
+
+        thread::scope(|scope| {
+            let printing = scope.spawn(|| {
+                let mut monitor = Monitor::new(
+                    queue.cancellable(&mut mock),
+                    Some(StatusQuery::DleEot),
+                    |status| queue.printer_stopped(status),
+                );
+                let job = queue.next_job();
+                queue.run_job(&mut monitor, &job, &settings());
+            });
+            if cancel {
+                while queue.status().printer == "ready" {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                queue.cancel(id).unwrap();
+            }
+            printing.join().unwrap();
+        });
+
+        let paper = mock.emulator().unwrap().paper();
+        (queue.job(id).unwrap(), paper)
+    }
+
+    /// Paper of the job printed without a hitch
+    fn uninterrupted() -> Vec<bool> {
+        let mut bytes = vec![];
+        print_strip(&mut bytes, "test", &strip(), &settings(), 0, |_| {}).unwrap();
+        Emulator::decode(WIDTH, &bytes).unwrap().paper()
+    }
+
+    #[test]
+    fn jobs_print_as_sent() {
+        let (job, paper) = run("near-end@1", false);
+        assert_eq!(job.state, JobState::Done);
+        assert_eq!(job.printed, 1);
+        assert!(paper == uninterrupted());
+    }
+
+    #[test]
+    fn reconnects_and_carries_on() {
+        let (job, paper) = run("disconnect@3x2", false);
+        assert_eq!(job.state, JobState::Done, "{:?}", job.error);
+        assert!(paper == uninterrupted());
+    }
+
+    #[test]
+    fn fails_when_the_printer_keeps_going_away() {
+        // Gone again before taking another band
+        let (job, _) = run("disconnect@3,disconnect@4", false);
+        assert_eq!(job.state, JobState::Failed);
+        assert!(job.error.is_some());
+    }
+
+    #[test]
+    fn cancel_ends_a_pause() {
+        let (job, paper) = run("paper-out@2x1000", true);
+        assert_eq!(job.state, JobState::Cancelled);
+        assert_eq!(job.printed, 0);
+        assert!(paper.len() < uninterrupted().len());
+    }
+
+    #[test]
+    fn waiting_jobs_are_moved_and_cancelled() {
+        let queue = Queue::new();
+        let ids: Vec<u64> = (0..3)
+            .map(|i| queue.submit(format!("job {}", i), vec![strip()]))
+            .collect();
+        queue.move_job(ids[2], 0).unwrap();
+        queue.cancel(ids[1]).unwrap();
+        assert!(queue.cancel(ids[1]).is_err());
+
+        let states: Vec<(u64, JobState)> = queue
+            .status()
+            .jobs
+            .iter()
+            .map(|job| (job.id, job.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (ids[2], JobState::Queued),
+                (ids[0], JobState::Queued),
+                (ids[1], JobState::Cancelled)
+            ]
+        );
+    }
+}
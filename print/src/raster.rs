@@ -0,0 +1,482 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    LS_SET,
+};
+
+pub const BIT_IMAGE: &[u8] = b"\x1b\x2a";
+pub const RASTER_IMAGE: &[u8] = b"\x1dv0";
+pub const GRAPHICS: &[u8] = b"\x1d(L";
+pub const GRAPHICS_LONG: &[u8] = b"\x1d8L";
+pub const FEED: &[u8] = b"\x1bJ";
+pub const ABS_POS_SET: &[u8] = b"\x1b$";
+
+/// Rows sent per `GS v 0` or `GS ( L` command, unless a profile says otherwise
+pub const RASTER_BAND_ROWS: usize = 128;
+
+/// Raster image commands a printer may understand
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RasterCommand {
+    /// `ESC * 33`: 24-dot double density bit image bands
+    BitImage24,
+    /// `ESC * 0`: 8-dot single density bit image bands, for older printers. Prints at a third of
+    /// the vertical and half the horizontal resolution.
+    BitImage8,
+    /// `GS v 0`: raster bit image
+    Raster,
+    /// `GS ( L` / `GS 8 L`: graphics stored in the print buffer, then printed
+    Graphics,
+}
+
+/// Every raster command, in the order they are listed in help text
+pub const ALL_RASTER_COMMANDS: [RasterCommand; 4] = [
+    RasterCommand::BitImage24,
+    RasterCommand::BitImage8,
+    RasterCommand::Raster,
+    RasterCommand::Graphics,
+];
+
+impl RasterCommand {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BitImage24 => "bit-image-24",
+            Self::BitImage8 => "bit-image-8",
+            Self::Raster => "raster",
+            Self::Graphics => "graphics",
+        }
+    }
+
+    /// Send `bitmap` (true = black), `width` dots per row
+    pub fn encode<W: Write>(&self, printer: W, bitmap: &[bool], width: usize) -> Result<()> {
+        let mut encoder = Encoder::new(printer, *self, width)?;
+        for row in bitmap.chunks(width) {
+            encoder.push_row(&pack_row(row))?;
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// `ESC *` mode, for the bit image commands
+    fn bit_image_mode(&self) -> Option<u8> {
+        match self {
+            Self::BitImage24 => Some(33),
+            Self::BitImage8 => Some(0),
+            Self::Raster | Self::Graphics => None,
+        }
+    }
+}
+
+impl FromStr for RasterCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match ALL_RASTER_COMMANDS.iter().find(|c| c.name() == s) {
+            Some(&command) => Ok(command),
+            None => {
+                let names: Vec<&str> = ALL_RASTER_COMMANDS.iter().map(|c| c.name()).collect();
+                bail!(
+                    "Unknown raster command \"{}\"; expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// `ESC *` mode parameters: (vertical dots per column, vertical scale, horizontal scale)
+pub fn bit_image_mode(m: u8) -> Option<(usize, usize, usize)> {
+    match m {
+        0 => Some((8, 3, 2)),
+        1 => Some((8, 3, 1)),
+        32 => Some((24, 1, 2)),
+        33 => Some((24, 1, 1)),
+        _ => None,
+    }
+}
+
+/// Streaming image encoder. Rows go in packed (see `pack_row`) and are sent as soon as a band is
+/// complete, so only one band is ever held in memory. Blank bands are fed past with `ESC J`, and
+/// blank columns at either side are skipped where the command allows it.
+pub struct Encoder<W: Write> {
+    printer: W,
+    command: RasterCommand,
+    bytes_per_row: usize,
+    /// Rows per band
+    band_rows: usize,
+    /// Packed rows of the band being collected
+    band: Vec<u8>,
+    /// Blank rows not yet fed past
+    blank_rows: usize,
+    /// Rows pushed so far
+    pushed: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Start an image `width` dots wide
+    pub fn new(printer: W, command: RasterCommand, width: usize) -> Result<Self> {
+        Self::with_band_rows(printer, command, width, None)
+    }
+
+    /// Start an image `width` dots wide, sending raster images `band_rows` rows at a time
+    /// instead of `RASTER_BAND_ROWS`. Bit images always go one line at a time.
+    pub fn with_band_rows(
+        mut printer: W,
+        command: RasterCommand,
+        width: usize,
+        band_rows: Option<usize>,
+    ) -> Result<Self> {
+        let band_rows = match command.bit_image_mode() {
+            Some(m) => {
+                // Zero line spacing so bands abut
+                printer.write_all(LS_SET)?;
+                printer.write_all(&[0])?;
+
+                let (dots, v_scale, _) = bit_image_mode(m).expect("Invalid bit image mode");
+                dots * v_scale
+            }
+            None => band_rows.unwrap_or(RASTER_BAND_ROWS),
+        };
+
+        let bytes_per_row = width.div_ceil(8);
+
+        Ok(Self {
+            printer,
+            command,
+            bytes_per_row,
+            band_rows,
+            band: Vec::with_capacity(band_rows * bytes_per_row),
+            blank_rows: 0,
+            pushed: 0,
+        })
+    }
+
+    /// Add the next row of the image
+    pub fn push_row(&mut self, row: &[u8]) -> Result<()> {
+        if row.len() != self.bytes_per_row {
+            return Err(Error::WrongWidth {
+                expected: self.bytes_per_row * 8,
+                actual: row.len() * 8,
+            });
+        }
+
+        self.pushed += 1;
+
+        // Raster images break at blank rows; bit images must keep their band structure
+        let blank = row.iter().all(|&b| b == 0);
+        if blank && self.command.bit_image_mode().is_none() {
+            self.emit_band()?;
+            self.blank_rows += 1;
+            return Ok(());
+        }
+
+        self.band.extend_from_slice(row);
+        if self.rows() == self.band_rows {
+            self.emit_band()?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the remaining rows, returning the printer
+    pub fn finish(mut self) -> Result<W> {
+        self.emit_band()?;
+        feed(&mut self.printer, self.blank_rows)?;
+        self.printer.flush()?;
+        Ok(self.printer)
+    }
+
+    /// Rows sent to the printer so far; the rest are still waiting for their band to fill
+    pub fn rows_sent(&self) -> usize {
+        self.pushed - self.rows() - self.blank_rows
+    }
+
+    fn rows(&self) -> usize {
+        self.band.len() / self.bytes_per_row
+    }
+
+    fn emit_band(&mut self) -> Result<()> {
+        if self.band.is_empty() {
+            return Ok(());
+        }
+
+        match self.command.bit_image_mode() {
+            Some(m) => self.emit_bit_image(m)?,
+            None => {
+                feed(&mut self.printer, std::mem::take(&mut self.blank_rows))?;
+                match self.command {
+                    RasterCommand::Graphics => self.emit_graphics()?,
+                    _ => self.emit_raster()?,
+                }
+            }
+        }
+
+        self.band.clear();
+        self.printer.flush()?;
+
+        Ok(())
+    }
+
+    /// Whether the dot at column `x` of band row `row` is black
+    fn dot(&self, x: usize, row: usize) -> bool {
+        self.band
+            .get(row * self.bytes_per_row + x / 8)
+            .is_some_and(|b| b & (0x80 >> (x % 8)) != 0)
+    }
+
+    /// One `ESC * m` band followed by a newline
+    fn emit_bit_image(&mut self, m: u8) -> Result<()> {
+        let (dots, v_scale, h_scale) = bit_image_mode(m).expect("Invalid bit image mode");
+        let columns = self.bytes_per_row * 8 / h_scale;
+        let bytes_per_column = dots / 8;
+
+        let mut data = Vec::with_capacity(columns * bytes_per_column);
+        for col in 0..columns {
+            let x = col * h_scale;
+            for set in 0..bytes_per_column {
+                let mut b = 0;
+                for bit in 0..8 {
+                    let row = (set * 8 + bit) * v_scale;
+
+                    b <<= 1;
+                    if self.dot(x, row) {
+                        b |= 1;
+                    };
+                }
+                data.push(b);
+            }
+        }
+
+        let column_used = |col: &usize| {
+            data[col * bytes_per_column..(col + 1) * bytes_per_column]
+                .iter()
+                .any(|&b| b != 0)
+        };
+        let (Some(first), Some(last)) = (
+            (0..columns).find(column_used),
+            (0..columns).rev().find(column_used),
+        ) else {
+            self.blank_rows += self.rows();
+            return Ok(());
+        };
+
+        feed(&mut self.printer, std::mem::take(&mut self.blank_rows))?;
+
+        if first > 0 {
+            self.printer.write_all(ABS_POS_SET)?;
+            self.printer
+                .write_all(&u16::to_le_bytes((first * h_scale) as u16))?;
+        }
+
+        self.printer.write_all(BIT_IMAGE)?;
+        self.printer.write_all(&[m])?;
+        // nL nH counts columns, not bytes
+        self.printer
+            .write_all(&u16::to_le_bytes((last + 1 - first) as u16))?;
+        self.printer
+            .write_all(&data[first * bytes_per_column..(last + 1) * bytes_per_column])?;
+
+        self.printer.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    /// One `GS v 0` image. The printer feeds past it by itself.
+    fn emit_raster(&mut self) -> Result<()> {
+        let rows = self.rows();
+        let bytes = self.used_width().div_ceil(8);
+
+        self.printer.write_all(RASTER_IMAGE)?;
+        self.printer.write_all(&[0])?; // Normal density
+        self.printer.write_all(&u16::to_le_bytes(bytes as u16))?;
+        self.printer.write_all(&u16::to_le_bytes(rows as u16))?;
+        for row in self.band.chunks(self.bytes_per_row) {
+            self.printer.write_all(&row[..bytes])?;
+        }
+
+        Ok(())
+    }
+
+    /// `GS ( L` (or `GS 8 L` when too long) "store raster graphics" followed by "print graphics"
+    fn emit_graphics(&mut self) -> Result<()> {
+        let rows = self.rows();
+        let used = self.used_width();
+        let bytes = used.div_ceil(8);
+
+        // m fn a bx by c xL xH yL yH
+        let mut params = vec![48, 112, 48, 1, 1, 49];
+        params.extend(u16::to_le_bytes(used as u16));
+        params.extend(u16::to_le_bytes(rows as u16));
+
+        let len = params.len() + bytes * rows;
+        match u16::try_from(len) {
+            Ok(len) => {
+                self.printer.write_all(GRAPHICS)?;
+                self.printer.write_all(&len.to_le_bytes())?;
+            }
+            Err(_) => {
+                self.printer.write_all(GRAPHICS_LONG)?;
+                self.printer.write_all(&(len as u32).to_le_bytes())?;
+            }
+        }
+        self.printer.write_all(&params)?;
+        for row in self.band.chunks(self.bytes_per_row) {
+            self.printer.write_all(&row[..bytes])?;
+        }
+
+        // Print the buffered graphics: pL pH m fn
+        self.printer.write_all(GRAPHICS)?;
+        self.printer.write_all(&[2, 0, 48, 50])?;
+
+        Ok(())
+    }
+
+    /// Dots up to and including the rightmost black one in any row of the band
+    fn used_width(&self) -> usize {
+        self.band
+            .chunks(self.bytes_per_row)
+            .filter_map(|row| {
+                let i = row.iter().rposition(|&b| b != 0)?;
+                Some(i * 8 + 8 - row[i].trailing_zeros() as usize)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Feed the paper `rows` dots with `ESC J`
+pub fn feed<W: Write>(mut printer: W, mut rows: usize) -> Result<()> {
+    while rows > 0 {
+        let n = rows.min(u8::MAX as usize);
+        printer.write_all(FEED)?;
+        printer.write_all(&[n as u8])?;
+        rows -= n;
+    }
+    Ok(())
+}
+
+/// Pack one row of dots (true = black) into bytes, leftmost dot in the most significant bit.
+/// Set bits are black, as printers expect; padding is white.
+pub fn pack_row(row: &[bool]) -> Vec<u8> {
+    row.chunks(8)
+        .map(|px| {
+            px.iter()
+                .enumerate()
+                .fold(0, |b, (i, &black)| if black { b | 0x80 >> i } else { b })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+
+    /// Packed row with black dots at `xs`
+    fn row(xs: &[usize]) -> Vec<u8> {
+        let mut dots = vec![false; WIDTH];
+        for &x in xs {
+            dots[x] = true;
+        }
+        pack_row(&dots)
+    }
+
+    fn encode(command: RasterCommand, band_rows: Option<usize>, rows: &[Vec<u8>]) -> Vec<u8> {
+        let mut encoder = Encoder::with_band_rows(vec![], command, WIDTH, band_rows).unwrap();
+        for row in rows {
+            encoder.push_row(row).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn blank_rows_are_fed_past() {
+        let mut rows = vec![row(&[]); 300];
+        rows.push(row(&[0]));
+        rows.extend(vec![row(&[]); 2]);
+
+        let bytes = encode(RasterCommand::Raster, None, &rows);
+        let mut expected = b"\x1bJ\xff\x1bJ\x2d".to_vec();
+        expected.extend(b"\x1dv0\x00\x01\x00\x01\x00\x80");
+        expected.extend(b"\x1bJ\x02");
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn raster_is_trimmed_to_the_rightmost_dot() {
+        let rows = [row(&[10]), row(&[3])];
+        let bytes = encode(RasterCommand::Raster, None, &rows);
+        // 11 dots used, so 2 bytes per row
+        assert_eq!(bytes, b"\x1dv0\x00\x02\x00\x02\x00\x00\x20\x10\x00");
+    }
+
+    #[test]
+    fn bit_image_skips_blank_columns() {
+        let mut rows = vec![row(&[40, 41])];
+        rows.extend(vec![row(&[]); 23]);
+        // A blank band, fed past at the end
+        rows.extend(vec![row(&[]); 24]);
+
+        let bytes = encode(RasterCommand::BitImage24, None, &rows);
+        let mut expected = b"\x1b3\x00".to_vec();
+        expected.extend(b"\x1b$\x28\x00");
+        expected.extend(b"\x1b*\x21\x02\x00\x80\x00\x00\x80\x00\x00\n");
+        expected.extend(b"\x1bJ\x18");
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn long_graphics_use_gs_8_l() {
+        let rows = 65536 / (WIDTH / 8);
+        let bytes = encode(RasterCommand::Graphics, Some(rows), &vec![row(&[63]); rows]);
+        let len = 10 + rows * WIDTH / 8;
+        assert!(len > u16::MAX as usize);
+
+        let mut header = b"\x1d8L".to_vec();
+        header.extend((len as u32).to_le_bytes());
+        header.extend([48, 112, 48, 1, 1, 49, 64, 0]);
+        header.extend((rows as u16).to_le_bytes());
+        assert_eq!(bytes[..header.len()], header[..]);
+        assert_eq!(
+            bytes[header.len() + rows * WIDTH / 8..],
+            *b"\x1d(L\x02\x00\x30\x32"
+        );
+
+        // A short band fits in GS ( L
+        let bytes = encode(RasterCommand::Graphics, None, &[row(&[63])]);
+        assert_eq!(
+            bytes,
+            b"\x1d(L\x12\x00\x30\x70\x30\x01\x01\x31\x40\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1d(L\x02\x00\x30\x32"
+        );
+    }
+
+    #[test]
+    fn rows_sent_waits_for_full_bands() {
+        let mut encoder =
+            Encoder::with_band_rows(vec![], RasterCommand::Raster, WIDTH, Some(4)).unwrap();
+        for _ in 0..3 {
+            encoder.push_row(&row(&[1])).unwrap();
+        }
+        assert_eq!(encoder.rows_sent(), 0);
+        encoder.push_row(&row(&[1])).unwrap();
+        assert_eq!(encoder.rows_sent(), 4);
+    }
+
+    #[test]
+    fn rows_must_match_the_width() {
+        let mut encoder = Encoder::new(vec![], RasterCommand::Raster, WIDTH).unwrap();
+        assert!(matches!(
+            encoder.push_row(&[0; 4]),
+            Err(Error::WrongWidth {
+                expected: 64,
+                actual: 32
+            })
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::status::Transport;
+
+/// Wait before the first attempt, giving the printer time to show up on the bus again
+const FIRST_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest wait between attempts
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// When to try reconnecting to a printer that went away: after `first`, then waiting twice as
+/// long each time up to `max`, until `limit` has passed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub first: Duration,
+    pub max: Duration,
+    pub limit: Duration,
+}
+
+impl Backoff {
+    /// Keep trying for `limit`
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            first: FIRST_DELAY,
+            max: MAX_DELAY,
+            limit,
+        }
+    }
+
+    /// Reconnect `transport`, calling `on_fail` with each failed attempt and the wait before the
+    /// next. Fails with the last error once `limit` has passed, or straight away if the
+    /// transport can't reconnect at all.
+    pub fn reconnect<T: Transport + ?Sized>(
+        &self,
+        transport: &mut T,
+        mut on_fail: impl FnMut(&io::Error, Duration),
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let mut delay = self.first;
+        loop {
+            thread::sleep(delay);
+            let e = match transport.reconnect() {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => return Err(e),
+                Err(e) => e,
+            };
+
+            delay = (delay * 2).min(self.max);
+            if start.elapsed() + delay > self.limit {
+                return Err(e);
+            }
+            on_fail(&e, delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Printer that is back after `failures` reconnection attempts, or never if they fail with
+    /// `Unsupported`
+    struct Flaky {
+        failures: usize,
+        kind: io::ErrorKind,
+        attempts: usize,
+    }
+
+    impl Flaky {
+        fn new(failures: usize, kind: io::ErrorKind) -> Self {
+            Self {
+                failures,
+                kind,
+                attempts: 0,
+            }
+        }
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Flaky {
+        fn reconnect(&mut self) -> io::Result<()> {
+            self.attempts += 1;
+            if self.attempts > self.failures {
+                return Ok(());
+            }
+            Err(io::Error::new(self.kind, "Flaky printer"))
+        }
+    }
+
+    fn backoff(first: u64, max: u64, limit: u64) -> Backoff {
+        Backoff {
+            first: Duration::from_millis(first),
+            max: Duration::from_millis(max),
+            limit: Duration::from_millis(limit),
+        }
+    }
+
+    #[test]
+    fn doubles_the_delay_up_to_max() {
+        let mut flaky = Flaky::new(4, io::ErrorKind::NotConnected);
+        let mut delays = vec![];
+        backoff(1, 4, 1000)
+            .reconnect(&mut flaky, |_, delay| delays.push(delay.as_millis()))
+            .unwrap();
+        assert_eq!(flaky.attempts, 5);
+        assert_eq!(delays, [2, 4, 4, 4]);
+    }
+
+    #[test]
+    fn gives_up_after_limit() {
+        let mut flaky = Flaky::new(usize::MAX, io::ErrorKind::NotConnected);
+        let e = backoff(5, 10, 40)
+            .reconnect(&mut flaky, |_, _| {})
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotConnected);
+        // Waits of 5, 10, 10 and 10 ms fit in 40, but not another 10; fewer if sleeps run over
+        assert!(
+            (2..=4).contains(&flaky.attempts),
+            "{} attempts",
+            flaky.attempts
+        );
+    }
+
+    #[test]
+    fn unsupported_stops_at_once() {
+        let mut flaky = Flaky::new(usize::MAX, io::ErrorKind::Unsupported);
+        let mut failures = 0;
+        let e = backoff(1, 4, 1000)
+            .reconnect(&mut flaky, |_, _| failures += 1)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert_eq!((flaky.attempts, failures), (1, 0));
+    }
+}
@@ -0,0 +1,177 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Result,
+    raster::{feed, pack_row, Encoder, RasterCommand},
+};
+
+pub const CUT: &[u8] = b"\x1dV";
+
+/// Length of the dashes in a cut line, in dots
+const DASH_DOTS: usize = 16;
+/// Gap between the dashes in a cut line, in dots
+const DASH_GAP_DOTS: usize = 8;
+/// Thickness of a cut line, in dots
+const DASH_ROWS: usize = 2;
+
+/// Auto-cutter mode
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cut {
+    /// Cut all the way through
+    Full,
+    /// Leave a tab in the middle so the strip hangs until torn off
+    Partial,
+}
+
+impl Cut {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Partial => "partial",
+        }
+    }
+
+    /// `GS V m n`: feed to the cutter, then cut. The cutter sits some way above the print head,
+    /// so a plain `GS V m` would cut through the end of the strip.
+    pub fn send<W: Write>(&self, mut printer: W) -> Result<()> {
+        let m = match self {
+            Self::Full => 65,
+            Self::Partial => 66,
+        };
+        printer.write_all(CUT)?;
+        printer.write_all(&[m, 0])?;
+        Ok(())
+    }
+}
+
+impl FromStr for Cut {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "full" => Self::Full,
+            "partial" => Self::Partial,
+            _ => bail!("Unknown cut \"{}\"; expected full or partial", s),
+        })
+    }
+}
+
+/// What goes on the paper between strips, so they don't run together on the roll
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Separator {
+    /// Blank paper before each strip, in dots, so it starts a known distance past the tear bar
+    pub margin: usize,
+    /// Blank paper after each strip, in dots
+    pub feed: usize,
+    /// Print a dashed line after the feed to cut along
+    pub cut_line: bool,
+    /// Cut with the auto-cutter after everything else
+    pub cut: Option<Cut>,
+}
+
+impl Separator {
+    /// Send what goes before a strip
+    pub fn before<W: Write>(&self, printer: W) -> Result<()> {
+        feed(printer, self.margin)
+    }
+
+    /// Send what goes after a strip. The cut line is drawn with `command`, `width` dots wide.
+    pub fn after<W: Write>(
+        &self,
+        mut printer: W,
+        command: RasterCommand,
+        width: usize,
+    ) -> Result<()> {
+        feed(&mut printer, self.feed)?;
+
+        if self.cut_line {
+            let dashes: Vec<bool> = (0..width)
+                .map(|x| x % (DASH_DOTS + DASH_GAP_DOTS) < DASH_DOTS)
+                .collect();
+            let row = pack_row(&dashes);
+
+            let mut encoder = Encoder::new(&mut printer, command, width)?;
+            for _ in 0..DASH_ROWS {
+                encoder.push_row(&row)?;
+            }
+            encoder.finish()?;
+        }
+
+        if let Some(cut) = self.cut {
+            cut.send(&mut printer)?;
+        }
+
+        printer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    const WIDTH: usize = 64;
+
+    /// Bytes sent around one strip
+    fn around(separator: &Separator) -> Vec<u8> {
+        let mut out = vec![];
+        separator.before(&mut out).unwrap();
+        separator
+            .after(&mut out, RasterCommand::Raster, WIDTH)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn feeds_the_margin_and_feed() {
+        let separator = Separator {
+            margin: 30,
+            feed: 300,
+            ..Separator::default()
+        };
+        let emu = Emulator::decode(WIDTH, &around(&separator)).unwrap();
+        assert_eq!(emu.height(), 330);
+        assert!(emu.paper().iter().all(|&dot| !dot));
+        assert!(emu.cuts().is_empty());
+    }
+
+    #[test]
+    fn draws_a_dashed_cut_line_after_the_feed() {
+        let separator = Separator {
+            feed: 10,
+            cut_line: true,
+            ..Separator::default()
+        };
+        let emu = Emulator::decode(WIDTH, &around(&separator)).unwrap();
+        assert_eq!(emu.height(), 10 + DASH_ROWS);
+
+        let paper = emu.paper();
+        let (blank, line) = paper.split_at(10 * WIDTH);
+        assert!(blank.iter().all(|&dot| !dot));
+        for row in line.chunks(WIDTH) {
+            let dashes: Vec<bool> = (0..WIDTH).map(|x| x % 24 < 16).collect();
+            assert_eq!(row, dashes);
+        }
+    }
+
+    #[test]
+    fn cuts_after_everything_else() {
+        for (cut, m) in [(Cut::Full, 65), (Cut::Partial, 66)] {
+            let separator = Separator {
+                feed: 20,
+                cut: Some(cut),
+                ..Separator::default()
+            };
+            let bytes = around(&separator);
+            assert!(bytes.ends_with(&[0x1d, b'V', m, 0]), "{:?}", cut);
+
+            let emu = Emulator::decode(WIDTH, &bytes).unwrap();
+            assert_eq!(emu.cuts(), [20], "{:?}", cut);
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    thread,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    dither::Dither,
+    load_luma,
+    manifest::Manifest,
+    profile::Profile,
+    queue::{Queue, QueuedStrip},
+    read_luma,
+    transform::{on_its_side, Step, Transform},
+};
+
+/// Address `print serve` listens on when none is given
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8058";
+
+/// First bytes of every PNG, to tell uploaded images from manifests
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Largest upload accepted, in bytes
+const MAX_UPLOAD: u64 = 64 << 20;
+
+/// Where the queue API listens
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Listen {
+    /// TCP address, as `host:port`
+    Tcp(String),
+    /// Unix socket; a stale one is replaced
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = anyhow::Error;
+
+    /// Parses `HOST:PORT`, a bare port (on localhost) or `unix:PATH`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            ensure!(!path.is_empty(), "Unix socket path is missing");
+            return Ok(Self::Unix(path.into()));
+        }
+        if s.parse::<u16>().is_ok() {
+            return Ok(Self::Tcp(format!("127.0.0.1:{}", s)));
+        }
+        ensure!(
+            s.contains(':'),
+            "Unknown address \"{}\"; expected HOST:PORT, PORT or unix:PATH",
+            s
+        );
+        Ok(Self::Tcp(s.into()))
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "http://{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Listen {
+    /// Start listening
+    pub fn bind(&self) -> Result<Server> {
+        match self {
+            Self::Tcp(addr) => {
+                Server::http(addr).map_err(|e| anyhow!("Listening on {}: {}", addr, e))
+            }
+            Self::Unix(path) => {
+                remove_stale_socket(path)?;
+                Server::http_unix(path)
+                    .map_err(|e| anyhow!("Listening on {}: {}", path.display(), e))
+            }
+        }
+    }
+}
+
+/// Remove a socket left behind by a server that didn't shut down, but nothing else
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            fs::remove_file(path).with_context(|| format!("Removing old socket {}", path.display()))
+        }
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Turns uploads into strips for the queue
+#[derive(Clone, Debug)]
+pub struct Uploads {
+    pub profile: Profile,
+    pub dither: Dither,
+    /// Copies of each strip, unless a manifest says otherwise
+    pub copies: usize,
+    /// Directory manifest paths are relative to; nothing outside it can be printed
+    pub dir: PathBuf,
+}
+
+/// Answer API requests until the server is shut down:
+///
+/// - `GET /jobs`: the queue and the printer status
+/// - `POST /jobs`: add a job. The body is a PNG, or a manifest in JSON or RON whose paths are
+///   relative to `Uploads::dir`. `name` names the job, `copies` sets the copies of a PNG, and
+///   `transform` transforms it.
+/// - `GET /jobs/ID`: one job
+/// - `DELETE /jobs/ID`: cancel a job, even part way through printing
+/// - `POST /jobs/ID/move?to=N`: move a waiting job to position N, 0 being next
+/// - `POST /pause`, `POST /resume`: stop printing before the next strip, and start again
+///
+/// Replies are JSON; errors are `{"error": "..."}`. Each request is answered on its own
+/// thread, so decoding an upload doesn't hold up the others.
+pub fn serve(server: &Server, queue: &Queue, uploads: &Uploads) {
+    thread::scope(|scope| {
+        for request in server.incoming_requests() {
+            scope.spawn(|| respond(request, queue, uploads));
+        }
+    })
+}
+
+fn respond(mut request: Request, queue: &Queue, uploads: &Uploads) {
+    let reply = route(&mut request, queue, uploads).unwrap_or_else(|reply| reply);
+    let header = Header::from_bytes("Content-Type", "application/json").expect("Header");
+    let response = Response::from_string(reply.body)
+        .with_status_code(reply.code)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Replying to a request: {}", e);
+    }
+}
+
+/// Status code and JSON body of a reply
+struct Reply {
+    code: u16,
+    body: String,
+}
+
+impl Reply {
+    fn json(code: u16, value: &impl Serialize) -> Self {
+        Self {
+            code,
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(code: u16, e: impl fmt::Display) -> Self {
+        #[derive(Serialize)]
+        struct Error {
+            error: String,
+        }
+        Self::json(
+            code,
+            &Error {
+                error: e.to_string(),
+            },
+        )
+    }
+}
+
+fn route(
+    request: &mut Request,
+    queue: &Queue,
+    uploads: &Uploads,
+) -> std::result::Result<Reply, Reply> {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (request.url().to_string(), HashMap::new()),
+    };
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let bad_request = |e: anyhow::Error| Reply::error(400, format!("{:#}", e));
+    let job_id = |id: &str| {
+        id.parse::<u64>()
+            .map_err(|_| Reply::error(404, format!("No job \"{}\"", id)))
+    };
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["jobs"]) => Ok(Reply::json(200, &queue.status())),
+        (Method::Post, ["jobs"]) => {
+            let mut body = vec![];
+            request
+                .as_reader()
+                .take(MAX_UPLOAD + 1)
+                .read_to_end(&mut body)
+                .map_err(|e| Reply::error(400, format!("Reading upload: {}", e)))?;
+            if body.len() as u64 > MAX_UPLOAD {
+                return Err(Reply::error(413, "Upload too large"));
+            }
+
+            let (name, strips) = job_strips(&body, &query, uploads).map_err(bad_request)?;
+            if strips.is_empty() {
+                return Err(Reply::error(400, "Nothing to print"));
+            }
+            let id = queue.submit(name, strips);
+            Ok(Reply::json(201, &queue.job(id)))
+        }
+        (Method::Get, ["jobs", id]) => match queue.job(job_id(id)?) {
+            Some(job) => Ok(Reply::json(200, &job)),
+            None => Err(Reply::error(404, format!("No job {}", id))),
+        },
+        (Method::Delete, ["jobs", id]) => {
+            let id = job_id(id)?;
+            queue
+                .cancel(id)
+                .map_err(|e| Reply::error(409, format!("{:#}", e)))?;
+            Ok(Reply::json(200, &queue.job(id)))
+        }
+        (Method::Post, ["jobs", id, "move"]) => {
+            let id = job_id(id)?;
+            let to = query
+                .get("to")
+                .context("Missing \"to\", the new position")
+                .and_then(|to| to.parse().context("Position must be a number"))
+                .map_err(bad_request)?;
+            queue
+                .move_job(id, to)
+                .map_err(|e| Reply::error(409, format!("{:#}", e)))?;
+            Ok(Reply::json(200, &queue.status()))
+        }
+        (Method::Post, ["pause"]) => {
+            queue.pause();
+            Ok(Reply::json(200, &queue.status()))
+        }
+        (Method::Post, ["resume"]) => {
+            queue.resume();
+            Ok(Reply::json(200, &queue.status()))
+        }
+        _ => Err(Reply::error(
+            404,
+            format!("No endpoint {} {}", request.method(), path),
+        )),
+    }
+}
+
+/// Job name and strips from an upload
+fn job_strips(
+    body: &[u8],
+    query: &HashMap<String, String>,
+    uploads: &Uploads,
+) -> Result<(String, Vec<QueuedStrip>)> {
+    let width = uploads.profile.dots_per_row;
+    let copies = match query.get("copies") {
+        Some(copies) => copies.parse().context("Copies must be a number")?,
+        None => uploads.copies,
+    };
+    let transform: Transform = match query.get("transform") {
+        Some(transform) => transform.parse()?,
+        None => Transform::default(),
+    };
+
+    if body.starts_with(PNG_SIGNATURE) {
+        let (w, luma) = read_luma(body)?;
+        let strip = QueuedStrip {
+            index: 0,
+            dots: strip_dots(w, luma, &transform, width, uploads.dither)?,
+            copies,
+            label: None,
+            density: None,
+            cut: None,
+        };
+        let name = query
+            .get("name")
+            .cloned()
+            .unwrap_or_else(|| "upload".into());
+        return Ok((name, vec![strip]));
+    }
+
+    let json = body.trim_ascii_start().starts_with(b"{");
+    let manifest = Manifest::read(body, json, Path::new("")).context("Parsing manifest")?;
+    manifest.check(&uploads.profile)?;
+
+    let mut strips = vec![];
+    for (pos, entry) in manifest.strips.iter().enumerate() {
+        let path = entry.path.display().to_string();
+        let file = upload_path(&uploads.dir, &entry.path)?;
+        let (w, luma) = load_luma(&file).context(path.clone())?;
+        let dots = strip_dots(
+            w,
+            luma,
+            &entry.transform.then(&transform),
+            width,
+            uploads.dither,
+        )
+        .context(path)?;
+        strips.push(QueuedStrip {
+            index: entry.index.unwrap_or(pos),
+            dots,
+            copies: entry.copies.unwrap_or(copies),
+            label: entry.label.clone(),
+            density: entry.density,
+            cut: entry.cut,
+        });
+    }
+    let name = query
+        .get("name")
+        .or(manifest.name.as_ref())
+        .cloned()
+        .unwrap_or_else(|| "manifest".into());
+    Ok((name, strips))
+}
+
+/// `path` from a manifest, in `dir`. Absolute paths, `..` and links leading out of `dir` are
+/// refused, so clients can only print files put there for them.
+fn upload_path(dir: &Path, path: &Path) -> Result<PathBuf> {
+    ensure!(
+        path.components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir)),
+        "Strip {} must be a path inside the upload directory",
+        path.display()
+    );
+    let file = dir.join(path);
+    let real = file
+        .canonicalize()
+        .with_context(|| file.display().to_string())?;
+    ensure!(
+        real.starts_with(dir.canonicalize()?),
+        "Strip {} leads out of the upload directory",
+        path.display()
+    );
+    Ok(file)
+}
+
+/// Dither luminance `w` pixels per row into a strip `width` dots wide, after turning it a
+/// quarter if it is on its side, then `transform`
+fn strip_dots(
+    w: usize,
+    luma: Vec<f32>,
+    transform: &Transform,
+    width: usize,
+    dither: Dither,
+) -> Result<Vec<bool>> {
+    let transform = if on_its_side(w, luma.len() / w, width) {
+        Transform(vec![Step::Rotate90]).then(transform)
+    } else {
+        transform.clone()
+    };
+    let (w, luma) = transform.apply(w, luma);
+    ensure!(
+        w == width,
+        "Image must be {} pixels wide after transforms, got {}",
+        width,
+        w
+    );
+    Ok(dither.apply(&luma, width))
+}
+
+/// Parameters of a URL query, percent-decoded
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes, and `+` as a space
+fn percent_decode(s: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => match rest
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+            {
+                Some(decoded) => {
+                    bytes.push(decoded);
+                    rest = &rest[2..];
+                }
+                None => bytes.push(b),
+            },
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::builtin_profiles, save_bitmap_png};
+
+    const WIDTH: usize = 384;
+
+    fn bitmap() -> Vec<bool> {
+        (0..WIDTH * 4).map(|i| (i / 3).is_multiple_of(2)).collect()
+    }
+
+    /// A directory of uploads holding `strip.png`, a link to `secret.png` beside the directory,
+    /// and `Uploads` for it
+    fn uploads(name: &str) -> (PathBuf, Uploads) {
+        let root =
+            std::env::temp_dir().join(format!("print-serve-{}-{}", std::process::id(), name));
+        let dir = root.join("uploads");
+        fs::create_dir_all(&dir).unwrap();
+        save_bitmap_png(dir.join("strip.png"), WIDTH, &bitmap()).unwrap();
+        save_bitmap_png(root.join("secret.png"), WIDTH, &bitmap()).unwrap();
+        std::os::unix::fs::symlink(root.join("secret.png"), dir.join("link.png")).unwrap();
+
+        let uploads = Uploads {
+            profile: builtin_profiles().remove(0),
+            dither: Dither::Threshold,
+            copies: 1,
+            dir,
+        };
+        (root, uploads)
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn manifest_paths_stay_in_the_upload_directory() {
+        let (root, uploads) = uploads("paths");
+        let secret = root.join("secret.png");
+        let job = |path: &str, query: &HashMap<String, String>| {
+            let manifest = format!("(strips: [(path: {:?})])", path);
+            job_strips(manifest.as_bytes(), query, &uploads)
+        };
+        let none = HashMap::new();
+
+        let results = [
+            job("strip.png", &none),
+            job("./strip.png", &none),
+            job("../secret.png", &none),
+            job(secret.to_str().unwrap(), &none),
+            job("link.png", &none),
+            // Clients can't pick the directory any more
+            job("secret.png", &query(&[("dir", root.to_str().unwrap())])),
+        ];
+        fs::remove_dir_all(&root).unwrap();
+
+        let [strip, dot, parent, absolute, link, dir] = results;
+        let (name, strips) = strip.unwrap();
+        assert_eq!(name, "manifest");
+        assert_eq!(strips[0].dots, bitmap());
+        assert!(dot.is_ok());
+        for (result, what) in [
+            (parent, ".."),
+            (absolute, "absolute"),
+            (link, "link"),
+            (dir, "dir"),
+        ] {
+            assert!(result.is_err(), "{}", what);
+        }
+    }
+
+    #[test]
+    fn pngs_are_queued_as_uploaded() {
+        let (root, uploads) = uploads("png");
+        let body = fs::read(uploads.dir.join("strip.png")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let query = query(&[("name", "poster"), ("copies", "3"), ("transform", "invert")]);
+        let (name, strips) = job_strips(&body, &query, &uploads).unwrap();
+        assert_eq!(name, "poster");
+        assert_eq!(strips.len(), 1);
+        assert_eq!(strips[0].copies, 3);
+        let inverted: Vec<bool> = bitmap().iter().map(|b| !b).collect();
+        assert_eq!(strips[0].dots, inverted);
+    }
+
+    #[test]
+    fn addresses_parse() {
+        let table = [
+            ("8058", Listen::Tcp("127.0.0.1:8058".into())),
+            ("0.0.0.0:80", Listen::Tcp("0.0.0.0:80".into())),
+            (
+                "unix:/run/print.sock",
+                Listen::Unix("/run/print.sock".into()),
+            ),
+        ];
+        for (text, listen) in table {
+            assert_eq!(text.parse::<Listen>().unwrap(), listen, "{}", text);
+        }
+        assert!("localhost".parse::<Listen>().is_err());
+        assert!("unix:".parse::<Listen>().is_err());
+    }
+
+    #[test]
+    fn queries_are_decoded() {
+        let query = parse_query("name=my+job%21&copies=2&flag&bad=%zz");
+        assert_eq!(query["name"], "my job!");
+        assert_eq!(query["copies"], "2");
+        assert_eq!(query["flag"], "");
+        assert_eq!(query["bad"], "%zz");
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::bail;
+
+use crate::{
+    error::{Error, Result},
+    mock::{MockEvent, MockPrinter},
+    status::Transport,
+    usb::{UsbPrinter, UsbSelector},
+};
+
+/// Default port for raw ("JetDirect") network printers
+pub const RAW_TCP_PORT: u16 = 9100;
+
+/// Destination for the ESC/POS byte stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sink {
+    /// Printer on the USB bus: the one selected, or the first found
+    Usb(Option<UsbSelector>),
+    /// Standard output
+    Stdout,
+    /// Regular file; created or truncated
+    File(PathBuf),
+    /// Raw TCP printer, as `host:port`
+    Tcp(String),
+    /// Serial or USB CDC tty device. Line settings (baud etc.) are left as configured by the OS.
+    /// Write-only, since reads would block without a timeout.
+    Serial(PathBuf),
+    /// Mock printer following a status script, saving what it prints as a PNG
+    Mock(PathBuf, Vec<MockEvent>),
+}
+
+impl FromStr for Sink {
+    type Err = anyhow::Error;
+
+    /// Parses `usb[:BUS:ADDRESS|:VID:PID|:SERIAL]`, `stdout` (or `-`), `file:PATH`,
+    /// `tcp:HOST[:PORT]`, `serial:PATH` or `mock:PATH[,STATE@N[xCOUNT]...]`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "usb" {
+            return Ok(Self::Usb(None));
+        }
+
+        if s == "stdout" || s == "-" {
+            return Ok(Self::Stdout);
+        }
+
+        let Some((kind, rest)) = s.split_once(':') else {
+            bail!("Unknown output \"{}\"; expected usb[:DEVICE], stdout, file:PATH, tcp:HOST[:PORT], serial:PATH or mock:PATH[,EVENTS]", s);
+        };
+
+        if rest.is_empty() {
+            bail!("Output \"{}\" is missing its target", s);
+        }
+
+        match kind {
+            "usb" => Ok(Self::Usb(Some(rest.parse()?))),
+            "file" => Ok(Self::File(rest.into())),
+            "serial" => Ok(Self::Serial(rest.into())),
+            "mock" => {
+                let mut parts = rest.split(',');
+                let path = parts.next().unwrap_or_default();
+                let script = parts.map(str::parse).collect::<anyhow::Result<_>>()?;
+                Ok(Self::Mock(path.into(), script))
+            }
+            "tcp" => {
+                // Bare hosts (and bracketed IPv6 addresses) get the default port
+                let has_port = match rest.rsplit_once(':') {
+                    Some((host, port)) => {
+                        port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+                    }
+                    None => false,
+                };
+
+                if has_port {
+                    Ok(Self::Tcp(rest.into()))
+                } else {
+                    Ok(Self::Tcp(format!("{}:{}", rest, RAW_TCP_PORT)))
+                }
+            }
+            _ => bail!("Unknown output kind \"{}\"", kind),
+        }
+    }
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Self::Usb(None)
+    }
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usb(None) => write!(f, "usb"),
+            Self::Usb(Some(selector)) => write!(f, "usb:{}", selector),
+            Self::Stdout => write!(f, "stdout"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp:{}", addr),
+            Self::Serial(path) => write!(f, "serial:{}", path.display()),
+            Self::Mock(path, _) => write!(f, "mock:{}", path.display()),
+        }
+    }
+}
+
+impl Sink {
+    /// This sink, or `device` if it is the first USB printer found
+    pub fn or_device(self, device: Option<&UsbSelector>) -> Self {
+        match self {
+            Self::Usb(None) => Self::Usb(device.cloned()),
+            sink => sink,
+        }
+    }
+
+    /// Open the sink for writing to a printer `width` dots wide. A libusb context is created in
+    /// `ctx` on demand, so non-USB sinks work on machines without libusb. A printer that can't be
+    /// found or connected to is `Error::PrinterOffline`. Transfers time out after `timeout`.
+    pub fn open<'ctx>(
+        &self,
+        ctx: &'ctx mut Option<rusb::Context>,
+        width: usize,
+        timeout: Duration,
+    ) -> Result<Box<dyn Transport + 'ctx>> {
+        Ok(match self {
+            Self::Usb(selector) => {
+                let ctx = match ctx {
+                    Some(ctx) => ctx,
+                    None => ctx.insert(rusb::Context::new().map_err(|e| {
+                        Error::PrinterOffline(format!("Initializing libusb: {}", e))
+                    })?),
+                };
+                Box::new(UsbPrinter::open(ctx, selector.as_ref(), timeout)?)
+            }
+            Self::Stdout => Box::new(io::stdout()),
+            Self::File(path) => Box::new(File::create(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("Creating {}: {}", path.display(), e))
+            })?),
+            Self::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)
+                    .map_err(|e| Error::PrinterOffline(format!("Connecting to {}: {}", addr, e)))?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Box::new(stream)
+            }
+            Self::Serial(path) => {
+                Box::new(OpenOptions::new().write(true).open(path).map_err(|e| {
+                    Error::PrinterOffline(format!("Opening {}: {}", path.display(), e))
+                })?)
+            }
+            Self::Mock(path, script) => {
+                Box::new(MockPrinter::new(width, script.clone(), Some(path.clone())))
+            }
+        })
+    }
+}
+
+impl Transport for TcpStream {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        self.write_all(request)?;
+
+        let mut reply = [0];
+        match self.read(&mut reply) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(Some(reply[0])),
+            // Read timeouts show up as WouldBlock on some platforms
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockState;
+
+    #[test]
+    fn parses_every_form() {
+        let disconnect = MockEvent {
+            state: MockState::Disconnect,
+            at: 3,
+            count: 2,
+        };
+        for (s, sink) in [
+            ("usb", Sink::Usb(None)),
+            (
+                "usb:1:4",
+                Sink::Usb(Some(UsbSelector::Address { bus: 1, address: 4 })),
+            ),
+            (
+                "usb:A1B2",
+                Sink::Usb(Some(UsbSelector::Serial("A1B2".into()))),
+            ),
+            ("stdout", Sink::Stdout),
+            ("-", Sink::Stdout),
+            ("file:out.bin", Sink::File("out.bin".into())),
+            ("file:C:/out.bin", Sink::File("C:/out.bin".into())),
+            ("tcp:printer", Sink::Tcp("printer:9100".into())),
+            ("tcp:printer:9101", Sink::Tcp("printer:9101".into())),
+            ("tcp:10.0.0.5", Sink::Tcp("10.0.0.5:9100".into())),
+            ("tcp:[::1]:9100", Sink::Tcp("[::1]:9100".into())),
+            ("tcp:[::1]:9101", Sink::Tcp("[::1]:9101".into())),
+            ("tcp:[::1]", Sink::Tcp("[::1]:9100".into())),
+            ("serial:/dev/ttyUSB0", Sink::Serial("/dev/ttyUSB0".into())),
+            ("mock:paper.png", Sink::Mock("paper.png".into(), vec![])),
+            (
+                "mock:paper.png,disconnect@3x2",
+                Sink::Mock("paper.png".into(), vec![disconnect]),
+            ),
+        ] {
+            assert_eq!(s.parse::<Sink>().unwrap(), sink, "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_forms() {
+        for s in ["usb:", "file:", "tcp:", "serial:", "mock:"] {
+            let e = s.parse::<Sink>().unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("Output \"{}\" is missing its target", s)
+            );
+        }
+        for s in ["", "printer", "lpt:1", "mock:paper.png,jam@1"] {
+            assert!(s.parse::<Sink>().is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::reconnect::Backoff;
+
+/// `DLE EOT n`: real-time status, answered even while the printer is stopped
+pub const DLE_EOT: &[u8] = b"\x10\x04";
+/// `GS r n`: status, answered once everything sent before it has been processed
+pub const GS_R: &[u8] = b"\x1dr";
+
+/// How often to ask a stopped printer whether it is ready again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An output to a printer, which may be able to read replies back
+pub trait Transport: Write {
+    /// Send `request` and read the one byte reply. `None` if this transport can't read.
+    fn transact(&mut self, _request: &[u8]) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    /// Connect to the printer again after it went away, e.g. was unplugged or switched off and
+    /// on. Fails with `ErrorKind::Unsupported` if this transport can't.
+    fn reconnect(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "This output can't reconnect",
+        ))
+    }
+}
+
+impl Transport for io::Stdout {}
+
+impl Transport for std::fs::File {}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        (**self).transact(request)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        (**self).reconnect()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        (**self).transact(request)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        (**self).reconnect()
+    }
+}
+
+/// Status request a printer understands
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusQuery {
+    /// `DLE EOT 2` and `DLE EOT 4`: cover, error and paper sensors
+    DleEot,
+    /// `GS r 1`: paper sensors only, for printers without real-time commands
+    GsR,
+}
+
+impl StatusQuery {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DleEot => "dle-eot",
+            Self::GsR => "gs-r",
+        }
+    }
+
+    /// Ask the printer for its status. `None` if the transport can't read replies.
+    pub fn query<T: Transport + ?Sized>(&self, transport: &mut T) -> io::Result<Option<Status>> {
+        match self {
+            Self::DleEot => {
+                let Some(cause) = transport.transact(&[DLE_EOT, &[2]].concat())? else {
+                    return Ok(None);
+                };
+                let Some(paper) = transport.transact(&[DLE_EOT, &[4]].concat())? else {
+                    return Ok(None);
+                };
+                Ok(Some(Status {
+                    cover_open: cause & 0x04 != 0,
+                    paper_out: cause & 0x20 != 0 || paper & 0x60 != 0,
+                    paper_near_end: paper & 0x0c != 0,
+                    error: cause & 0x40 != 0,
+                    offline: false,
+                }))
+            }
+            Self::GsR => {
+                let Some(paper) = transport.transact(&[GS_R, &[1]].concat())? else {
+                    return Ok(None);
+                };
+                Ok(Some(Status {
+                    paper_out: paper & 0x0c != 0,
+                    paper_near_end: paper & 0x03 != 0,
+                    ..Status::default()
+                }))
+            }
+        }
+    }
+}
+
+impl FromStr for StatusQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "dle-eot" => Self::DleEot,
+            "gs-r" => Self::GsR,
+            _ => bail!("Unknown status query \"{}\"; expected dle-eot or gs-r", s),
+        })
+    }
+}
+
+/// Printer state, as far as the printer reports it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    pub cover_open: bool,
+    pub paper_out: bool,
+    /// The roll is running low; printing continues
+    pub paper_near_end: bool,
+    /// Cutter jam, overheating or another error the printer stopped for
+    pub error: bool,
+    /// The printer stopped answering status requests
+    pub offline: bool,
+}
+
+impl Status {
+    /// Whether the printer can print
+    pub fn ready(&self) -> bool {
+        !(self.cover_open || self.paper_out || self.error || self.offline)
+    }
+
+    /// What is wrong, for messages
+    pub fn describe(&self) -> String {
+        let problems: Vec<&str> = [
+            (self.cover_open, "cover open"),
+            (self.paper_out, "paper out"),
+            (self.error, "printer error"),
+            (self.offline, "not responding"),
+            (self.paper_near_end, "paper near end"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+
+        if problems.is_empty() {
+            "ready".into()
+        } else {
+            problems.join(", ")
+        }
+    }
+}
+
+/// Sends a job one band at a time, checking the printer's status after each, or after every
+/// few with `poll_every`. Encoders flush after every band, so a flush marks the end of one.
+/// When the printer stops (paper out, cover open) the job pauses until it is ready again, then
+/// the bands since the last check are sent again from their start, since part of them may have
+/// been printed on nothing.
+pub struct Monitor<T: Transport, F: FnMut(&Status)> {
+    transport: T,
+    /// `None` when the printer can't report its status
+    query: Option<StatusQuery>,
+    /// Called when the printer stops, and again whenever its status changes while paused
+    on_pause: F,
+    /// Whether the printer has answered a status request yet
+    answered: bool,
+    /// Bands between status checks
+    poll_every: usize,
+    /// Bytes of the bands sent since the last check, then of the band being collected
+    band: Vec<u8>,
+    /// Bytes of `band` already sent
+    sent: usize,
+    /// Where in `band` the last band sent starts
+    last: usize,
+    /// Bands sent since the last check
+    unchecked: usize,
+    /// Bands sent since the last check when sending failed, which the printer may have lost;
+    /// sent again if it is reconnected, dropped once anything else is written
+    lost: Vec<u8>,
+}
+
+impl<T: Transport, F: FnMut(&Status)> Monitor<T, F> {
+    pub fn new(transport: T, query: Option<StatusQuery>, on_pause: F) -> Self {
+        Self {
+            transport,
+            query,
+            on_pause,
+            answered: false,
+            poll_every: 1,
+            band: vec![],
+            sent: 0,
+            last: 0,
+            unchecked: 0,
+            lost: vec![],
+        }
+    }
+
+    /// Check the printer's status after every `bands` bands instead of every one, for printers
+    /// that print slower when asked often
+    pub fn poll_every(mut self, bands: usize) -> Self {
+        self.poll_every = bands.max(1);
+        self
+    }
+
+    /// Current printer status, or `None` if it isn't known
+    pub fn status(&mut self) -> io::Result<Option<Status>> {
+        let Some(query) = self.query else {
+            return Ok(None);
+        };
+
+        match query.query(&mut self.transport) {
+            Ok(Some(status)) => {
+                self.answered = true;
+                Ok(Some(status))
+            }
+            // A printer that has answered before is busy or stopped, e.g. `GS r` waits for
+            // paper
+            Err(e) if e.kind() == io::ErrorKind::TimedOut && self.answered => Ok(Some(Status {
+                offline: true,
+                ..Status::default()
+            })),
+            // No back channel, or the printer doesn't understand the query; don't keep asking
+            Ok(None) => {
+                self.query = None;
+                Ok(None)
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                self.query = None;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Connect to the printer again after it went away, trying as `backoff` says. Whatever was
+    /// left of the band being sent is dropped, since the job has to start again from one the
+    /// printer took. Bands sent since the last status check are sent again first, since the
+    /// printer may have lost them too.
+    pub fn reconnect(
+        &mut self,
+        backoff: &Backoff,
+        on_fail: impl FnMut(&io::Error, Duration),
+    ) -> io::Result<()> {
+        self.clear();
+        backoff.reconnect(&mut self.transport, on_fail)?;
+
+        self.band = std::mem::take(&mut self.lost);
+        if self.band.is_empty() {
+            return Ok(());
+        }
+        let result = self.resend();
+        self.clear();
+        result
+    }
+
+    /// Send the kept bands again and wait for the printer to take them
+    fn resend(&mut self) -> io::Result<()> {
+        self.transport.write_all(&self.band)?;
+        self.transport.flush()?;
+        self.check()
+    }
+
+    /// Wait until the printer reports it is ready
+    pub fn wait_ready(&mut self) -> io::Result<()> {
+        match self.status()? {
+            Some(status) if !status.ready() => self.pause(status),
+            _ => Ok(()),
+        }
+    }
+
+    /// Report that the printer stopped with `status`, then poll until it is ready again
+    fn pause(&mut self, mut status: Status) -> io::Result<()> {
+        (self.on_pause)(&status);
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            match self.status()? {
+                Some(new) if !new.ready() => {
+                    if new != status {
+                        (self.on_pause)(&new);
+                        status = new;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Send the collected band. If sending fails, it is dropped, not sent again with the next
+    /// one, and the bands before it since the last check are kept for `reconnect`.
+    fn send_band(&mut self) -> io::Result<()> {
+        let result = self.try_send_band();
+        if result.is_err() {
+            self.lose();
+        }
+        result
+    }
+
+    /// Send the collected band. Every `poll_every` bands, check the printer's status, and send
+    /// every band since the last check again after each pause, until the printer has taken them.
+    fn try_send_band(&mut self) -> io::Result<()> {
+        self.last = self.sent;
+        self.transport.write_all(&self.band[self.sent..])?;
+        self.transport.flush()?;
+        self.sent = self.band.len();
+        self.unchecked += 1;
+        if self.unchecked < self.poll_every {
+            return Ok(());
+        }
+        self.check()
+    }
+
+    /// Wait for the printer to take every band since the last check, sending them again after
+    /// each pause. They are kept if it fails.
+    fn check(&mut self) -> io::Result<()> {
+        while let Some(status) = self.status()?.filter(|status| !status.ready()) {
+            self.pause(status)?;
+            self.transport.write_all(&self.band)?;
+            self.transport.flush()?;
+        }
+        self.clear();
+        Ok(())
+    }
+
+    /// Keep the bands sent since the last check for `reconnect`, after sending failed. The
+    /// last one is dropped, since the job sends it again when it resumes.
+    fn lose(&mut self) {
+        self.band.truncate(self.last);
+        self.lost = std::mem::take(&mut self.band);
+        self.clear();
+    }
+
+    /// Forget the bands kept for sending again
+    fn clear(&mut self) {
+        self.band.clear();
+        self.sent = 0;
+        self.last = 0;
+        self.unchecked = 0;
+    }
+}
+
+impl<T: Transport, F: FnMut(&Status)> Write for Monitor<T, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lost.clear();
+        self.band.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.band.len() > self.sent {
+            self.send_band()
+        } else if self.unchecked > 0 {
+            // Nothing new, so the image or job may be over: check the bands a poll hasn't
+            // covered yet
+            let result = self.check();
+            if result.is_err() {
+                self.lose();
+            }
+            result
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emulator::Emulator,
+        mock::{MockEvent, MockPrinter},
+        raster::{pack_row, Encoder, RasterCommand},
+    };
+
+    const WIDTH: usize = 64;
+    const BAND_ROWS: usize = 8;
+
+    /// Striped test image, six bands long
+    fn bitmap() -> Vec<bool> {
+        (0..WIDTH * 6 * BAND_ROWS)
+            .map(|i| (i % WIDTH + i / WIDTH / 3).is_multiple_of(5))
+            .collect()
+    }
+
+    /// Send a bitmap as `GS v 0` bands of `BAND_ROWS` rows, flushing after each
+    fn send(printer: impl Write, bitmap: &[bool]) {
+        let mut encoder =
+            Encoder::with_band_rows(printer, RasterCommand::Raster, WIDTH, Some(BAND_ROWS))
+                .unwrap();
+        for row in bitmap.chunks(WIDTH) {
+            encoder.push_row(&pack_row(row)).unwrap();
+        }
+        encoder.finish().unwrap();
+    }
+
+    /// Paper a mock printer running `script` prints the test image on, and the pauses reported
+    fn print_job(script: &str, poll_every: usize) -> (Vec<bool>, Vec<Status>) {
+        let script = script.split(',').map(|e| e.parse().unwrap()).collect();
+        let mut mock = MockPrinter::new(WIDTH, script, None);
+        let mut pauses = vec![];
+        let mut monitor = Monitor::new(&mut mock, Some(StatusQuery::DleEot), |status| {
+            pauses.push(*status)
+        })
+        .poll_every(poll_every);
+        send(&mut monitor, &bitmap());
+        monitor.flush().unwrap();
+        drop(monitor);
+
+        (mock.emulator().unwrap().paper(), pauses)
+    }
+
+    fn uninterrupted() -> Vec<bool> {
+        let mut bytes = vec![];
+        send(&mut bytes, &bitmap());
+        Emulator::decode(WIDTH, &bytes).unwrap().paper()
+    }
+
+    #[test]
+    fn pauses_until_ready_and_resends_the_band() {
+        let (paper, pauses) = print_job("paper-out@2", 1);
+        assert_eq!(
+            pauses,
+            [Status {
+                paper_out: true,
+                ..Status::default()
+            }]
+        );
+        assert!(paper == uninterrupted());
+
+        let (paper, pauses) = print_job("cover-open@4", 1);
+        assert_eq!(
+            pauses,
+            [Status {
+                cover_open: true,
+                ..Status::default()
+            }]
+        );
+        assert!(paper == uninterrupted());
+    }
+
+    #[test]
+    fn resends_every_unchecked_band() {
+        // The first check comes after 3 bands, all of which went nowhere
+        let (paper, pauses) = print_job("paper-out@1", 3);
+        assert_eq!(pauses.len(), 1);
+        assert!(paper == uninterrupted());
+    }
+
+    #[test]
+    fn polls_every_n_bands() {
+        // Six bands: polled after bands 3 and 6, or after band 4 and then for the last two
+        // when the job ends
+        for (poll_every, polls) in [(1, 6), (3, 2), (4, 2), (6, 1), (10, 1)] {
+            let mut mock = MockPrinter::new(WIDTH, vec![], None);
+            let mut monitor =
+                Monitor::new(&mut mock, Some(StatusQuery::DleEot), |_| {}).poll_every(poll_every);
+            send(&mut monitor, &bitmap());
+            monitor.flush().unwrap();
+            drop(monitor);
+            assert_eq!(mock.polls(), polls, "every {} bands", poll_every);
+        }
+    }
+
+    #[test]
+    fn near_end_does_not_pause() {
+        let (paper, pauses) = print_job("near-end@1x10", 1);
+        assert!(pauses.is_empty());
+        assert!(paper == uninterrupted());
+    }
+
+    #[test]
+    fn mock_events_parse() {
+        assert_eq!(
+            "paper-out@3x2".parse::<MockEvent>().unwrap(),
+            MockEvent {
+                state: crate::mock::MockState::PaperOut,
+                at: 3,
+                count: 2
+            }
+        );
+        assert_eq!(
+            "fail@1".parse::<MockEvent>().unwrap().state,
+            crate::mock::MockState::Fail
+        );
+        assert!("paper-out".parse::<MockEvent>().is_err());
+        assert!("jam@1".parse::<MockEvent>().is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// One change to an image's orientation or tone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Turn a quarter clockwise, so the left edge prints first
+    Rotate90,
+    /// Turn upside down, so the bottom prints first
+    Rotate180,
+    /// Turn a quarter anticlockwise, so the right edge prints first
+    Rotate270,
+    /// Swap left and right
+    MirrorH,
+    /// Swap top and bottom
+    MirrorV,
+    /// Swap black and white
+    Invert,
+}
+
+/// Every step, in the order they are listed in help text
+pub const ALL_STEPS: [Step; 6] = [
+    Step::Rotate90,
+    Step::Rotate180,
+    Step::Rotate270,
+    Step::MirrorH,
+    Step::MirrorV,
+    Step::Invert,
+];
+
+impl Step {
+    /// Name as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rotate90 => "rotate-90",
+            Self::Rotate180 => "rotate-180",
+            Self::Rotate270 => "rotate-270",
+            Self::MirrorH => "mirror-h",
+            Self::MirrorV => "mirror-v",
+            Self::Invert => "invert",
+        }
+    }
+
+    /// Apply to luminance `width` pixels per row, returning the new width and pixels
+    fn apply(&self, width: usize, luma: &[f32]) -> (usize, Vec<f32>) {
+        let height = luma.len() / width;
+        let at = |x: usize, y: usize| luma[y * width + x];
+        match self {
+            Self::Rotate90 => (
+                height,
+                (0..width)
+                    .flat_map(|y| (0..height).map(move |x| at(y, height - 1 - x)))
+                    .collect(),
+            ),
+            Self::Rotate180 => (width, luma.iter().rev().copied().collect()),
+            Self::Rotate270 => (
+                height,
+                (0..width)
+                    .flat_map(|y| (0..height).map(move |x| at(width - 1 - y, x)))
+                    .collect(),
+            ),
+            Self::MirrorH => (
+                width,
+                luma.chunks(width)
+                    .flat_map(|row| row.iter().rev().copied())
+                    .collect(),
+            ),
+            Self::MirrorV => (width, luma.chunks(width).rev().flatten().copied().collect()),
+            Self::Invert => (width, luma.iter().map(|l| 1. - l).collect()),
+        }
+    }
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match ALL_STEPS.iter().find(|step| step.name() == s) {
+            Some(&step) => Ok(step),
+            None => {
+                let names: Vec<&str> = ALL_STEPS.iter().map(Step::name).collect();
+                bail!(
+                    "Unknown transform \"{}\"; expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Steps applied to an image in order, written as their names separated by commas, e.g.
+/// `rotate-90,invert`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Transform(pub Vec<Step>);
+
+impl Transform {
+    /// Whether the image is left as it is
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// This transform followed by `next`
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform(self.0.iter().chain(&next.0).copied().collect())
+    }
+
+    /// Apply to luminance (0 = black, 1 = white) `width` pixels per row, returning the new width
+    /// and pixels
+    pub fn apply(&self, width: usize, luma: Vec<f32>) -> (usize, Vec<f32>) {
+        self.0.iter().fold((width, luma), |(width, luma), step| {
+            step.apply(width, &luma)
+        })
+    }
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.is_empty() || s == "none" {
+            return Ok(Self::default());
+        }
+        s.split(',')
+            .map(|step| step.trim().parse())
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Transform> for String {
+    fn from(transform: Transform) -> Self {
+        transform.to_string()
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = self.0.iter().map(Step::name).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// Whether an image `width` by `height` is on its side for a printer `dots` wide: as tall as the
+/// printer is wide, but not as wide. Such images are turned a quarter clockwise.
+pub fn on_its_side(width: usize, height: usize, dots: usize) -> bool {
+    width != dots && height == dots
+}
+
+/// Parse `--rotate`: 90, 180 or 270 degrees clockwise
+pub fn parse_rotation(s: &str) -> anyhow::Result<Step> {
+    Ok(match s {
+        "90" => Step::Rotate90,
+        "180" => Step::Rotate180,
+        "270" => Step::Rotate270,
+        _ => bail!("Rotation must be 90, 180 or 270 degrees, got \"{}\"", s),
+    })
+}
+
+/// Parse `--mirror`: horizontal (h) or vertical (v)
+pub fn parse_mirror(s: &str) -> anyhow::Result<Step> {
+    Ok(match s {
+        "horizontal" | "h" => Step::MirrorH,
+        "vertical" | "v" => Step::MirrorV,
+        _ => bail!("Mirror must be horizontal or vertical, got \"{}\"", s),
+    })
+}
+
+/// Parse `FILE=TRANSFORM`, a transform for one file
+pub fn parse_file_transform(s: &str) -> anyhow::Result<(PathBuf, Transform)> {
+    let (path, transform) = s
+        .rsplit_once('=')
+        .with_context(|| format!("Expected FILE=TRANSFORM, got \"{}\"", s))?;
+    Ok((path.into(), transform.parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3 by 2 image with a distinct value per pixel:
+    ///
+    /// ```text
+    /// a b c
+    /// d e f
+    /// ```
+    fn image() -> Vec<f32> {
+        (0..6).map(|i| i as f32 / 10.).collect()
+    }
+
+    /// Pixels of `image` by letter
+    fn pixels(letters: &str) -> Vec<f32> {
+        letters
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| (c as u8 - b'a') as f32 / 10.)
+            .collect()
+    }
+
+    #[test]
+    fn steps_move_pixels() {
+        let table = [
+            (Step::Rotate90, 2, "da eb fc"),
+            (Step::Rotate180, 3, "fed cba"),
+            (Step::Rotate270, 2, "cf be ad"),
+            (Step::MirrorH, 3, "cba fed"),
+            (Step::MirrorV, 3, "def abc"),
+        ];
+        for (step, width, expected) in table {
+            assert_eq!(
+                step.apply(3, &image()),
+                (width, pixels(expected)),
+                "{}",
+                step.name()
+            );
+        }
+
+        let (width, inverted) = Step::Invert.apply(3, &image());
+        assert_eq!(width, 3);
+        let expected: Vec<f32> = image().iter().map(|l| 1. - l).collect();
+        assert_eq!(inverted, expected);
+    }
+
+    #[test]
+    fn steps_undo_each_other() {
+        let table = [
+            "rotate-90,rotate-270",
+            "rotate-180,rotate-180",
+            "rotate-90,rotate-90,rotate-90,rotate-90",
+            "mirror-h,mirror-h",
+            "mirror-v,mirror-h,rotate-180",
+            "invert,invert",
+        ];
+        for text in table {
+            let transform: Transform = text.parse().unwrap();
+            let (width, luma) = transform.apply(3, image());
+            assert_eq!(width, 3, "{}", text);
+            // Inverting twice may round
+            assert!(
+                luma.iter().zip(image()).all(|(a, b)| (a - b).abs() < 1e-6),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn transforms_parse() {
+        let table = [
+            ("", vec![]),
+            ("none", vec![]),
+            ("invert", vec![Step::Invert]),
+            ("rotate-90,invert", vec![Step::Rotate90, Step::Invert]),
+            (" mirror-h , mirror-v", vec![Step::MirrorH, Step::MirrorV]),
+        ];
+        for (text, steps) in table {
+            assert_eq!(
+                text.parse::<Transform>().unwrap(),
+                Transform(steps),
+                "{}",
+                text
+            );
+        }
+        assert!("rotate-45".parse::<Transform>().is_err());
+        assert!("invert,".parse::<Transform>().is_err());
+
+        for step in ALL_STEPS {
+            assert_eq!(step.name().parse::<Step>().unwrap(), step);
+        }
+    }
+
+    #[test]
+    fn transforms_display_as_parsed() {
+        for text in ["none", "rotate-270", "mirror-v,invert,rotate-90"] {
+            assert_eq!(text.parse::<Transform>().unwrap().to_string(), text);
+        }
+
+        let transform: Transform = ron::from_str("\"rotate-90,invert\"").unwrap();
+        assert_eq!(transform, Transform(vec![Step::Rotate90, Step::Invert]));
+        assert_eq!(ron::to_string(&transform).unwrap(), "\"rotate-90,invert\"");
+    }
+
+    #[test]
+    fn options_parse() {
+        assert_eq!(parse_rotation("90").unwrap(), Step::Rotate90);
+        assert_eq!(parse_rotation("180").unwrap(), Step::Rotate180);
+        assert_eq!(parse_rotation("270").unwrap(), Step::Rotate270);
+        assert!(parse_rotation("360").is_err());
+
+        assert_eq!(parse_mirror("h").unwrap(), Step::MirrorH);
+        assert_eq!(parse_mirror("horizontal").unwrap(), Step::MirrorH);
+        assert_eq!(parse_mirror("v").unwrap(), Step::MirrorV);
+        assert_eq!(parse_mirror("vertical").unwrap(), Step::MirrorV);
+        assert!(parse_mirror("diagonal").is_err());
+    }
+
+    #[test]
+    fn file_transforms_parse() {
+        let (path, transform) = parse_file_transform("strips/a=b.png=rotate-90,invert").unwrap();
+        assert_eq!(path, PathBuf::from("strips/a=b.png"));
+        assert_eq!(transform, Transform(vec![Step::Rotate90, Step::Invert]));
+
+        assert!(parse_file_transform("strip.png").is_err());
+        assert!(parse_file_transform("strip.png=sideways").is_err());
+    }
+
+    #[test]
+    fn landscape_strips_are_on_their_side() {
+        assert!(on_its_side(1000, 384, 384));
+        assert!(!on_its_side(384, 1000, 384));
+        assert!(!on_its_side(384, 384, 384));
+        assert!(!on_its_side(500, 300, 384));
+    }
+
+    #[test]
+    fn job_transform_follows_the_file_transform() {
+        let file: Transform = "rotate-90".parse().unwrap();
+        let job: Transform = "invert".parse().unwrap();
+        assert_eq!(file.then(&job).to_string(), "rotate-90,invert");
+    }
+}
@@ -0,0 +1,638 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::ensure;
+use rusb::{Direction, TransferType, UsbContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    status::Transport,
+};
+
+/// USB interface class of printers
+const PRINTER_CLASS: u8 = 7;
+
+/// (vendor, product) IDs of printers that report a vendor specific interface class instead:
+/// the POS58 (a Winbond chip) that `pos58_usb` used to find by ID
+const KNOWN_PRINTERS: &[(u16, u16)] = &[(0x0416, 0x5011)];
+
+/// Which USB printer to open, when there is more than one. Written as `BUS:ADDRESS`,
+/// `VID:PID` (four hex digits each, as `lsusb` shows them) or the serial number.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum UsbSelector {
+    /// Bus number and device address, as `lsusb` shows them. The address changes whenever the
+    /// printer is plugged in again.
+    Address { bus: u8, address: u8 },
+    /// Vendor and product ID: the first printer of that model
+    Id { vendor: u16, product: u16 },
+    /// Serial number the printer reports
+    Serial(String),
+}
+
+impl FromStr for UsbSelector {
+    type Err = anyhow::Error;
+
+    /// Parses `VID:PID` with four hex digits each, `BUS:ADDRESS` in decimal, or anything else as
+    /// a serial number
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some((left, right)) = s.split_once(':') {
+            let hex = |id: &str| {
+                (id.len() == 4)
+                    .then(|| u16::from_str_radix(id, 16).ok())
+                    .flatten()
+            };
+            if let (Some(vendor), Some(product)) = (hex(left), hex(right)) {
+                return Ok(Self::Id { vendor, product });
+            }
+            if let (Ok(bus), Ok(address)) = (left.parse(), right.parse()) {
+                return Ok(Self::Address { bus, address });
+            }
+        }
+        ensure!(!s.is_empty(), "USB serial number is missing");
+        Ok(Self::Serial(s.into()))
+    }
+}
+
+impl TryFrom<String> for UsbSelector {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<UsbSelector> for String {
+    fn from(selector: UsbSelector) -> Self {
+        selector.to_string()
+    }
+}
+
+impl fmt::Display for UsbSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address { bus, address } => write!(f, "{}:{}", bus, address),
+            Self::Id { vendor, product } => write!(f, "{:04x}:{:04x}", vendor, product),
+            Self::Serial(serial) => write!(f, "{}", serial),
+        }
+    }
+}
+
+impl UsbSelector {
+    /// Whether `device` can be ruled out without opening it, i.e. from everything but its
+    /// strings
+    fn excludes(&self, device: &UsbDevice) -> bool {
+        match self {
+            Self::Serial(_) => false,
+            selector => !device.is(selector),
+        }
+    }
+
+    /// Whether only one printer can match, so there is no other to try if it can't be claimed
+    fn is_unique(&self) -> bool {
+        !matches!(self, Self::Id { .. })
+    }
+}
+
+/// A printer found on the USB bus
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub bus: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Strings the device reports; `None` if it has none, or can't be opened to read them
+    /// (usually for lack of permission)
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl UsbDevice {
+    /// Selector that finds this printer again: its serial number if it has one, since the
+    /// address changes when it is plugged in again
+    pub fn selector(&self) -> UsbSelector {
+        match &self.serial {
+            Some(serial) => UsbSelector::Serial(serial.clone()),
+            None => UsbSelector::Address {
+                bus: self.bus,
+                address: self.address,
+            },
+        }
+    }
+
+    /// Whether `selector` picks this printer
+    pub fn is(&self, selector: &UsbSelector) -> bool {
+        match selector {
+            UsbSelector::Address { bus, address } => (self.bus, self.address) == (*bus, *address),
+            UsbSelector::Id { vendor, product } => {
+                (self.vendor_id, self.product_id) == (*vendor, *product)
+            }
+            UsbSelector::Serial(serial) => self.serial.as_ref() == Some(serial),
+        }
+    }
+}
+
+/// Every printer on the USB bus (see `printer_interface`), in bus order; none if there are
+/// none. Reading a device's strings times out after `timeout`.
+pub fn list_printers(timeout: Duration) -> Result<Vec<UsbDevice>> {
+    let offline = |e: rusb::Error| Error::PrinterOffline(format!("USB: {}", e));
+    let ctx = rusb::Context::new()
+        .map_err(|e| Error::PrinterOffline(format!("Initializing libusb: {}", e)))?;
+
+    let mut printers = vec![];
+    for device in ctx.devices().map_err(offline)?.iter() {
+        if printer_interface(&device).is_none() {
+            continue;
+        }
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+
+        let mut printer = UsbDevice {
+            bus: device.bus_number(),
+            address: device.address(),
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            serial: None,
+            manufacturer: None,
+            product: None,
+        };
+        if let Ok(handle) = device.open() {
+            let languages = handle.read_languages(timeout).unwrap_or_default();
+            if let Some(&language) = languages.first() {
+                printer.serial = handle
+                    .read_serial_number_string(language, &desc, timeout)
+                    .ok();
+                printer.manufacturer = handle
+                    .read_manufacturer_string(language, &desc, timeout)
+                    .ok();
+                printer.product = handle.read_product_string(language, &desc, timeout).ok();
+            }
+        }
+        printers.push(printer);
+    }
+    printers.sort_by_key(|printer| (printer.bus, printer.address));
+    Ok(printers)
+}
+
+/// Printer interface of a device, with its bulk endpoints
+struct PrinterInterface {
+    number: u8,
+    out_endpoint: u8,
+    /// Printers without one can't report their status
+    in_endpoint: Option<u8>,
+}
+
+/// The first printer class interface of `device` that can be written to, if any. For printers in
+/// `KNOWN_PRINTERS`, the first interface of any class.
+fn printer_interface(device: &rusb::Device<rusb::Context>) -> Option<PrinterInterface> {
+    let known = device
+        .device_descriptor()
+        .is_ok_and(|desc| KNOWN_PRINTERS.contains(&(desc.vendor_id(), desc.product_id())));
+    let config = device.active_config_descriptor().ok()?;
+    for interface in config.interfaces() {
+        for desc in interface.descriptors() {
+            if desc.class_code() != PRINTER_CLASS && !known {
+                continue;
+            }
+
+            let bulk = |direction| {
+                desc.endpoint_descriptors()
+                    .find(|ep| {
+                        ep.direction() == direction && ep.transfer_type() == TransferType::Bulk
+                    })
+                    .map(|ep| ep.address())
+            };
+            let Some(out_endpoint) = bulk(Direction::Out) else {
+                continue;
+            };
+            return Some(PrinterInterface {
+                number: desc.interface_number(),
+                out_endpoint,
+                in_endpoint: bulk(Direction::In),
+            });
+        }
+    }
+    None
+}
+
+/// Printer on the USB bus, written to and read from over its bulk endpoints
+pub struct UsbPrinter<'ctx> {
+    ctx: &'ctx rusb::Context,
+    /// Finds the same printer again to reconnect: its serial number if it has one, or else what
+    /// it was opened with
+    selector: Option<UsbSelector>,
+    handle: rusb::DeviceHandle<rusb::Context>,
+    interface: u8,
+    out_endpoint: u8,
+    /// Printers without one can't report their status
+    in_endpoint: Option<u8>,
+    /// Timeout for every transfer
+    timeout: Duration,
+}
+
+impl<'ctx> UsbPrinter<'ctx> {
+    /// Open the printer `selector` picks, or the first one that can be opened and claimed. A
+    /// kernel driver bound to it (usblp) is detached. Printers that can't be opened are passed
+    /// over, since they may not be the one `selector` picks.
+    pub fn open(
+        ctx: &'ctx rusb::Context,
+        selector: Option<&UsbSelector>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let offline = |e: rusb::Error| Error::PrinterOffline(format!("USB: {}", e));
+        let devices = ctx.devices().map_err(offline)?;
+        let found = devices.iter().filter_map(|device| {
+            let interface = printer_interface(&device)?;
+            let desc = device.device_descriptor().ok()?;
+            Some(Found {
+                device,
+                desc,
+                interface,
+                timeout,
+            })
+        });
+        let (found, handle) = choose(found, selector)?;
+
+        let selector = match found.serial(&handle) {
+            Some(serial) => Some(UsbSelector::Serial(serial)),
+            None => selector.cloned(),
+        };
+        Ok(Self {
+            ctx,
+            selector,
+            handle,
+            interface: found.interface.number,
+            out_endpoint: found.interface.out_endpoint,
+            in_endpoint: found.interface.in_endpoint,
+            timeout,
+        })
+    }
+}
+
+/// A USB printer that may be the one to open, so choosing one can be tried without any
+trait Candidate {
+    type Handle;
+
+    /// What is known without opening it, i.e. everything but its strings
+    fn device(&self) -> UsbDevice;
+
+    fn open(&self) -> rusb::Result<Self::Handle>;
+
+    /// Serial number it reports, if any
+    fn serial(&self, handle: &Self::Handle) -> Option<String>;
+
+    /// Detach any kernel driver (usblp) from the printer interface and claim it
+    fn claim(&self, handle: &Self::Handle) -> rusb::Result<()>;
+}
+
+/// The first of `candidates` that `selector` picks and that can be opened and claimed. Printers
+/// that can't be opened are passed over, and so are ones that can't be claimed unless
+/// `selector` picks only one.
+fn choose<C: Candidate>(
+    candidates: impl IntoIterator<Item = C>,
+    selector: Option<&UsbSelector>,
+) -> Result<(C, C::Handle)> {
+    // Why the last printer passed over couldn't be opened, e.g. another program has it
+    let mut skipped = None;
+    for candidate in candidates {
+        if selector.is_some_and(|selector| selector.excludes(&candidate.device())) {
+            continue;
+        }
+
+        let handle = match candidate.open() {
+            Ok(handle) => handle,
+            Err(e) => {
+                skipped = Some(e);
+                continue;
+            }
+        };
+        if let Some(UsbSelector::Serial(serial)) = selector {
+            if candidate.serial(&handle).as_ref() != Some(serial) {
+                continue;
+            }
+        }
+        match candidate.claim(&handle) {
+            Ok(()) => return Ok((candidate, handle)),
+            // Another printer will do, so try the next
+            Err(e) if !selector.is_some_and(UsbSelector::is_unique) => skipped = Some(e),
+            Err(e) => return Err(Error::PrinterOffline(format!("USB: {}", e))),
+        }
+    }
+
+    let missing = match selector {
+        Some(UsbSelector::Address { bus, address }) => {
+            format!("No USB printer at bus {} address {}", bus, address)
+        }
+        Some(UsbSelector::Id { vendor, product }) => {
+            format!("No USB printer with ID {:04x}:{:04x}", vendor, product)
+        }
+        Some(UsbSelector::Serial(serial)) => {
+            format!("No USB printer with serial number {}", serial)
+        }
+        None => "No USB printer found".into(),
+    };
+    Err(Error::PrinterOffline(match skipped {
+        Some(e) => format!("{} (one couldn't be opened: {})", missing, e),
+        None => missing,
+    }))
+}
+
+/// A printer on the bus, with its printer interface
+struct Found {
+    device: rusb::Device<rusb::Context>,
+    desc: rusb::DeviceDescriptor,
+    interface: PrinterInterface,
+    /// Timeout for reading its strings
+    timeout: Duration,
+}
+
+impl Candidate for Found {
+    type Handle = rusb::DeviceHandle<rusb::Context>;
+
+    fn device(&self) -> UsbDevice {
+        UsbDevice {
+            bus: self.device.bus_number(),
+            address: self.device.address(),
+            vendor_id: self.desc.vendor_id(),
+            product_id: self.desc.product_id(),
+            serial: None,
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    fn open(&self) -> rusb::Result<Self::Handle> {
+        self.device.open()
+    }
+
+    fn serial(&self, handle: &Self::Handle) -> Option<String> {
+        let language = *handle.read_languages(self.timeout).ok()?.first()?;
+        handle
+            .read_serial_number_string(language, &self.desc, self.timeout)
+            .ok()
+    }
+
+    fn claim(&self, handle: &Self::Handle) -> rusb::Result<()> {
+        let number = self.interface.number;
+        if handle.kernel_driver_active(number).unwrap_or(false) {
+            handle.detach_kernel_driver(number)?;
+        }
+        handle.claim_interface(number)
+    }
+}
+
+impl Drop for UsbPrinter<'_> {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+impl Write for UsbPrinter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handle
+            .write_bulk(self.out_endpoint, buf, self.timeout)
+            .map_err(usb_to_io)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for UsbPrinter<'_> {
+    fn transact(&mut self, request: &[u8]) -> io::Result<Option<u8>> {
+        let Some(in_endpoint) = self.in_endpoint else {
+            return Ok(None);
+        };
+
+        self.write_all(request)?;
+
+        // Full speed bulk packets are up to 64 bytes; the reply is the last byte received
+        let mut buf = [0; 64];
+        let n = self
+            .handle
+            .read_bulk(in_endpoint, &mut buf, self.timeout)
+            .map_err(usb_to_io)?;
+        match n {
+            0 => Err(io::ErrorKind::TimedOut.into()),
+            n => Ok(Some(buf[n - 1])),
+        }
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        // Let go of the old handle first, in case the printer never really went away
+        let _ = self.handle.release_interface(self.interface);
+        let printer = Self::open(self.ctx, self.selector.as_ref(), self.timeout)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotConnected, e.to_string()))?;
+        *self = printer;
+        Ok(())
+    }
+}
+
+fn usb_to_io(e: rusb::Error) -> io::Error {
+    let kind = match e {
+        rusb::Error::Timeout => io::ErrorKind::TimedOut,
+        // Unplugged, or switched off part way through a transfer
+        rusb::Error::NoDevice | rusb::Error::Io | rusb::Error::Pipe => io::ErrorKind::NotConnected,
+        rusb::Error::Interrupted => io::ErrorKind::Interrupted,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn device(bus: u8, address: u8, product_id: u16, serial: Option<&str>) -> UsbDevice {
+        UsbDevice {
+            bus,
+            address,
+            vendor_id: 0x0416,
+            product_id,
+            serial: serial.map(Into::into),
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    #[test]
+    fn selectors_parse() {
+        for (s, selector) in [
+            ("1:4", UsbSelector::Address { bus: 1, address: 4 }),
+            (
+                "001:012",
+                UsbSelector::Address {
+                    bus: 1,
+                    address: 12,
+                },
+            ),
+            (
+                "0416:5011",
+                UsbSelector::Id {
+                    vendor: 0x0416,
+                    product: 0x5011,
+                },
+            ),
+            (
+                "04B8:0E15",
+                UsbSelector::Id {
+                    vendor: 0x04b8,
+                    product: 0x0e15,
+                },
+            ),
+            ("A1B2C3", UsbSelector::Serial("A1B2C3".into())),
+            ("1:x", UsbSelector::Serial("1:x".into())),
+            ("1:400", UsbSelector::Serial("1:400".into())),
+        ] {
+            assert_eq!(s.parse::<UsbSelector>().unwrap(), selector, "{}", s);
+            let again = selector.to_string().parse::<UsbSelector>().unwrap();
+            assert_eq!(again, selector, "{}", s);
+        }
+        assert!("".parse::<UsbSelector>().is_err());
+    }
+
+    #[test]
+    fn selectors_match() {
+        let printer = device(1, 4, 0x5011, Some("A1B2"));
+        for (selector, picks) in [
+            ("1:4", true),
+            ("1:5", false),
+            ("2:4", false),
+            ("0416:5011", true),
+            ("0416:5012", false),
+            ("A1B2", true),
+            ("A1B3", false),
+        ] {
+            let selector = selector.parse().unwrap();
+            assert_eq!(printer.is(&selector), picks, "{}", selector);
+        }
+        assert!(!device(1, 4, 0x5011, None).is(&"A1B2".parse().unwrap()));
+    }
+
+    #[test]
+    fn selects_devices_by_their_serial_number_or_else_address() {
+        let serial = device(1, 4, 0x5011, Some("A1B2"));
+        assert_eq!(serial.selector(), UsbSelector::Serial("A1B2".into()));
+        let bare = device(1, 4, 0x5011, None);
+        assert_eq!(bare.selector(), UsbSelector::Address { bus: 1, address: 4 });
+    }
+
+    /// Printer on a pretend bus, which may refuse to be opened or claimed
+    struct Fake<'a> {
+        device: UsbDevice,
+        opens: bool,
+        claims: bool,
+        /// Times any fake was opened
+        opened: &'a Cell<usize>,
+    }
+
+    impl Candidate for Fake<'_> {
+        type Handle = ();
+
+        fn device(&self) -> UsbDevice {
+            UsbDevice {
+                serial: None,
+                ..self.device.clone()
+            }
+        }
+
+        fn open(&self) -> rusb::Result<()> {
+            self.opened.set(self.opened.get() + 1);
+            if self.opens {
+                Ok(())
+            } else {
+                Err(rusb::Error::Access)
+            }
+        }
+
+        fn serial(&self, _: &()) -> Option<String> {
+            self.device.serial.clone()
+        }
+
+        fn claim(&self, _: &()) -> rusb::Result<()> {
+            if self.claims {
+                Ok(())
+            } else {
+                Err(rusb::Error::Busy)
+            }
+        }
+    }
+
+    /// Addresses of the printers on the bus: one that can't be opened, one another program has
+    /// claimed, then two that are free
+    fn bus(opened: &Cell<usize>) -> Vec<Fake<'_>> {
+        [
+            (1, Some("A"), false, true),
+            (2, Some("B"), true, false),
+            (3, Some("C"), true, true),
+            (4, None, true, true),
+        ]
+        .into_iter()
+        .map(|(address, serial, opens, claims)| Fake {
+            device: device(1, address, 0x5011, serial),
+            opens,
+            claims,
+            opened,
+        })
+        .collect()
+    }
+
+    fn chosen(selector: Option<&str>) -> Result<u8> {
+        let opened = Cell::new(0);
+        let selector = selector.map(|s| s.parse().unwrap());
+        choose(bus(&opened), selector.as_ref()).map(|(fake, ())| fake.device.address)
+    }
+
+    #[test]
+    fn passes_over_printers_that_cant_be_opened_or_claimed() {
+        assert_eq!(chosen(None).unwrap(), 3);
+        assert_eq!(chosen(Some("0416:5011")).unwrap(), 3);
+    }
+
+    #[test]
+    fn finds_the_selected_printer() {
+        assert_eq!(chosen(Some("1:4")).unwrap(), 4);
+        assert_eq!(chosen(Some("C")).unwrap(), 3);
+
+        // Only printers that might have the serial number are opened
+        let opened = Cell::new(0);
+        let selector = "1:3".parse().unwrap();
+        choose(bus(&opened), Some(&selector)).unwrap();
+        assert_eq!(opened.get(), 1);
+    }
+
+    #[test]
+    fn reports_why_the_selected_printer_cant_be_used() {
+        let offline = |selector| match chosen(Some(selector)) {
+            Err(Error::PrinterOffline(e)) => e,
+            result => panic!("{}: {:?}", selector, result.map_err(|e| e.to_string())),
+        };
+        // A is the one that can't be opened, so B might have been it
+        assert!(offline("A")
+            .starts_with("No USB printer with serial number A (one couldn't be opened: "));
+        // The selected printer is busy, and no other will do
+        assert!(offline("B").contains("Resource busy"), "{}", offline("B"));
+        assert_eq!(offline("1:9"), "No USB printer at bus 1 address 9");
+        assert_eq!(offline("0416:5012"), "No USB printer with ID 0416:5012");
+
+        let opened = Cell::new(0);
+        let nothing = bus(&opened).into_iter().map(|fake| Fake {
+            opens: false,
+            ..fake
+        });
+        let e = choose(nothing, None).map(|_| ()).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("No USB printer found (one couldn't be opened: "));
+    }
+}
@@ -0,0 +1,178 @@
+use qrcode::{types::QrError, Color, EcLevel, QrCode};
+
+/// PNG text chunk holding the hash of the strip_gui scene a strip was cut from
+pub const SCENE_KEYWORD: &str = "Scene";
+
+/// QR module size, in dots
+pub const QR_MODULE_DOTS: usize = 4;
+/// White modules around a QR code
+pub const QR_QUIET_ZONE: usize = 4;
+/// White modules on either side of a barcode
+pub const BARCODE_QUIET_ZONE: usize = 10;
+/// Height of a barcode, in dots
+pub const BARCODE_HEIGHT_DOTS: usize = 64;
+/// Blank paper between codes and the strip, in dots
+pub const CODE_GAP: usize = 16;
+
+/// Code 128 symbols 0 to 105, one bit per module from the most significant of 11 bits; set bits
+/// are bars
+#[rustfmt::skip]
+const CODE128_PATTERNS: [u16; 106] = [
+    0b11011001100, 0b11001101100, 0b11001100110, 0b10010011000, 0b10010001100, 0b10001001100,
+    0b10011001000, 0b10011000100, 0b10001100100, 0b11001001000, 0b11001000100, 0b11000100100,
+    0b10110011100, 0b10011011100, 0b10011001110, 0b10111001100, 0b10011101100, 0b10011100110,
+    0b11001110010, 0b11001011100, 0b11001001110, 0b11011100100, 0b11001110100, 0b11101101110,
+    0b11101001100, 0b11100101100, 0b11100100110, 0b11101100100, 0b11100110100, 0b11100110010,
+    0b11011011000, 0b11011000110, 0b11000110110, 0b10100011000, 0b10001011000, 0b10001000110,
+    0b10110001000, 0b10001101000, 0b10001100010, 0b11010001000, 0b11000101000, 0b11000100010,
+    0b10110111000, 0b10110001110, 0b10001101110, 0b10111011000, 0b10111000110, 0b10001110110,
+    0b11101110110, 0b11010001110, 0b11000101110, 0b11011101000, 0b11011100010, 0b11011101110,
+    0b11101011000, 0b11101000110, 0b11100010110, 0b11101101000, 0b11101100010, 0b11100011010,
+    0b11101111010, 0b11001000010, 0b11110001010, 0b10100110000, 0b10100001100, 0b10010110000,
+    0b10010000110, 0b10000101100, 0b10000100110, 0b10110010000, 0b10110000100, 0b10011010000,
+    0b10011000010, 0b10000110100, 0b10000110010, 0b11000010010, 0b11001010000, 0b11110111010,
+    0b11000010100, 0b10001111010, 0b10100111100, 0b10010111100, 0b10010011110, 0b10111100100,
+    0b10011110100, 0b10011110010, 0b11110100100, 0b11110010100, 0b11110010010, 0b11011011110,
+    0b11011110110, 0b11110110110, 0b10101111000, 0b10100011110, 0b10001011110, 0b10111101000,
+    0b10111100010, 0b11110101000, 0b11110100010, 0b10111011110, 0b10111101110, 0b11101011110,
+    0b11110101110, 0b11010000100, 0b11010010000, 0b11010011100,
+];
+/// Code 128 start symbol for code set B
+const CODE128_START_B: usize = 104;
+/// Code 128 stop pattern, 13 modules
+const CODE128_STOP: u16 = 0b1100011101011;
+
+/// Text encoded in a strip's codes: `SCENE/INDEX` with the scene hash in hex, or just the index
+/// if the strip's scene isn't known
+pub fn payload(scene: Option<u32>, index: usize) -> String {
+    match scene {
+        Some(scene) => format!("{:08x}/{}", scene, index),
+        None => index.to_string(),
+    }
+}
+
+/// Modules of the smallest QR code holding `data`: its side, and its modules row-major (true =
+/// dark) without the quiet zone
+pub fn qr_modules(data: &str, ec: EcLevel) -> Result<(usize, Vec<bool>), QrError> {
+    let code = QrCode::with_error_correction_level(data, ec)?;
+    let modules = code.to_colors().into_iter().map(|c| c == Color::Dark);
+    Ok((code.width(), modules.collect()))
+}
+
+/// Dots per module for a QR code `side` modules across, with its quiet zone, on paper `width`
+/// dots wide
+pub fn qr_module_dots(side: usize, width: usize) -> usize {
+    (width / (side + 2 * QR_QUIET_ZONE)).clamp(1, QR_MODULE_DOTS)
+}
+
+/// Modules of a Code 128 barcode of `data` in code set B (true = bar), without the quiet zone.
+/// `None` if `data` has characters outside printable ASCII.
+pub fn code128(data: &str) -> Option<Vec<bool>> {
+    let mut symbols = vec![CODE128_START_B];
+    for c in data.chars() {
+        match c {
+            ' '..='~' => symbols.push(c as usize - ' ' as usize),
+            _ => return None,
+        }
+    }
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| i.max(1) * s)
+        .sum::<usize>()
+        % 103;
+    symbols.push(checksum);
+
+    let bits = |pattern: u16, n: u32| (0..n).rev().map(move |i| pattern >> i & 1 != 0);
+    let mut modules: Vec<bool> = symbols
+        .iter()
+        .flat_map(|&s| bits(CODE128_PATTERNS[s], 11))
+        .collect();
+    modules.extend(bits(CODE128_STOP, 13));
+    Some(modules)
+}
+
+/// Dots per module for a barcode `modules` wide, with its quiet zone, on paper `width` dots
+/// wide; `None` if it doesn't fit
+pub fn barcode_module_dots(modules: usize, width: usize) -> Option<usize> {
+    match width / (modules + 2 * BARCODE_QUIET_ZONE) {
+        0 => None,
+        dots => Some(dots),
+    }
+}
+
+/// Scale `modules` (`side` per row, true = dark) to `w` by `h` dots each. Returns the width
+/// in dots and the bitmap.
+pub fn scale_modules(modules: &[bool], side: usize, w: usize, h: usize) -> (usize, Vec<bool>) {
+    let mut bitmap = vec![];
+    for row in modules.chunks(side) {
+        let line: Vec<bool> = row
+            .iter()
+            .flat_map(|&dark| std::iter::repeat_n(dark, w))
+            .collect();
+        for _ in 0..h {
+            bitmap.extend_from_slice(&line);
+        }
+    }
+    (side * w, bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        assert_eq!(payload(Some(0xbeef), 12), "0000beef/12");
+        assert_eq!(payload(None, 12), "12");
+    }
+
+    #[test]
+    fn code128_layout() {
+        let modules = code128("0000beef/12").unwrap();
+        // Start, 11 characters and the checksum, then the stop pattern
+        assert_eq!(modules.len(), 13 * 11 + 13);
+        assert_eq!(modules[..11], code128("").unwrap()[..11]);
+        assert!(modules[modules.len() - 13..]
+            .iter()
+            .zip([1, 1, 0, 0, 0, 1, 1, 1, 0, 1, 0, 1, 1])
+            .all(|(&bar, bit)| bar == (bit == 1)));
+
+        assert_eq!(code128("é"), None);
+        assert_eq!(code128("a\n"), None);
+    }
+
+    #[test]
+    fn code128_checksum() {
+        // "Wikipedia": 104 + 55 + 2 * 73 + 3 * 75 + 4 * 73 + 5 * 80 + 6 * 69 + 7 * 68 + 8 * 73
+        // + 9 * 65 = 3281, and 3281 mod 103 = 88, the symbol for 'x'
+        let modules = code128("Wikipedia").unwrap();
+        let check = &modules[10 * 11..11 * 11];
+        let expected = code128("x").unwrap()[11..22].to_vec();
+        assert_eq!(check, expected);
+    }
+
+    #[test]
+    fn module_sizes() {
+        assert_eq!(qr_module_dots(21, 384), QR_MODULE_DOTS);
+        assert_eq!(qr_module_dots(21, 40), 1);
+        assert_eq!(qr_module_dots(21, 10), 1);
+
+        assert_eq!(barcode_module_dots(100, 384), Some(3));
+        assert_eq!(barcode_module_dots(400, 384), None);
+    }
+
+    #[test]
+    fn scaling() {
+        let (w, bitmap) = scale_modules(&[true, false, false, true], 2, 3, 2);
+        assert_eq!(w, 6);
+        let row = |a: bool, b: bool| [[a; 3], [b; 3]].concat();
+        let expected = [
+            row(true, false),
+            row(true, false),
+            row(false, true),
+            row(false, true),
+        ];
+        assert_eq!(bitmap, expected.concat());
+    }
+}
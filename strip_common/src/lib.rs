@@ -0,0 +1,5 @@
+//! Formats shared by `print` and strip_gui, so what one writes the other can read
+
+pub mod code;
+pub mod manifest;
+pub mod profile;
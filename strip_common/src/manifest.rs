@@ -0,0 +1,45 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use serde::Serialize;
+
+/// Manifest written next to the saved strips, so `print` can print them all from one file
+pub const MANIFEST_NAME: &str = "job.ron";
+
+/// Job manifest in the layout `print` reads, as strip_gui writes it. Only what the GUI knows is
+/// written; `print` takes the rest from its command line, and strips can be given settings of
+/// their own (copies, transform, label, density, cut) by editing the file.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// Job name for labels
+    pub name: Option<String>,
+    pub strips: Vec<Entry>,
+}
+
+/// One strip of a manifest
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// PNG, relative to the manifest
+    pub path: String,
+    pub index: Option<usize>,
+}
+
+impl Manifest {
+    /// Manifest for strips saved as `INDEX.png`, numbered from 0
+    pub fn new(name: Option<String>, count: usize) -> Self {
+        Self {
+            name,
+            strips: (0..count)
+                .map(|idx| Entry {
+                    path: format!("{}.png", idx),
+                    index: Some(idx),
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the manifest to `MANIFEST_NAME` in `dir`
+    pub fn save(&self, dir: impl AsRef<Path>) -> ron::Result<()> {
+        let f = BufWriter::new(File::create(dir.as_ref().join(MANIFEST_NAME))?);
+        ron::ser::to_writer_pretty(f, self, Default::default())
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+/// Printer geometry. Same layout as the `print` crate's profiles, so its `printers.ron` can be
+/// loaded here; fields the GUI doesn't need are ignored.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PrinterProfile {
+    /// Name used to select the profile
+    pub name: String,
+    /// Width of the paper roll, in millimeters
+    pub paper_width: f32,
+    /// Width of the printable area, in millimeters
+    pub print_width: f32,
+    /// Dots across the printable area
+    pub dots_per_row: usize,
+}
+
+impl PrinterProfile {
+    /// Paper width in centimeters
+    pub fn paper_width_cm(&self) -> f32 {
+        self.paper_width / 10.
+    }
+
+    /// Printable width in centimeters
+    pub fn print_width_cm(&self) -> f32 {
+        self.print_width / 10.
+    }
+
+    /// Printed dots per centimeter
+    pub fn dots_per_cm(&self) -> f32 {
+        self.dots_per_row as f32 / self.print_width_cm()
+    }
+}
+
+/// Profiles for the printers we own, used until a `printers.ron` is loaded. `print` has the
+/// same printers built in, with the rest of their settings.
+pub fn builtin_profiles() -> Vec<PrinterProfile> {
+    vec![
+        PrinterProfile {
+            name: "pos58".into(),
+            paper_width: 58.,
+            print_width: 48.,
+            dots_per_row: 384,
+        },
+        PrinterProfile {
+            name: "pos80".into(),
+            paper_width: 80.,
+            print_width: 72.,
+            dots_per_row: 576,
+        },
+        PrinterProfile {
+            name: "pos58-180dpi".into(),
+            paper_width: 58.,
+            print_width: 47.4,
+            dots_per_row: 336,
+        },
+    ]
+}
+
+impl Default for PrinterProfile {
+    fn default() -> Self {
+        builtin_profiles().remove(0)
+    }
+}
@@ -14,12 +14,16 @@ use egui::{
 };
 use png::{BitDepth, ColorType};
 
-use crate::{Dimensions, Scene, Strip};
+use strip_common::{
+    code::{payload, SCENE_KEYWORD},
+    manifest::{Manifest, MANIFEST_NAME},
+};
 
-const STRIP_DRAW_WIDTH: f32 = 4.8; // cm
-const STRIP_PAPER_WIDTH: f32 = 5.8; // cm
-const STRIP_PIXELS_PER_ROW: usize = 384;
-const STRIP_DOTS_PER_CM: f32 = STRIP_PIXELS_PER_ROW as f32 / STRIP_DRAW_WIDTH;
+use crate::{
+    builtin_profiles,
+    code::{self, scene_hash, Codes},
+    Dimensions, PrinterProfile, Scene, Strip,
+};
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -29,6 +33,9 @@ pub struct StripApp {
     color_counter: usize,
     image_path: Option<PathBuf>,
     out_path: Option<PathBuf>,
+    profiles: Vec<PrinterProfile>,
+    /// Codes drawn above each saved strip
+    codes: Codes,
 
     #[serde(skip)]
     texture: Option<TextureHandle>,
@@ -46,6 +53,8 @@ impl Default for StripApp {
             image_data: None,
             color_counter: 0,
             scene: Scene::default(),
+            profiles: builtin_profiles(),
+            codes: Codes::default(),
         }
     }
 }
@@ -161,6 +170,40 @@ impl eframe::App for StripApp {
                     ui.label(format!("Height: {} cm", self.scene.dims.height()));
                 });
 
+                // Printer selection
+                ui.horizontal(|ui| {
+                    let mut selected = None;
+                    egui::ComboBox::from_label("Printer")
+                        .selected_text(self.scene.printer.name.as_str())
+                        .show_ui(ui, |ui| {
+                            for profile in &self.profiles {
+                                let is_current = *profile == self.scene.printer;
+                                if ui
+                                    .selectable_label(is_current, profile.name.as_str())
+                                    .clicked()
+                                {
+                                    selected = Some(profile.clone());
+                                }
+                            }
+                        });
+
+                    if let Some(profile) = selected {
+                        set_printer(&mut self.scene, profile);
+                    }
+
+                    if ui.button("Load printers").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("RON", &["ron"])
+                            .pick_file()
+                        {
+                            match load_profiles(&path) {
+                                Ok(profiles) => self.profiles = profiles,
+                                Err(e) => eprintln!("Failed to load {}; {}", path.display(), e),
+                            }
+                        }
+                    }
+                });
+
                 ui.horizontal(|ui| {
                     // Save config
                     if ui.button("Save config").clicked() {
@@ -185,21 +228,51 @@ impl eframe::App for StripApp {
                     }
                 });
 
-                if ui.button("Save images").clicked() {
-                    if let Some(output_path) = self.out_path.clone().or_else(prompt_output_path) {
-                        if let Some(input_img) = self.image_data.as_ref() {
-                            sample_strips(
-                                &output_path,
-                                input_img,
-                                &self.scene.strips,
-                                &self.scene.dims,
-                            )
+                ui.horizontal(|ui| {
+                    if ui.button("Save images").clicked() {
+                        if let Some(output_path) = self.out_path.clone().or_else(prompt_output_path)
+                        {
+                            if let Some(input_img) = self.image_data.as_ref() {
+                                sample_strips(
+                                    &output_path,
+                                    input_img,
+                                    &self.scene.strips,
+                                    &self.scene.dims,
+                                    &self.scene.printer,
+                                    self.codes,
+                                    scene_hash(&self.scene),
+                                );
+
+                                let name = self
+                                    .image_path
+                                    .as_ref()
+                                    .and_then(|path| path.file_stem())
+                                    .map(|stem| stem.to_string_lossy().into_owned());
+                                let manifest = Manifest::new(name, self.scene.strips.len());
+                                if let Err(e) = manifest.save(&output_path) {
+                                    eprintln!(
+                                        "Failed to write {} to {}; {}",
+                                        MANIFEST_NAME,
+                                        output_path.display(),
+                                        e
+                                    );
+                                }
+                            }
                         }
                     }
-                }
+
+                    // Codes identifying each strip, drawn above it
+                    ui.checkbox(&mut self.codes.qr, "QR code");
+                    ui.checkbox(&mut self.codes.barcode, "Barcode");
+                });
 
                 // Stip controls
-                strip_controls(ui, &mut self.scene.strips, &mut self.color_counter);
+                strip_controls(
+                    ui,
+                    &mut self.scene.strips,
+                    &self.scene.printer,
+                    &mut self.color_counter,
+                );
             });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -212,6 +285,23 @@ fn prompt_output_path() -> Option<PathBuf> {
     rfd::FileDialog::new().pick_folder()
 }
 
+fn load_profiles(path: &Path) -> Result<Vec<PrinterProfile>, String> {
+    let f = File::open(path).map_err(|e| e.to_string())?;
+    let profiles: Vec<PrinterProfile> = ron::de::from_reader(f).map_err(|e| e.to_string())?;
+    if profiles.is_empty() {
+        return Err("no profiles in file".into());
+    }
+    Ok(profiles)
+}
+
+/// Switch printers, resizing the strips to the new printable width
+fn set_printer(scene: &mut Scene, profile: PrinterProfile) {
+    for strip in &mut scene.strips {
+        strip.size[0] = profile.print_width_cm();
+    }
+    scene.printer = profile;
+}
+
 fn strip_plot(ui: &mut Ui, scene: &Scene, tex_id: Option<TextureId>) {
     Plot::new("Plot").data_aspect(1.).show(ui, |ui| {
         // Reference image
@@ -226,12 +316,12 @@ fn strip_plot(ui: &mut Ui, scene: &Scene, tex_id: Option<TextureId>) {
 
         // Strips
         for strip in &scene.strips {
-            draw_strip(ui, strip, &scene.dims);
+            draw_strip(ui, strip, &scene.dims, &scene.printer);
         }
     });
 }
 
-fn draw_strip(ui: &mut PlotUi, strip: &Strip, dims: &Dimensions) {
+fn draw_strip(ui: &mut PlotUi, strip: &Strip, dims: &Dimensions, printer: &PrinterProfile) {
     let mut draw_size = |width: f32| {
         draw_rectangle(
             ui,
@@ -243,7 +333,7 @@ fn draw_strip(ui: &mut PlotUi, strip: &Strip, dims: &Dimensions) {
     };
 
     draw_size(strip.size[0]);
-    draw_size(STRIP_PAPER_WIDTH);
+    draw_size(printer.paper_width_cm());
 }
 
 fn draw_rectangle(ui: &mut PlotUi, pos: Pos2, size: Vec2, color: Color32, angle: f32) {
@@ -270,14 +360,19 @@ fn draw_rectangle(ui: &mut PlotUi, pos: Pos2, size: Vec2, color: Color32, angle:
     }
 }
 
-fn strip_controls(ui: &mut Ui, strips: &mut Vec<Strip>, color_counter: &mut usize) {
+fn strip_controls(
+    ui: &mut Ui,
+    strips: &mut Vec<Strip>,
+    printer: &PrinterProfile,
+    color_counter: &mut usize,
+) {
     ui.horizontal(|ui| {
         if ui.button("+").clicked() {
             let color = COLOR_TABLE[*color_counter % COLOR_TABLE.len()];
             *color_counter += 1;
             strips.push(Strip {
                 position: [0.5; 2],
-                size: [STRIP_DRAW_WIDTH, 50.],
+                size: [printer.print_width_cm(), 50.],
                 rotation: 0.,
                 color,
             })
@@ -375,21 +470,46 @@ const COLOR_TABLE: [Color32; 17 - 2] = [
     Color32::GOLD,
 ];
 
-fn sample_strips(out_path: &PathBuf, input_img: &ColorImage, strips: &[Strip], dims: &Dimensions) {
+/// Save each strip as `INDEX.png`, below the selected codes. Every PNG records the scene hash,
+/// which `print` puts in codes of its own. The manifest listing them is written separately.
+fn sample_strips(
+    out_path: &PathBuf,
+    input_img: &ColorImage,
+    strips: &[Strip],
+    dims: &Dimensions,
+    printer: &PrinterProfile,
+    codes: Codes,
+    scene: u32,
+) {
     for (idx, strip) in strips.iter().enumerate() {
-        let strip_img = sample_strip(input_img, strip, STRIP_DOTS_PER_CM, dims);
+        let strip_img = sample_strip(input_img, strip, printer, dims);
+        let code_img = code::render(codes, &payload(Some(scene), idx), printer.dots_per_row);
         let fname = out_path.join(format!("{}.png", idx));
-        save_image(fname, &strip_img);
+        save_image(fname, &stack(&code_img, &strip_img), scene);
     }
 }
 
-fn save_image(path: impl AsRef<Path>, image: &ColorImage) {
+/// Join two images of the same width, `top` above `bottom`
+fn stack(top: &ColorImage, bottom: &ColorImage) -> ColorImage {
+    let mut image = ColorImage::new(
+        [bottom.width(), top.height() + bottom.height()],
+        Color32::WHITE,
+    );
+    image.pixels[..top.pixels.len()].copy_from_slice(&top.pixels);
+    image.pixels[top.pixels.len()..].copy_from_slice(&bottom.pixels);
+    image
+}
+
+fn save_image(path: impl AsRef<Path>, image: &ColorImage, scene: u32) {
     let file = File::create(path).unwrap();
     let ref mut w = BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, image.width() as _, image.height() as _); // Width is 2 pixels and height is 1.
     encoder.set_color(png::ColorType::Rgba);
     encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .add_text_chunk(SCENE_KEYWORD.into(), format!("{:08x}", scene))
+        .unwrap();
     let mut writer = encoder.write_header().unwrap();
 
     let bytes: Vec<u8> = image
@@ -405,11 +525,14 @@ fn save_image(path: impl AsRef<Path>, image: &ColorImage) {
 fn sample_strip(
     input_img: &ColorImage,
     strip: &Strip,
-    dots_per_cm: f32,
+    printer: &PrinterProfile,
     dims: &Dimensions,
 ) -> ColorImage {
+    let dots_per_cm = printer.dots_per_cm();
+
+    // Always exactly one printer row wide, whatever the rounding
     let mut strip_img = ColorImage::new(
-        strip.size.map(|v| (v * dots_per_cm) as usize),
+        [printer.dots_per_row, (strip.size[1] * dots_per_cm) as usize],
         Color32::WHITE,
     );
 
@@ -429,7 +552,7 @@ fn sample_strip(
 fn strip_pixel_cm(x: usize, y: usize, strip: &Strip, dots_per_cm: f32, dims: &Dimensions) -> Vec2 {
     let px = Vec2::new(x as f32, y as f32);
     let wh = Vec2::from(strip.size);
-    
+
     let strip_res = dots_per_cm * wh;
 
     let xy = px / strip_res; // Normalize to 0 to 1
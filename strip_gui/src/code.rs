@@ -0,0 +1,70 @@
+use egui::{Color32, ColorImage};
+use qrcode::EcLevel;
+use strip_common::code::{
+    barcode_module_dots, code128, qr_module_dots, qr_modules, scale_modules, BARCODE_HEIGHT_DOTS,
+    CODE_GAP,
+};
+
+use crate::Scene;
+
+/// Which codes to draw above each strip
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Codes {
+    pub qr: bool,
+    pub barcode: bool,
+}
+
+/// 32 bit FNV-1a hash of the scene, identifying which scene a strip was cut from
+pub fn scene_hash(scene: &Scene) -> u32 {
+    let text = ron::to_string(scene).unwrap_or_default();
+    text.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Draw `codes` for `payload` centered on an image `width` dots wide, followed by the gap
+/// `print` leaves before a strip. They are drawn as `print --code` draws them, so strips can be
+/// identified the same way whichever added the codes. Empty if no codes are selected.
+pub fn render(codes: Codes, payload: &str, width: usize) -> ColorImage {
+    let mut parts = vec![];
+
+    if codes.qr {
+        if let Ok((side, modules)) = qr_modules(payload, EcLevel::M) {
+            let dots = qr_module_dots(side, width);
+            parts.push(scale_modules(&modules, side, dots, dots));
+        }
+    }
+
+    if codes.barcode {
+        // Payloads are ASCII, so every barcode that fits is drawn
+        if let Some(modules) = code128(payload) {
+            if let Some(dots) = barcode_module_dots(modules.len(), width) {
+                parts.push(scale_modules(
+                    &modules,
+                    modules.len(),
+                    dots,
+                    BARCODE_HEIGHT_DOTS,
+                ));
+            }
+        }
+    }
+
+    let height: usize = parts.iter().map(|(w, bitmap)| bitmap.len() / w).sum();
+    let gaps = parts.len() * CODE_GAP;
+    let mut image = ColorImage::new([width, height + gaps], Color32::WHITE);
+
+    let mut top = 0;
+    for (w, bitmap) in parts {
+        let left = width.saturating_sub(w) / 2;
+        for (y, row) in bitmap.chunks(w).enumerate() {
+            for (x, &black) in row.iter().enumerate() {
+                if black && left + x < width {
+                    image[(left + x, top + y)] = Color32::BLACK;
+                }
+            }
+        }
+        top += bitmap.len() / w + CODE_GAP;
+    }
+
+    image
+}
@@ -1,9 +1,11 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod code;
 pub use app::StripApp;
 use egui::{Color32, Vec2};
 use serde::{Deserialize, Serialize};
+pub use strip_common::profile::{builtin_profiles, PrinterProfile};
 
 /// Dimensions of the peice
 #[derive(Deserialize, Serialize, Clone, Copy, Debug)]
@@ -33,6 +35,9 @@ pub struct Strip {
 pub struct Scene {
     pub dims: Dimensions,
     pub strips: Vec<Strip>,
+    /// Printer the strips are laid out for
+    #[serde(default)]
+    pub printer: PrinterProfile,
 }
 
 impl Dimensions {
@@ -74,6 +79,7 @@ impl Default for Scene {
                 width: 100.,
             },
             strips: vec![],
+            printer: PrinterProfile::default(),
         }
     }
 }